@@ -1,4 +1,6 @@
+use super::MAX_DEPTH;
 use crate::{
+  diagnostic::{Diagnostic, IntoDiagnostic},
   lex::{LexError, Token},
   types::Span,
 };
@@ -9,7 +11,9 @@ pub type ParseResult<'buf, T> = Result<T, ParseError<'buf>>;
 /// An error which can be returned when tokenizing a str.
 #[derive(Clone)]
 pub enum ParseError<'buf> {
-  Lex(LexError<'buf>),
+  /// A lexer error detached from the source so it can be the [`Error::source`], along with where
+  /// it occurred.
+  Lex(Box<LexError<'static>>, Span<'buf>),
   Unexpected(String, Span<'buf>),
   UnexpectedToken(String, Token<'buf>),
   Missing(String, Span<'buf>),
@@ -17,6 +21,17 @@ pub enum ParseError<'buf> {
 }
 
 impl<'buf> ParseError<'buf> {
+  /// Get where the error occurred.
+  pub fn span(&self) -> &Span<'buf> {
+    match self {
+      ParseError::UnexpectedToken(_, token) => &token.0,
+      ParseError::Lex(_, span)
+      | ParseError::Unexpected(_, span)
+      | ParseError::Missing(_, span)
+      | ParseError::EmptyExpression(_, span) => span,
+    }
+  }
+
   pub fn expected_left_paren(span: &Span<'buf>) -> Self {
     Self::Missing("Missing open delimiter".to_string(), span.clone())
   }
@@ -52,14 +67,46 @@ impl<'buf> ParseError<'buf> {
     Self::Missing("Expected if body".to_string(), span.clone())
   }
 
+  pub fn expected_cond_arm(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected cond arm".to_string(), span.clone())
+  }
+
+  pub fn expected_cond_condition(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected cond condition".to_string(), span.clone())
+  }
+
+  pub fn expected_cond_body(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected cond body".to_string(), span.clone())
+  }
+
+  pub fn expected_import_path(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected import path string".to_string(), span.clone())
+  }
+
   pub fn expected_var_expr(span: &Span<'buf>) -> Self {
     Self::Missing("Expected variable expression".to_string(), span.clone())
   }
 
+  pub fn expected_const_expr(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected constant expression".to_string(), span.clone())
+  }
+
+  pub fn expected_type(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected type name".to_string(), span.clone())
+  }
+
   pub fn expected_func_body(span: &Span<'buf>) -> Self {
     Self::Missing("Expected function body".to_string(), span.clone())
   }
 
+  pub fn expected_return_expr(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected return value".to_string(), span.clone())
+  }
+
+  pub fn expected_quote_expr(span: &Span<'buf>) -> Self {
+    Self::Missing("Expected quoted expression".to_string(), span.clone())
+  }
+
   pub fn expected_op_lhs(span: &Span<'buf>) -> Self {
     Self::Missing("Expected operator lhs".to_string(), span.clone())
   }
@@ -71,18 +118,41 @@ impl<'buf> ParseError<'buf> {
   pub fn expected_op_operand(span: &Span<'buf>) -> Self {
     Self::Missing("Expected operator lhs".to_string(), span.clone())
   }
+
+  pub fn nested_too_deep(span: &Span<'buf>) -> Self {
+    Self::Unexpected(
+      format!("Expression nested more than {} levels deep", MAX_DEPTH),
+      span.clone(),
+    )
+  }
+}
+
+impl<'buf> IntoDiagnostic<'buf> for ParseError<'buf> {
+  fn into_diagnostic(self) -> Diagnostic<'buf> {
+    match self {
+      ParseError::Lex(err, span) => (*err).with_span(span).into_diagnostic(),
+      ParseError::UnexpectedToken(message, Token(span, kind)) => {
+        Diagnostic::error(format!("{} `{:?}`", message, kind), span)
+      }
+      ParseError::Unexpected(message, span)
+      | ParseError::Missing(message, span)
+      | ParseError::EmptyExpression(message, span) => Diagnostic::error(message, span),
+    }
+  }
 }
 
 impl<'buf> From<LexError<'buf>> for ParseError<'buf> {
   fn from(inner: LexError<'buf>) -> Self {
-    ParseError::Lex(inner)
+    let span = inner.span().clone();
+
+    ParseError::Lex(Box::new(inner.with_span(span.detach())), span)
   }
 }
 
 impl std::fmt::Debug for ParseError<'_> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
-      ParseError::Lex(err) => write!(f, "{:?}", err),
+      ParseError::Lex(err, _) => write!(f, "{:?}", err),
       ParseError::Unexpected(message, span) => write!(f, "{} at {:?}", message, span),
       ParseError::UnexpectedToken(message, token) => {
         write!(f, "{} `{:?}` at {:?}", message, token.1, token.0)
@@ -99,4 +169,11 @@ impl std::fmt::Display for ParseError<'_> {
   }
 }
 
-impl Error for ParseError<'_> {}
+impl Error for ParseError<'_> {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      ParseError::Lex(inner, _) => Some(inner.as_ref()),
+      _ => None,
+    }
+  }
+}
@@ -1,4 +1,5 @@
-use std::borrow::Cow;
+use crate::types::Span;
+use std::{borrow::Cow, cmp::Ordering};
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub enum Expr<'buf> {
@@ -7,13 +8,21 @@ pub enum Expr<'buf> {
   // Literal
   String(StringLit<'buf>),
   Number(NumberLit),
+  Bool(BoolLit),
 
   // Stmt
   If(Box<If<'buf>>),
+  Cond(Box<Cond<'buf>>),
   Call(Box<Call<'buf>>),
   Assign(Box<Assign<'buf>>),
+  CompoundAssign(Box<CompoundAssign<'buf>>),
+  Const(Box<Const<'buf>>),
   Define(Box<Define<'buf>>),
   Function(Box<Function<'buf>>),
+  Import(Import<'buf>),
+  Lambda(Box<Lambda<'buf>>),
+  Return(Box<Return<'buf>>),
+  Quote(Box<Quote<'buf>>),
   UnaryOp(Box<UnaryOp<'buf>>),
   BinaryOp(Box<BinaryOp<'buf>>),
 
@@ -34,6 +43,9 @@ pub struct StringLit<'buf>(pub Cow<'buf, str>);
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct NumberLit(pub f64);
 
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct BoolLit(pub bool);
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct If<'buf> {
   pub condition: Expr<'buf>,
@@ -41,10 +53,17 @@ pub struct If<'buf> {
   pub fallthrough: Option<Expr<'buf>>,
 }
 
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Cond<'buf> {
+  pub arms: Vec<(Expr<'buf>, Expr<'buf>)>,
+  pub default: Option<Expr<'buf>>,
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Call<'buf> {
   pub name: &'buf str,
   pub args: Option<Expr<'buf>>,
+  pub loc: Loc<'buf>,
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -53,16 +72,64 @@ pub struct Assign<'buf> {
   pub expr: Expr<'buf>,
 }
 
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct CompoundAssign<'buf> {
+  pub op: BinaryOperator,
+  pub ident: &'buf str,
+  pub expr: Expr<'buf>,
+}
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Const<'buf> {
+  pub ident: &'buf str,
+  pub ty: Option<TypeName<'buf>>,
+  pub expr: Expr<'buf>,
+  pub loc: Loc<'buf>,
+}
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Define<'buf> {
   pub ident: &'buf str,
+  pub ty: Option<TypeName<'buf>>,
   pub expr: Expr<'buf>,
+  pub loc: Loc<'buf>,
 }
 
+/// Leave the enclosing function with the value of an expression.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Return<'buf>(pub Expr<'buf>);
+
+/// A literal tree taken as data rather than evaluated.
+///
+/// Lists are [`Compound`]s, atoms are literals and any other token, such as an identifier or
+/// operator, is a [`StringLit`] of its source.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Quote<'buf>(pub Expr<'buf>);
+
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
 pub struct Function<'buf> {
   pub name: &'buf str,
-  pub params: Vec<&'buf str>,
+  pub params: Vec<Param<'buf>>,
+  pub body: Expr<'buf>,
+}
+
+/// A function parameter with an optional type annotation.
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Param<'buf> {
+  pub name: &'buf str,
+  pub ty: Option<TypeName<'buf>>,
+}
+
+/// A type annotation such as `number` in `(var x:number 1)`, not yet enforced.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct TypeName<'buf>(pub &'buf str);
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Import<'buf>(pub Cow<'buf, str>);
+
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub struct Lambda<'buf> {
+  pub params: Vec<Param<'buf>>,
   pub body: Expr<'buf>,
 }
 
@@ -70,6 +137,7 @@ pub struct Function<'buf> {
 pub struct UnaryOp<'buf> {
   pub op: UnaryOperator,
   pub expr: Expr<'buf>,
+  pub loc: Loc<'buf>,
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -77,6 +145,37 @@ pub struct BinaryOp<'buf> {
   pub lhs: Expr<'buf>,
   pub op: BinaryOperator,
   pub rhs: Expr<'buf>,
+  pub loc: Loc<'buf>,
+}
+
+/// Where an expression was parsed from.
+///
+/// Locations are ignored when comparing expressions, the same code is equal wherever it's from.
+#[derive(Clone)]
+pub struct Loc<'buf>(pub Span<'buf>);
+
+impl Default for Loc<'_> {
+  fn default() -> Self {
+    Self(Span::eof())
+  }
+}
+
+impl std::fmt::Debug for Loc<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self.0)
+  }
+}
+
+impl PartialEq for Loc<'_> {
+  fn eq(&self, _: &Self) -> bool {
+    true
+  }
+}
+
+impl PartialOrd for Loc<'_> {
+  fn partial_cmp(&self, _: &Self) -> Option<Ordering> {
+    Some(Ordering::Equal)
+  }
 }
 
 #[derive(Debug, Clone, PartialEq, PartialOrd)]
@@ -91,6 +190,7 @@ pub struct Compound<'buf>(pub Vec<Expr<'buf>>);
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum UnaryOperator {
   Neg,
+  Not,
   BNot,
   AddInc,
   SubInc,
@@ -136,12 +236,24 @@ impl<'buf> From<NumberLit> for Expr<'buf> {
   }
 }
 
+impl<'buf> From<BoolLit> for Expr<'buf> {
+  fn from(expr: BoolLit) -> Self {
+    Expr::Bool(expr)
+  }
+}
+
 impl<'buf> From<If<'buf>> for Expr<'buf> {
   fn from(expr: If<'buf>) -> Self {
     Expr::If(Box::new(expr))
   }
 }
 
+impl<'buf> From<Cond<'buf>> for Expr<'buf> {
+  fn from(expr: Cond<'buf>) -> Self {
+    Expr::Cond(Box::new(expr))
+  }
+}
+
 impl<'buf> From<Call<'buf>> for Expr<'buf> {
   fn from(expr: Call<'buf>) -> Self {
     Expr::Call(Box::new(expr))
@@ -154,6 +266,24 @@ impl<'buf> From<Assign<'buf>> for Expr<'buf> {
   }
 }
 
+impl<'buf> From<CompoundAssign<'buf>> for Expr<'buf> {
+  fn from(expr: CompoundAssign<'buf>) -> Self {
+    Expr::CompoundAssign(Box::new(expr))
+  }
+}
+
+impl<'buf> From<&'buf str> for Param<'buf> {
+  fn from(name: &'buf str) -> Self {
+    Param { name, ty: None }
+  }
+}
+
+impl<'buf> From<Const<'buf>> for Expr<'buf> {
+  fn from(expr: Const<'buf>) -> Self {
+    Expr::Const(Box::new(expr))
+  }
+}
+
 impl<'buf> From<Define<'buf>> for Expr<'buf> {
   fn from(expr: Define<'buf>) -> Self {
     Expr::Define(Box::new(expr))
@@ -166,6 +296,30 @@ impl<'buf> From<Function<'buf>> for Expr<'buf> {
   }
 }
 
+impl<'buf> From<Import<'buf>> for Expr<'buf> {
+  fn from(expr: Import<'buf>) -> Self {
+    Expr::Import(expr)
+  }
+}
+
+impl<'buf> From<Lambda<'buf>> for Expr<'buf> {
+  fn from(expr: Lambda<'buf>) -> Self {
+    Expr::Lambda(Box::new(expr))
+  }
+}
+
+impl<'buf> From<Return<'buf>> for Expr<'buf> {
+  fn from(expr: Return<'buf>) -> Self {
+    Expr::Return(Box::new(expr))
+  }
+}
+
+impl<'buf> From<Quote<'buf>> for Expr<'buf> {
+  fn from(expr: Quote<'buf>) -> Self {
+    Expr::Quote(Box::new(expr))
+  }
+}
+
 impl<'buf> From<UnaryOp<'buf>> for Expr<'buf> {
   fn from(expr: UnaryOp<'buf>) -> Self {
     Expr::UnaryOp(Box::new(expr))
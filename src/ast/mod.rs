@@ -10,21 +10,85 @@ use crate::{
   lex::{Lexer, Token, TokenKind},
   types::Span,
 };
-use std::iter::Peekable;
+use std::borrow::Cow;
+
+/// Most `(` a parser accepts without a matching `)`, deeper sources are rejected rather than
+/// overflowing the stack.
+pub const MAX_DEPTH: usize = 48;
 
 pub struct Parser<'buf> {
-  tokens: Peekable<Lexer<'buf>>,
+  tokens: Lexer<'buf>,
 }
 
 impl<'buf> Parser<'buf> {
   pub fn new(buf: &'buf str) -> Self {
     Self {
-      tokens: Lexer::new(buf).peekable(),
+      tokens: Lexer::new(buf),
     }
   }
 
+  /// Parse every top-level form until end of file.
+  ///
+  /// Sibling forms are wrapped in an implicit [`Compound`], a single form is returned as is
+  /// and, an empty source yields [`Noop`].
   pub fn parse(&mut self) -> ParseResult<'buf, Expr<'buf>> {
-    Ok(self.next_expr(0, false)?.unwrap_or_else(|| Noop.into()))
+    let (expr, mut errors) = self.parse_recovering();
+
+    match errors.is_empty() {
+      true => Ok(expr),
+      false => Err(errors.remove(0)),
+    }
+  }
+
+  /// Parse every top-level form like [`Parser::parse`], skipping the rest of a form which fails
+  /// to parse so every error is found.
+  ///
+  /// The forms which parsed are returned along with the errors, in source order.
+  pub fn parse_recovering(&mut self) -> (Expr<'buf>, Vec<ParseError<'buf>>) {
+    let mut exprs = Vec::new();
+    let mut errors = Vec::new();
+
+    for result in self {
+      match result {
+        Ok(expr) => exprs.push(expr),
+        Err(err) => errors.push(err),
+      }
+    }
+
+    let expr = match exprs.len() {
+      0 => Noop.into(),
+      1 => exprs.remove(0),
+      _ => Compound(exprs).into(),
+    };
+
+    (expr, errors)
+  }
+
+  /// Parse the next top-level form, skipping the rest of it if it fails to parse.
+  fn next_form(&mut self) -> Option<ParseResult<'buf, Expr<'buf>>> {
+    loop {
+      match self.tokens.peek()? {
+        // A `)` at the top-level has no matching `(`
+        Ok(token) if token.is_right_paren() => {
+          let err = ParseError::unexpected_token(token);
+          self.tokens.next();
+
+          return Some(Err(err));
+        }
+        _ => {}
+      }
+
+      match self.next_expr(1, false) {
+        Ok(Some(expr)) => return Some(Ok(expr)),
+        Ok(None) => {}
+        Err(err) => {
+          // Skip to the end of the failed top-level form
+          while self.tokens.depth() > 0 && self.tokens.next().is_some() {}
+
+          return Some(Err(err));
+        }
+      }
+    }
   }
 
   fn next_expr(&mut self, limit: usize, in_paren: bool) -> ParseResult<'buf, Option<Expr<'buf>>> {
@@ -41,10 +105,13 @@ impl<'buf> Parser<'buf> {
 
       match self.tokens.next().transpose()? {
         Some(Token(span, TokenKind::LParen)) => {
-          if let Some(expr) = self.next_expr(255, true)? {
-            exprs.push(expr);
+          if self.tokens.depth() > MAX_DEPTH {
+            return Err(ParseError::nested_too_deep(&span));
           }
 
+          // An empty group is still a value so `(f ())` passes one argument
+          exprs.push(self.next_expr(255, true)?.unwrap_or_else(|| Noop.into()));
+
           match self.tokens.next().transpose()? {
             Some(Token(_, TokenKind::RParen)) => {}
             Some(Token(span, _)) => return Err(ParseError::expected_left_paren(&span)),
@@ -80,56 +147,112 @@ impl<'buf> Parser<'buf> {
 
   fn next_stmt(&mut self, token: &Token<'buf>) -> ParseResult<'buf, Option<Expr<'buf>>> {
     Ok(Some(match token {
-      // (var ident expr)
-      Token(span, TokenKind::Ident("var")) => Define {
-        ident: self.next_ident(&span)?,
-        expr: self
-          .next_expr(1, false)?
-          .ok_or_else(|| ParseError::expected_var_expr(&span))?,
+      // (var ident(:type)? expr)
+      Token(span, TokenKind::Ident("var")) => {
+        let (ident, ty) = self.next_typed_ident(span)?;
+
+        Define {
+          ident,
+          ty,
+          expr: self
+            .next_expr(1, false)?
+            .ok_or_else(|| ParseError::expected_var_expr(span))?,
+          loc: Loc(span.clone()),
+        }
+        .into()
+      }
+
+      // (const ident(:type)? expr)
+      Token(span, TokenKind::Ident("const")) => {
+        let (ident, ty) = self.next_typed_ident(span)?;
+
+        Const {
+          ident,
+          ty,
+          expr: self
+            .next_expr(1, false)?
+            .ok_or_else(|| ParseError::expected_const_expr(span))?,
+          loc: Loc(span.clone()),
+        }
+        .into()
       }
-      .into(),
 
       // (if expr expr expr?)
       Token(span, TokenKind::Ident("if")) => If {
         condition: self
           .next_expr(1, false)?
-          .ok_or_else(|| ParseError::expected_if_condition(&span))?,
+          .ok_or_else(|| ParseError::expected_if_condition(span))?,
         body: self
           .next_expr(1, false)?
-          .ok_or_else(|| ParseError::expected_if_body(&span))?,
+          .ok_or_else(|| ParseError::expected_if_body(span))?,
         fallthrough: self.next_expr(1, false)?,
       }
       .into(),
 
+      // (cond (expr expr)* (else expr)?)
+      Token(span, TokenKind::Ident("cond")) => self.next_cond(span)?,
+
+      // (import string)
+      Token(span, TokenKind::Ident("import")) => match self.tokens.next().transpose()? {
+        Some(Token(_, TokenKind::String(path))) => Import(path).into(),
+        Some(Token(span, _)) => return Err(ParseError::expected_import_path(&span)),
+        None => return Err(ParseError::expected_import_path(span)),
+      },
+
+      // (fun (ident*) expr)
+      Token(span, TokenKind::Ident("fun"))
+        if matches!(self.tokens.peek(), Some(Ok(Token(_, TokenKind::LParen)))) =>
+      {
+        Lambda {
+          params: self.next_params(span)?,
+          body: self
+            .next_expr(0, false)?
+            .ok_or_else(|| ParseError::expected_func_body(span))?,
+        }
+        .into()
+      }
+
       // (fun ident (ident*) expr)
       Token(span, TokenKind::Ident("fun")) => Function {
-        name: self.next_ident(&span)?,
-        params: self.next_params(&span)?,
+        name: self.next_ident(span)?,
+        params: self.next_params(span)?,
         body: self
           .next_expr(0, false)?
-          .ok_or_else(|| ParseError::expected_func_body(&span))?,
+          .ok_or_else(|| ParseError::expected_func_body(span))?,
       }
       .into(),
 
+      // (return expr)
+      Token(span, TokenKind::Ident("return")) => Return(
+        self
+          .next_expr(1, false)?
+          .ok_or_else(|| ParseError::expected_return_expr(span))?,
+      )
+      .into(),
+
+      // (quote datum)
+      Token(span, TokenKind::Ident("quote")) => Quote(self.next_datum(span)?).into(),
+
       Token(span, TokenKind::Ident("equal")) => self.next_binary_op(BinaryOperator::Eq, span)?,
       Token(span, TokenKind::Ident("neq")) => self.next_binary_op(BinaryOperator::NEq, span)?,
+      Token(span, TokenKind::Ident("not")) => self.next_unary_op(UnaryOperator::Not, span)?,
 
       // (ident expr*)
-      Token(_, TokenKind::Ident(ident)) => match self.tokens.peek().cloned() {
-        Some(Ok(Token(_, TokenKind::RParen))) => RefParam(ident).into(),
-        _ => Call {
-          name: ident,
-          args: self.next_expr(0, false)?,
-        }
-        .into(),
-      },
+      Token(span, TokenKind::Ident(ident)) => Call {
+        name: ident,
+        args: self.next_expr(0, false)?,
+        loc: Loc(span.clone()),
+      }
+      .into(),
 
-      Token(span, TokenKind::Var(ident)) => match self.tokens.peek().cloned() {
+      Token(span, TokenKind::Var(ident)) => match self.tokens.peek() {
         Some(Ok(Token(paren, TokenKind::LParen))) => {
+          let paren = paren.clone();
+
           // Consume `(`
           self.tokens.next().transpose()?;
 
-          let name = self.next_ident(&span)?;
+          let name = self.next_ident(span)?;
           let args = self.next_expr(0, false)?;
 
           match self
@@ -144,7 +267,12 @@ impl<'buf> Parser<'buf> {
 
           Assign {
             ident,
-            expr: Call { name, args }.into(),
+            expr: Call {
+              name,
+              args,
+              loc: Loc(span.clone()),
+            }
+            .into(),
           }
           .into()
         }
@@ -152,7 +280,7 @@ impl<'buf> Parser<'buf> {
       },
 
       Token(span, TokenKind::Add) => self.next_binary_op(BinaryOperator::Add, span)?,
-      Token(span, TokenKind::Sub) => self.next_binary_op(BinaryOperator::Sub, span)?,
+      Token(span, TokenKind::Sub) => self.next_sub_op(span)?,
       Token(span, TokenKind::Mul) => self.next_binary_op(BinaryOperator::Mul, span)?,
       Token(span, TokenKind::Div) => self.next_binary_op(BinaryOperator::Div, span)?,
       Token(span, TokenKind::Pow) => self.next_binary_op(BinaryOperator::Pow, span)?,
@@ -166,6 +294,11 @@ impl<'buf> Parser<'buf> {
       Token(span, TokenKind::GtEq) => self.next_binary_op(BinaryOperator::GtEq, span)?,
       Token(span, TokenKind::LtEq) => self.next_binary_op(BinaryOperator::LtEq, span)?,
 
+      Token(span, TokenKind::AddAssign) => self.next_compound_assign(BinaryOperator::Add, span)?,
+      Token(span, TokenKind::SubAssign) => self.next_compound_assign(BinaryOperator::Sub, span)?,
+      Token(span, TokenKind::MulAssign) => self.next_compound_assign(BinaryOperator::Mul, span)?,
+      Token(span, TokenKind::DivAssign) => self.next_compound_assign(BinaryOperator::Div, span)?,
+
       Token(span, TokenKind::BNot) => self.next_unary_op(UnaryOperator::BNot, span)?,
       Token(span, TokenKind::AddInc) => self.next_unary_inc_op(BinaryOperator::Add, span)?,
       Token(span, TokenKind::SubInc) => self.next_unary_inc_op(BinaryOperator::Sub, span)?,
@@ -177,6 +310,8 @@ impl<'buf> Parser<'buf> {
   fn next_simple(&mut self, token: &Token<'buf>) -> ParseResult<'buf, Option<Expr<'buf>>> {
     Ok(Some(match token {
       Token(_, TokenKind::Var(value)) => RefVar(value).into(),
+      Token(_, TokenKind::Ident("true")) => BoolLit(true).into(),
+      Token(_, TokenKind::Ident("false")) => BoolLit(false).into(),
       Token(_, TokenKind::Ident(value)) => RefParam(value).into(),
       Token(_, TokenKind::Number(value)) => NumberLit(*value).into(),
       Token(_, TokenKind::String(value)) => StringLit(value.clone()).into(),
@@ -185,6 +320,93 @@ impl<'buf> Parser<'buf> {
     }))
   }
 
+  /// Parse a literal tree for `quote`, a list is kept as a [`Compound`] even when it has fewer
+  /// than two items.
+  fn next_datum(&mut self, beg: &Span<'buf>) -> ParseResult<'buf, Expr<'buf>> {
+    match self.tokens.next().transpose()? {
+      Some(Token(span, TokenKind::LParen)) => {
+        if self.tokens.depth() > MAX_DEPTH {
+          return Err(ParseError::nested_too_deep(&span));
+        }
+
+        let mut items = Vec::new();
+
+        loop {
+          match self.peek_token()? {
+            Some(token) if token.is_right_paren() => {
+              // Consume `)`
+              self.tokens.next().transpose()?;
+
+              return Ok(Compound(items).into());
+            }
+            Some(_) => items.push(self.next_datum(&span)?),
+            None => return Err(ParseError::expected_right_paren(&span)),
+          }
+        }
+      }
+      Some(Token(span, TokenKind::RParen)) => Err(ParseError::expected_quote_expr(&span)),
+      Some(Token(_, TokenKind::Ident("true"))) => Ok(BoolLit(true).into()),
+      Some(Token(_, TokenKind::Ident("false"))) => Ok(BoolLit(false).into()),
+      Some(Token(_, TokenKind::Number(value))) => Ok(NumberLit(value).into()),
+      Some(Token(_, TokenKind::String(value))) => Ok(StringLit(value).into()),
+      // Anything else is a symbol, kept as it's written
+      Some(Token(span, _)) => Ok(
+        StringLit(Cow::Borrowed(
+          &span.source()[span.begin().offset..span.end().offset],
+        ))
+        .into(),
+      ),
+      None => Err(ParseError::expected_quote_expr(beg)),
+    }
+  }
+
+  fn next_cond(&mut self, span: &Span<'buf>) -> ParseResult<'buf, Expr<'buf>> {
+    let mut arms = Vec::new();
+    let mut default = None;
+
+    loop {
+      let paren = match self.peek_token()? {
+        // Closing `)` of `cond` is left for the caller to consume
+        Some(Token(_, TokenKind::RParen)) if default.is_some() || !arms.is_empty() => break,
+        Some(Token(span, TokenKind::LParen)) if default.is_none() => span.clone(),
+        Some(Token(span, _)) => return Err(ParseError::expected_cond_arm(span)),
+        None => return Err(ParseError::expected_cond_arm(span)),
+      };
+
+      // Consume `(`
+      self.tokens.next();
+
+      match self.peek_token()? {
+        Some(Token(_, TokenKind::Ident("else"))) => {
+          // Consume `else`
+          self.tokens.next();
+
+          default = Some(
+            self
+              .next_expr(1, false)?
+              .ok_or_else(|| ParseError::expected_cond_body(&paren))?,
+          );
+        }
+        _ => arms.push((
+          self
+            .next_expr(1, false)?
+            .ok_or_else(|| ParseError::expected_cond_condition(&paren))?,
+          self
+            .next_expr(1, false)?
+            .ok_or_else(|| ParseError::expected_cond_body(&paren))?,
+        )),
+      }
+
+      match self.tokens.next().transpose()? {
+        Some(Token(_, TokenKind::RParen)) => {}
+        Some(Token(span, _)) => return Err(ParseError::expected_right_paren(&span)),
+        None => return Err(ParseError::expected_right_paren(&paren)),
+      }
+    }
+
+    Ok(Cond { arms, default }.into())
+  }
+
   fn next_binary_op(
     &mut self,
     op: BinaryOperator,
@@ -199,6 +421,49 @@ impl<'buf> Parser<'buf> {
         rhs: self
           .next_expr(1, false)?
           .ok_or_else(|| ParseError::expected_op_lhs(span))?,
+        loc: Loc(span.clone()),
+      }
+      .into(),
+    )
+  }
+
+  /// Parse `(- lhs rhs)` or the negation `(- expr)`.
+  fn next_sub_op(&mut self, span: &Span<'buf>) -> ParseResult<'buf, Expr<'buf>> {
+    let lhs = self
+      .next_expr(1, false)?
+      .ok_or_else(|| ParseError::expected_op_lhs(span))?;
+
+    Ok(match self.tokens.peek() {
+      Some(Ok(token)) if token.is_right_paren() => UnaryOp {
+        op: UnaryOperator::Neg,
+        expr: lhs,
+        loc: Loc(span.clone()),
+      }
+      .into(),
+      _ => BinaryOp {
+        op: BinaryOperator::Sub,
+        lhs,
+        rhs: self
+          .next_expr(1, false)?
+          .ok_or_else(|| ParseError::expected_op_rhs(span))?,
+        loc: Loc(span.clone()),
+      }
+      .into(),
+    })
+  }
+
+  fn next_compound_assign(
+    &mut self,
+    op: BinaryOperator,
+    span: &Span<'buf>,
+  ) -> ParseResult<'buf, Expr<'buf>> {
+    Ok(
+      CompoundAssign {
+        op,
+        ident: self.next_ident(span)?,
+        expr: self
+          .next_expr(1, false)?
+          .ok_or_else(|| ParseError::expected_op_rhs(span))?,
       }
       .into(),
     )
@@ -215,6 +480,7 @@ impl<'buf> Parser<'buf> {
         expr: self
           .next_expr(1, false)?
           .ok_or_else(|| ParseError::expected_op_lhs(span))?,
+        loc: Loc(span.clone()),
       }
       .into(),
     )
@@ -225,8 +491,10 @@ impl<'buf> Parser<'buf> {
     op: BinaryOperator,
     span: &Span<'buf>,
   ) -> ParseResult<'buf, Expr<'buf>> {
-    Ok(match self.tokens.peek().cloned().transpose()? {
+    Ok(match self.peek_token()? {
       Some(Token(_, TokenKind::Ident(ident))) => {
+        let ident = *ident;
+
         // Consume `Token(_, TokenKind::Ident(_))`
         self.tokens.next();
 
@@ -237,6 +505,7 @@ impl<'buf> Parser<'buf> {
               op,
               lhs: RefVar(ident).into(),
               rhs: NumberLit(1.0).into(),
+              loc: Loc(span.clone()),
             }
             .into(),
           }
@@ -252,11 +521,24 @@ impl<'buf> Parser<'buf> {
           .next_expr(1, false)?
           .ok_or_else(|| ParseError::expected_op_lhs(span))?,
         rhs: NumberLit(1.0).into(),
+        loc: Loc(span.clone()),
       }
       .into(),
     })
   }
 
+  /// Get the next token without consuming it, failing if it didn't lex.
+  ///
+  /// The token is borrowed rather than cloned so deciding how to parse a form doesn't copy the
+  /// payload of a string token.
+  fn peek_token(&mut self) -> ParseResult<'buf, Option<&Token<'buf>>> {
+    match self.tokens.peek() {
+      Some(Ok(token)) => Ok(Some(token)),
+      Some(Err(err)) => Err(err.clone().into()),
+      None => Ok(None),
+    }
+  }
+
   fn next_ident(&mut self, beg: &Span<'buf>) -> ParseResult<'buf, &'buf str> {
     match self.tokens.next().transpose()? {
       Some(Token(_, TokenKind::Ident(ident))) => Ok(ident),
@@ -265,7 +547,33 @@ impl<'buf> Parser<'buf> {
     }
   }
 
-  fn next_params(&mut self, beg: &Span<'buf>) -> ParseResult<'buf, Vec<&'buf str>> {
+  /// Parse an identifier followed by an optional `:type` annotation.
+  fn next_typed_ident(
+    &mut self,
+    beg: &Span<'buf>,
+  ) -> ParseResult<'buf, (&'buf str, Option<TypeName<'buf>>)> {
+    let ident = self.next_ident(beg)?;
+
+    Ok((ident, self.next_type(beg)?))
+  }
+
+  fn next_type(&mut self, beg: &Span<'buf>) -> ParseResult<'buf, Option<TypeName<'buf>>> {
+    match self.tokens.peek() {
+      Some(Ok(Token(_, TokenKind::Colon))) => {
+        // Consume `:`
+        self.tokens.next().transpose()?;
+
+        match self.tokens.next().transpose()? {
+          Some(Token(_, TokenKind::Ident(name))) => Ok(Some(TypeName(name))),
+          Some(Token(span, _)) => Err(ParseError::expected_type(&span)),
+          None => Err(ParseError::expected_type(beg)),
+        }
+      }
+      _ => Ok(None),
+    }
+  }
+
+  fn next_params(&mut self, beg: &Span<'buf>) -> ParseResult<'buf, Vec<Param<'buf>>> {
     let mut params = Vec::new();
 
     match self.tokens.next().transpose()? {
@@ -276,7 +584,10 @@ impl<'buf> Parser<'buf> {
 
     loop {
       match self.tokens.next().transpose()? {
-        Some(Token(_, TokenKind::Ident(ident))) => params.push(ident),
+        Some(Token(span, TokenKind::Ident(name))) => params.push(Param {
+          name,
+          ty: self.next_type(&span)?,
+        }),
         Some(Token(_, TokenKind::RParen)) => return Ok(params),
         Some(Token(span, _)) => return Err(ParseError::expected_right_paren(&span)),
         None => return Err(ParseError::expected_right_paren(beg)),
@@ -285,6 +596,17 @@ impl<'buf> Parser<'buf> {
   }
 }
 
+/// Top-level forms one at a time, for reading sources incrementally.
+///
+/// A form which fails to parse is skipped after its error, so iteration can carry on.
+impl<'buf> Iterator for Parser<'buf> {
+  type Item = ParseResult<'buf, Expr<'buf>>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.next_form()
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -338,12 +660,40 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_top_level_compound() {
+    assert_eq!(
+      Parser::new("(var a 1) (var b 2)").parse().unwrap(),
+      Compound(vec![
+        Define {
+          loc: Loc::default(),
+          ident: "a",
+          ty: None,
+          expr: NumberLit(1.0).into()
+        }
+        .into(),
+        Define {
+          loc: Loc::default(),
+          ident: "b",
+          ty: None,
+          expr: NumberLit(2.0).into()
+        }
+        .into(),
+      ])
+      .into()
+    );
+
+    assert!(Parser::new("(var a 1)) (var b 2)").parse().is_err());
+  }
+
   #[test]
   fn test_parse_define() {
     assert_eq!(
       Parser::new("(var variable 1)").parse().unwrap(),
       Define {
+        loc: Loc::default(),
         ident: "variable",
+        ty: None,
         expr: NumberLit(1.0).into()
       }
       .into()
@@ -352,13 +702,69 @@ mod tests {
     assert_eq!(
       Parser::new("((var variable (1)))").parse().unwrap(),
       Define {
+        loc: Loc::default(),
         ident: "variable",
+        ty: None,
         expr: NumberLit(1.0).into()
       }
       .into()
     );
   }
 
+  #[test]
+  fn test_parse_const() {
+    assert_eq!(
+      Parser::new("(const limit 10)").parse().unwrap(),
+      Const {
+        loc: Loc::default(),
+        ident: "limit",
+        ty: None,
+        expr: NumberLit(10.0).into()
+      }
+      .into()
+    );
+
+    assert!(Parser::new("(const limit)").parse().is_err());
+  }
+
+  #[test]
+  fn test_type_annotations() {
+    assert_eq!(
+      Parser::new("(fun f (a:number b:string c) $a)")
+        .parse()
+        .unwrap(),
+      Function {
+        name: "f",
+        params: vec![
+          Param {
+            name: "a",
+            ty: Some(TypeName("number")),
+          },
+          Param {
+            name: "b",
+            ty: Some(TypeName("string")),
+          },
+          "c".into(),
+        ],
+        body: RefVar("a").into(),
+      }
+      .into()
+    );
+
+    assert_eq!(
+      Parser::new("(var x:number 1)").parse().unwrap(),
+      Define {
+        loc: Loc::default(),
+        ident: "x",
+        ty: Some(TypeName("number")),
+        expr: NumberLit(1.0).into()
+      }
+      .into()
+    );
+
+    assert!(Parser::new("(var x: 1)").parse().is_err());
+  }
+
   #[test]
   fn test_if() {
     assert_eq!(
@@ -382,13 +788,51 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_cond() {
+    assert_eq!(
+      Parser::new("(cond ((equal $x 1) \"one\") ($y 2) (else 0))")
+        .parse()
+        .unwrap(),
+      Cond {
+        arms: vec![
+          (
+            BinaryOp {
+              op: BinaryOperator::Eq,
+              lhs: RefVar("x").into(),
+              rhs: NumberLit(1.0).into(),
+              loc: Loc::default(),
+            }
+            .into(),
+            StringLit(Cow::from("one")).into()
+          ),
+          (RefVar("y").into(), NumberLit(2.0).into()),
+        ],
+        default: Some(NumberLit(0.0).into())
+      }
+      .into()
+    );
+
+    assert_eq!(
+      Parser::new("(cond ($x 1))").parse().unwrap(),
+      Cond {
+        arms: vec![(RefVar("x").into(), NumberLit(1.0).into())],
+        default: None
+      }
+      .into()
+    );
+
+    assert!(Parser::new("(cond)").parse().is_err());
+    assert!(Parser::new("(cond (else 0) ($x 1))").parse().is_err());
+  }
+
   #[test]
   fn test_func() {
     assert_eq!(
       Parser::new("(fun function (a b c d) 1)").parse().unwrap(),
       Function {
         name: "function",
-        params: vec!["a", "b", "c", "d"],
+        params: vec!["a".into(), "b".into(), "c".into(), "d".into()],
         body: NumberLit(1.0).into()
       }
       .into()
@@ -400,7 +844,7 @@ mod tests {
         .unwrap(),
       Function {
         name: "function",
-        params: vec!["a", "b", "c", "d"],
+        params: vec!["a".into(), "b".into(), "c".into(), "d".into()],
         body: Compound(vec![
           NumberLit(1.0).into(),
           NumberLit(2.0).into(),
@@ -416,6 +860,131 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_lambda() {
+    assert_eq!(
+      Parser::new("(fun make (n) (fun (x) (+ x n)))")
+        .parse()
+        .unwrap(),
+      Function {
+        name: "make",
+        params: vec!["n".into()],
+        body: Lambda {
+          params: vec!["x".into()],
+          body: BinaryOp {
+            op: BinaryOperator::Add,
+            lhs: RefParam("x").into(),
+            rhs: RefParam("n").into(),
+            loc: Loc::default(),
+          }
+          .into()
+        }
+        .into()
+      }
+      .into()
+    );
+  }
+
+  #[test]
+  fn test_return() {
+    assert_eq!(
+      Parser::new("(fun f (n) (return n))").parse().unwrap(),
+      Function {
+        name: "f",
+        params: vec!["n".into()],
+        body: Return(RefParam("n").into()).into(),
+      }
+      .into()
+    );
+
+    assert!(Parser::new("(fun f (n) (return))").parse().is_err());
+  }
+
+  #[test]
+  fn test_quote() {
+    assert_eq!(
+      Parser::new("(quote (1 (f \"a\") () true))")
+        .parse()
+        .unwrap(),
+      Quote(
+        Compound(vec![
+          NumberLit(1.0).into(),
+          Compound(vec![
+            StringLit(Cow::from("f")).into(),
+            StringLit(Cow::from("a")).into()
+          ])
+          .into(),
+          Compound(vec![]).into(),
+          BoolLit(true).into(),
+        ])
+        .into()
+      )
+      .into()
+    );
+
+    assert_eq!(
+      Parser::new("(quote $x)").parse().unwrap(),
+      Quote(StringLit(Cow::from("$x")).into()).into()
+    );
+
+    assert!(Parser::new("(quote)").parse().is_err());
+    assert!(Parser::new("(quote (1 2)").parse().is_err());
+  }
+
+  #[test]
+  fn test_iter() {
+    let exprs = Parser::new("(var a 1)(var b 2)")
+      .collect::<ParseResult<Vec<_>>>()
+      .unwrap();
+
+    assert_eq!(
+      exprs,
+      vec![
+        Define {
+          loc: Loc::default(),
+          ident: "a",
+          ty: None,
+          expr: NumberLit(1.0).into(),
+        }
+        .into(),
+        Define {
+          loc: Loc::default(),
+          ident: "b",
+          ty: None,
+          expr: NumberLit(2.0).into(),
+        }
+        .into(),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_parse_recovering() {
+    let (expr, errors) = Parser::new("(var x (return)) ) (var y 1) (if)").parse_recovering();
+
+    assert_eq!(
+      expr,
+      Define {
+        loc: Loc::default(),
+        ident: "y",
+        ty: None,
+        expr: NumberLit(1.0).into(),
+      }
+      .into()
+    );
+    assert_eq!(errors.len(), 3);
+  }
+
+  #[test]
+  fn test_import() {
+    assert_eq!(
+      Parser::new("(import \"lib.chal\")").parse().unwrap(),
+      Import(Cow::from("lib.chal")).into()
+    );
+
+    assert!(Parser::new("(import lib)").parse().is_err());
+  }
+
   #[test]
   fn test_call() {
     assert_eq!(
@@ -430,7 +999,30 @@ mod tests {
             NumberLit(4.0).into(),
           ])
           .into()
-        )
+        ),
+        loc: Loc::default(),
+      }
+      .into()
+    );
+  }
+
+  #[test]
+  fn test_call_empty_group() {
+    assert_eq!(
+      Parser::new("(f ())").parse().unwrap(),
+      Call {
+        name: "f",
+        args: Some(Noop.into()),
+        loc: Loc::default(),
+      }
+      .into()
+    );
+    assert_eq!(
+      Parser::new("(f () 1)").parse().unwrap(),
+      Call {
+        name: "f",
+        args: Some(Compound(vec![Noop.into(), NumberLit(1.0).into()]).into()),
+        loc: Loc::default(),
       }
       .into()
     );
@@ -452,7 +1044,8 @@ mod tests {
               NumberLit(4.0).into(),
             ])
             .into()
-          )
+          ),
+          loc: Loc::default(),
         }
         .into()
       }
@@ -460,6 +1053,48 @@ mod tests {
     );
   }
 
+  #[test]
+  fn test_parse_bool() {
+    assert_eq!(
+      Parser::new("(equal true false)").parse().unwrap(),
+      BinaryOp {
+        op: BinaryOperator::Eq,
+        lhs: BoolLit(true).into(),
+        rhs: BoolLit(false).into(),
+        loc: Loc::default(),
+      }
+      .into()
+    );
+  }
+
+  #[test]
+  fn test_not() {
+    assert_eq!(
+      Parser::new("(not 0)").parse().unwrap(),
+      UnaryOp {
+        op: UnaryOperator::Not,
+        expr: NumberLit(0.0).into(),
+        loc: Loc::default(),
+      }
+      .into()
+    );
+  }
+
+  #[test]
+  fn test_neg() {
+    assert_eq!(
+      Parser::new("(- 5)").parse().unwrap(),
+      UnaryOp {
+        op: UnaryOperator::Neg,
+        expr: NumberLit(5.0).into(),
+        loc: Loc::default(),
+      }
+      .into()
+    );
+
+    assert!(Parser::new("(-)").parse().is_err());
+  }
+
   #[test]
   fn test_binop() {
     let mut tests = [
@@ -487,11 +1122,72 @@ mod tests {
         op: *op,
         lhs: NumberLit(0.0).into(),
         rhs: NumberLit(1.0).into(),
+        loc: Loc::default(),
+      }
+      .into();
+
+      assert_eq!(left, right);
+    }
+  }
+
+  #[test]
+  fn test_compound_assign() {
+    let mut tests = [
+      (Parser::new("(+= x 1)"), BinaryOperator::Add),
+      (Parser::new("(-= x 1)"), BinaryOperator::Sub),
+      (Parser::new("(*= x 1)"), BinaryOperator::Mul),
+      (Parser::new("(/= x 1)"), BinaryOperator::Div),
+    ];
+
+    for (parser, op) in tests.iter_mut() {
+      let left = parser.parse().unwrap();
+      let right = CompoundAssign {
+        op: *op,
+        ident: "x",
+        expr: NumberLit(1.0).into(),
       }
       .into();
 
       assert_eq!(left, right);
     }
+
+    assert!(Parser::new("(+= 1 1)").parse().is_err());
+    assert!(Parser::new("(+= x)").parse().is_err());
+  }
+
+  #[test]
+  fn test_peek_borrowed() {
+    // Forms decided by peeking still parse the same and report lex errors of the peeked token
+    assert_eq!(
+      Parser::new("(++ x)").parse().unwrap(),
+      Compound(vec![
+        Assign {
+          ident: "x",
+          expr: BinaryOp {
+            op: BinaryOperator::Add,
+            lhs: RefVar("x").into(),
+            rhs: NumberLit(1.0).into(),
+            loc: Loc::default(),
+          }
+          .into(),
+        }
+        .into(),
+        RefVar("x").into(),
+      ])
+      .into()
+    );
+    assert_eq!(
+      Parser::new("(cond (\"ab\" 1))").parse().unwrap(),
+      Cond {
+        arms: vec![(StringLit(Cow::from("ab")).into(), NumberLit(1.0).into())],
+        default: None
+      }
+      .into()
+    );
+
+    for src in &["(cond @)", "(cond (else @))", "(++ @)", "(f @)"] {
+      assert!(matches!(Parser::new(src).parse(), Err(ParseError::Lex(..))));
+    }
   }
 
   #[test]
@@ -499,6 +1195,20 @@ mod tests {
     assert!(Parser::new("(if 1 1 1 3)").parse().is_err())
   }
 
+  #[test]
+  pub fn test_error_source() {
+    use std::error::Error;
+
+    // The source doesn't have to outlive the error to be chained
+    let src = String::from("(print \"unterminated)");
+    let err = Parser::new(&src).parse().unwrap_err();
+
+    assert!(matches!(err, ParseError::Lex(..)));
+    assert!(err.source().is_some());
+    assert_eq!(err.span().source(), src);
+    assert!(Parser::new("(if)").parse().unwrap_err().source().is_none());
+  }
+
   #[test]
   pub fn test_parse_errors_chal() {
     assert!(Parser::new(include_str!("../../data/errors.chal"))
@@ -541,6 +1251,83 @@ mod tests {
       .unwrap();
   }
 
+  #[test]
+  pub fn test_malformed_no_panic() {
+    let deep = [
+      "(".repeat(100_000),
+      "(- ".repeat(100_000),
+      "(cond (".repeat(10_000),
+      format!("{}1{}", "(".repeat(1_000), ")".repeat(1_000)),
+    ];
+    let corpus = [
+      "",
+      ")",
+      "(",
+      "())",
+      "(()",
+      ")(",
+      "(+)",
+      "(- )",
+      "(^ 1)",
+      "(:",
+      "!",
+      "$",
+      "$1",
+      "(var)",
+      "(var x",
+      "(fun)",
+      "(fun f",
+      "(fun f (1) 2)",
+      "(if)",
+      "(cond)",
+      "(cond (else))",
+      "(cond 1)",
+      "(import)",
+      "(return)",
+      "(+= 1 2)",
+      "1..2",
+      "1.2.3",
+      ".5",
+      "1e999",
+      "999999999999999999999999999999999999999999",
+      "\"",
+      "'unterminated",
+      "\"a\nb\"",
+      "# comment only",
+      "é",
+      "(€ 1)",
+      "(x é€𝄞)",
+      "(print \"𝄞\" é)",
+      "\u{0}",
+      "(\t\r\n)",
+    ];
+
+    for src in corpus
+      .iter()
+      .copied()
+      .chain(deep.iter().map(String::as_str))
+    {
+      // Errors are rendered too since they slice the source around their span
+      if let Err(err) = Parser::new(src).parse() {
+        let _ = format!("{} {:?} {}", err, err.span(), err.span());
+      }
+
+      Parser::new(src).parse_recovering();
+    }
+
+    assert!(matches!(
+      Parser::new(&"(- ".repeat(MAX_DEPTH + 1)).parse(),
+      Err(ParseError::Unexpected(..))
+    ));
+    assert!(Parser::new(&format!(
+      "{}1{}",
+      "(".repeat(MAX_DEPTH),
+      ")".repeat(MAX_DEPTH)
+    ))
+    .parse()
+    .is_ok());
+  }
+
   #[test]
   #[cfg_attr(miri, ignore)]
   pub fn test_parse_stress() {
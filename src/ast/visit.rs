@@ -1,6 +1,6 @@
 use super::{
-  Assign, BinaryOp, Call, Define, Expr, Function, If, NumberLit, RefParam, RefVar, StringLit,
-  UnaryOp,
+  Assign, BinaryOp, BoolLit, Call, Compound, CompoundAssign, Cond, Const, Define, Expr, Function,
+  If, Import, Lambda, Noop, NumberLit, Quote, RefParam, RefVar, Return, StringLit, UnaryOp,
 };
 
 pub trait Visitor<'buf> {
@@ -8,30 +8,60 @@ pub trait Visitor<'buf> {
 
   fn visit(&mut self, expr: &Expr<'buf>) -> Result<(), Self::Error> {
     match expr {
-      Expr::Noop(_) => Ok(()),
+      Expr::Noop(expr) => self.visit_noop(expr),
+
+      Expr::String(expr) => self.visit_string(expr),
+      Expr::Number(expr) => self.visit_number(expr),
+      Expr::Bool(expr) => self.visit_bool(expr),
+
+      Expr::If(expr) => self.visit_if(expr),
+      Expr::Cond(expr) => self.visit_cond(expr),
+      Expr::Call(expr) => self.visit_call(expr),
+      Expr::Const(expr) => self.visit_const(expr),
+      Expr::Define(expr) => self.visit_define(expr),
+      Expr::Assign(expr) => self.visit_assign(expr),
+      Expr::CompoundAssign(expr) => self.visit_compound_assign(expr),
+      Expr::Function(expr) => self.visit_function(expr),
+      Expr::Import(expr) => self.visit_import(expr),
+      Expr::Lambda(expr) => self.visit_lambda(expr),
+      Expr::Return(expr) => self.visit_return(expr),
+      Expr::Quote(expr) => self.visit_quote(expr),
+      Expr::UnaryOp(expr) => self.visit_unary(expr),
+      Expr::BinaryOp(expr) => self.visit_binary(expr),
+
+      Expr::RefVar(expr) => self.visit_var(expr),
+      Expr::RefParam(expr) => self.visit_param(expr),
+
+      Expr::Compound(expr) => self.visit_compound(expr),
+    }
+  }
 
-      Expr::String(expr) => self.visit_string(&expr),
-      Expr::Number(expr) => self.visit_number(&expr),
+  /// Visit each expression of a sequence, see [`Visitor::visit_stmt`] and
+  /// [`Visitor::visit_tail`].
+  fn visit_compound(&mut self, expr: &Compound<'buf>) -> Result<(), Self::Error> {
+    if let Some((last, rest)) = expr.0.split_last() {
+      for expr in rest {
+        self.visit_stmt(expr)?;
+      }
 
-      Expr::If(expr) => self.visit_if(&expr),
-      Expr::Call(expr) => self.visit_call(&expr),
-      Expr::Define(expr) => self.visit_define(&expr),
-      Expr::Assign(expr) => self.visit_assign(&expr),
-      Expr::Function(expr) => self.visit_function(&expr),
-      Expr::UnaryOp(expr) => self.visit_unary(&expr),
-      Expr::BinaryOp(expr) => self.visit_binary(&expr),
+      self.visit_tail(last)?;
+    }
+
+    Ok(())
+  }
 
-      Expr::RefVar(expr) => self.visit_var(&expr),
-      Expr::RefParam(expr) => self.visit_param(&expr),
+  /// Visit an expression of a sequence other than the last, whose value is discarded.
+  fn visit_stmt(&mut self, expr: &Expr<'buf>) -> Result<(), Self::Error> {
+    self.visit(expr)
+  }
 
-      Expr::Compound(expr) => {
-        for expr in &expr.0 {
-          self.visit(expr)?;
-        }
+  /// Visit the last expression of a sequence, whose value is the value of the sequence.
+  fn visit_tail(&mut self, expr: &Expr<'buf>) -> Result<(), Self::Error> {
+    self.visit(expr)
+  }
 
-        Ok(())
-      }
-    }
+  fn visit_noop(&mut self, _: &Noop) -> Result<(), Self::Error> {
+    Ok(())
   }
 
   fn visit_string(&mut self, _: &StringLit<'buf>) -> Result<(), Self::Error> {
@@ -42,6 +72,10 @@ pub trait Visitor<'buf> {
     Ok(())
   }
 
+  fn visit_bool(&mut self, _: &BoolLit) -> Result<(), Self::Error> {
+    Ok(())
+  }
+
   fn visit_if(&mut self, expr: &If<'buf>) -> Result<(), Self::Error> {
     self.visit(&expr.condition)?;
     self.visit(&expr.body)?;
@@ -53,6 +87,19 @@ pub trait Visitor<'buf> {
     Ok(())
   }
 
+  fn visit_cond(&mut self, expr: &Cond<'buf>) -> Result<(), Self::Error> {
+    for (condition, body) in &expr.arms {
+      self.visit(condition)?;
+      self.visit(body)?;
+    }
+
+    if let Some(expr) = &expr.default {
+      self.visit(expr)?;
+    }
+
+    Ok(())
+  }
+
   fn visit_call(&mut self, expr: &Call<'buf>) -> Result<(), Self::Error> {
     if let Some(expr) = &expr.args {
       self.visit(expr)?;
@@ -65,6 +112,14 @@ pub trait Visitor<'buf> {
     self.visit(&expr.expr)
   }
 
+  fn visit_compound_assign(&mut self, expr: &CompoundAssign<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.expr)
+  }
+
+  fn visit_const(&mut self, expr: &Const<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.expr)
+  }
+
   fn visit_define(&mut self, expr: &Define<'buf>) -> Result<(), Self::Error> {
     self.visit(&expr.expr)
   }
@@ -73,6 +128,23 @@ pub trait Visitor<'buf> {
     self.visit(&expr.body)
   }
 
+  fn visit_import(&mut self, _: &Import<'buf>) -> Result<(), Self::Error> {
+    Ok(())
+  }
+
+  fn visit_lambda(&mut self, expr: &Lambda<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.body)
+  }
+
+  fn visit_return(&mut self, expr: &Return<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.0)
+  }
+
+  /// Quoted data isn't evaluated, so it holds no references or calls to visit.
+  fn visit_quote(&mut self, _: &Quote<'buf>) -> Result<(), Self::Error> {
+    Ok(())
+  }
+
   fn visit_unary(&mut self, expr: &UnaryOp<'buf>) -> Result<(), Self::Error> {
     self.visit(&expr.expr)
   }
@@ -92,3 +164,44 @@ pub trait Visitor<'buf> {
     Ok(())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::Visitor;
+  use crate::ast::{Expr, NumberLit, Parser};
+  use std::convert::Infallible;
+
+  /// Records each number and whether it's the value of its sequence.
+  #[derive(Default)]
+  struct Positions(Vec<(f64, bool)>);
+
+  impl<'buf> Visitor<'buf> for Positions {
+    type Error = Infallible;
+
+    fn visit_stmt(&mut self, expr: &Expr<'buf>) -> Result<(), Self::Error> {
+      if let Expr::Number(NumberLit(value)) = expr {
+        self.0.push((*value, false));
+      }
+
+      self.visit(expr)
+    }
+
+    fn visit_tail(&mut self, expr: &Expr<'buf>) -> Result<(), Self::Error> {
+      if let Expr::Number(NumberLit(value)) = expr {
+        self.0.push((*value, true));
+      }
+
+      self.visit(expr)
+    }
+  }
+
+  #[test]
+  fn test_stmt_and_tail() {
+    let mut positions = Positions::default();
+    positions
+      .visit(&Parser::new("(1 2 3)").parse().unwrap())
+      .unwrap();
+
+    assert_eq!(positions.0, vec![(1.0, false), (2.0, false), (3.0, true)]);
+  }
+}
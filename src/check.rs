@@ -0,0 +1,124 @@
+//! Checking sources for errors without compiling them.
+
+use crate::{
+  ast::{Expr, Noop, ParseError, Parser},
+  diagnostic::{Diagnostic, IntoDiagnostic},
+  ir::resolve::resolve,
+  lex::{Lexer, Token, TokenKind},
+  types::Span,
+};
+
+/// The tokens, syntax tree and errors of a source, for editor tooling.
+#[derive(Debug, Clone)]
+pub struct Analysis<'buf> {
+  /// Every token up to the first which fails to lex, that one given as [`TokenKind::Error`].
+  pub tokens: Vec<(TokenKind<'buf>, Span<'buf>)>,
+  /// The forms which parsed, `None` if none did.
+  pub ast: Option<Expr<'buf>>,
+  /// The same errors as [`check`] finds.
+  pub diagnostics: Vec<Diagnostic<'buf>>,
+}
+
+/// Find every syntax error and undefined variable in `src` without generating any code.
+///
+/// Forms which fail to parse are skipped, so variables they define are reported as undefined
+/// where they're used.
+pub fn check(src: &str) -> Vec<Diagnostic<'_>> {
+  let (expr, errors) = Parser::new(src).parse_recovering();
+
+  diagnose(&expr, errors)
+}
+
+/// Lex, parse and check `src` in one pass.
+pub fn analyze(src: &str) -> Analysis<'_> {
+  let mut tokens = Vec::new();
+
+  for token in Lexer::new(src) {
+    match token {
+      Ok(Token(span, kind)) => tokens.push((kind, span)),
+      Err(err) => {
+        tokens.push((TokenKind::Error, err.span().clone()));
+
+        break;
+      }
+    }
+  }
+
+  let (expr, errors) = Parser::new(src).parse_recovering();
+  let diagnostics = diagnose(&expr, errors);
+  let ast = match expr {
+    Expr::Noop(Noop) if !diagnostics.is_empty() => None,
+    expr => Some(expr),
+  };
+
+  Analysis {
+    tokens,
+    ast,
+    diagnostics,
+  }
+}
+
+fn diagnose<'buf>(expr: &Expr<'buf>, errors: Vec<ParseError<'buf>>) -> Vec<Diagnostic<'buf>> {
+  let mut diagnostics = errors
+    .into_iter()
+    .map(IntoDiagnostic::into_diagnostic)
+    .collect::<Vec<_>>();
+
+  if let Err(errors) = resolve(expr) {
+    diagnostics.extend(errors.into_iter().map(IntoDiagnostic::into_diagnostic));
+  }
+
+  diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{analyze, check};
+  use crate::lex::TokenKind;
+
+  #[test]
+  fn test_check() {
+    let diagnostics = check("(var x 1)\n(var y @)\n(print (+ $x $y))")
+      .into_iter()
+      .map(|diagnostic| (diagnostic.message, format!("{:?}", diagnostic.span)))
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      diagnostics,
+      vec![
+        (
+          "Unexpected character".to_string(),
+          "line 2, column 8".to_string()
+        ),
+        (
+          "Undefined variable `y`".to_string(),
+          "line 3, column 9".to_string()
+        ),
+      ]
+    );
+
+    assert!(check("(var x 1) (print $x)").is_empty());
+  }
+
+  #[test]
+  fn test_analyze() {
+    let analysis = analyze("(var x 1)\n(print $x)");
+
+    assert_eq!(analysis.tokens.len(), 9);
+    assert_eq!(analysis.tokens[1].0, TokenKind::Ident("var"));
+    assert_eq!(analysis.tokens[1].1.begin().column, 2);
+    assert!(analysis.ast.is_some());
+    assert!(analysis.diagnostics.is_empty());
+
+    let analysis = analyze("(var x 1)\n(print $y @)");
+
+    assert_eq!(analysis.tokens.last().unwrap().0, TokenKind::Error);
+    assert!(analysis.ast.is_some());
+    assert_eq!(analysis.diagnostics.len(), 1);
+
+    let analysis = analyze("(@)");
+
+    assert!(analysis.ast.is_none());
+    assert!(!analysis.diagnostics.is_empty());
+  }
+}
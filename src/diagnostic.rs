@@ -0,0 +1,67 @@
+//! Errors of every stage in a single shape for rendering.
+
+use crate::types::Span;
+
+/// How serious a diagnostic is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+  Error,
+  Warning,
+}
+
+/// A problem found in a source and where it is.
+#[derive(Debug, Clone)]
+pub struct Diagnostic<'buf> {
+  pub severity: Severity,
+  pub message: String,
+  pub span: Span<'buf>,
+  /// Further details shown after the message.
+  pub notes: Vec<String>,
+}
+
+impl<'buf> Diagnostic<'buf> {
+  pub fn error(message: impl Into<String>, span: Span<'buf>) -> Self {
+    Self {
+      severity: Severity::Error,
+      message: message.into(),
+      span,
+      notes: Vec::new(),
+    }
+  }
+
+  pub fn with_note(mut self, note: impl Into<String>) -> Self {
+    self.notes.push(note.into());
+    self
+  }
+}
+
+/// Conversion of a stage's error into a [`Diagnostic`].
+pub trait IntoDiagnostic<'buf> {
+  fn into_diagnostic(self) -> Diagnostic<'buf>;
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{IntoDiagnostic, Severity};
+  use crate::{ast::Parser, lex::Lexer};
+
+  #[test]
+  fn test_into_diagnostic() {
+    let err = Lexer::new("(var x\n  1.2.3)")
+      .find_map(Result::err)
+      .unwrap();
+    let diagnostic = err.into_diagnostic();
+
+    assert_eq!(diagnostic.severity, Severity::Error);
+    assert_eq!(diagnostic.message, "Invalid number");
+    assert_eq!(diagnostic.notes.len(), 1);
+    assert_eq!(format!("{:?}", diagnostic.span), "line 2, column 3");
+
+    let err = Parser::new("(var x 1)\n(if)").parse().unwrap_err();
+    let diagnostic = err.into_diagnostic();
+
+    assert_eq!(diagnostic.severity, Severity::Error);
+    assert_eq!(diagnostic.message, "Expected if condition");
+    assert_eq!(format!("{:?}", diagnostic.span), "line 2, column 2");
+  }
+}
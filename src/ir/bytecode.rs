@@ -0,0 +1,491 @@
+//! Binary serialization of instructions.
+//!
+//! Serialized bytecode starts with [`MAGIC`] followed by the little-endian [`VERSION`] it was
+//! written with, then the `u32` count of constants and the constants, each a tag byte followed by
+//! its value.  Each instruction is an opcode byte followed by its operands, integers are
+//! little-endian and strings are prefixed by their `u32` length.
+//!
+//! Source locations, local names and warnings aren't serialized.
+
+use super::{
+  error::{BytecodeError, BytecodeResult},
+  instr::{Constant, Instruction, Label},
+  scope::Local,
+  CompiledProgram,
+};
+use crate::util::uuid::Uuid;
+use std::{borrow::Cow, convert::TryInto};
+
+pub const MAGIC: &[u8; 4] = b"CHAL";
+
+/// Version of the instruction set, bump whenever instructions or their encoding change.
+pub const VERSION: u16 = 13;
+
+pub fn serialize(program: &CompiledProgram<'_>) -> Vec<u8> {
+  let mut writer = Writer(Vec::new());
+
+  writer.0.extend_from_slice(MAGIC);
+  writer.0.extend_from_slice(&VERSION.to_le_bytes());
+  writer.u32(program.consts.len() as u32);
+
+  for constant in &program.consts {
+    writer.constant(constant);
+  }
+
+  for instruction in &program.code {
+    writer.instruction(instruction);
+  }
+
+  writer.0
+}
+
+/// Deserialize a program borrowing its strings from `bytes`.
+pub fn deserialize(bytes: &[u8]) -> BytecodeResult<CompiledProgram<'_>> {
+  let mut reader = Reader(bytes);
+
+  if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+    return Err(BytecodeError::BadMagic);
+  }
+
+  match reader.u16()? {
+    VERSION => {}
+    version => return Err(BytecodeError::UnsupportedVersion(version)),
+  }
+
+  let mut program = CompiledProgram::default();
+
+  for _ in 0..reader.u32()? {
+    program.consts.push(reader.constant()?);
+  }
+
+  while !reader.0.is_empty() {
+    program.code.push(reader.instruction()?);
+  }
+
+  Ok(program)
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn op(&mut self, op: u8) {
+    self.0.push(op);
+  }
+
+  fn u32(&mut self, value: u32) {
+    self.0.extend_from_slice(&value.to_le_bytes());
+  }
+
+  fn usize(&mut self, value: usize) {
+    self.0.extend_from_slice(&(value as u64).to_le_bytes());
+  }
+
+  fn uuid(&mut self, value: Uuid) {
+    self.0.extend_from_slice(&value.as_u128().to_le_bytes());
+  }
+
+  fn str(&mut self, value: &str) {
+    self.u32(value.len() as u32);
+    self.0.extend_from_slice(value.as_bytes());
+  }
+
+  fn constant(&mut self, constant: &Constant<'_>) {
+    match constant {
+      Constant::Number(value) => {
+        self.op(0);
+        self.0.extend_from_slice(&value.to_le_bytes());
+      }
+      Constant::String(value) => {
+        self.op(1);
+        self.str(value);
+      }
+    }
+  }
+
+  fn label(&mut self, op: u8, label: Label) {
+    self.op(op);
+    self.uuid(label.into_inner());
+  }
+
+  fn local(&mut self, op: u8, local: Local) {
+    self.op(op);
+    self.uuid(local.into_inner());
+  }
+
+  fn instruction(&mut self, instruction: &Instruction<'_>) {
+    match instruction {
+      Instruction::Nop => self.op(0),
+
+      Instruction::LdNull => self.op(1),
+      Instruction::LdTrue => self.op(2),
+      Instruction::LdFalse => self.op(3),
+      Instruction::LdStr(value) => {
+        self.op(4);
+        self.str(value);
+      }
+      Instruction::LdF64(value) => {
+        self.op(5);
+        self.0.extend_from_slice(&value.to_le_bytes());
+      }
+      Instruction::LdLoc(local) => self.local(6, *local),
+      Instruction::LdAddr(addr) => {
+        self.op(7);
+        self.usize(*addr);
+      }
+      Instruction::LdFn(label, arity) => {
+        self.label(8, *label);
+        self.usize(*arity);
+      }
+      Instruction::MkClosure(label, arity, captured) => {
+        self.label(9, *label);
+        self.usize(*arity);
+        self.usize(*captured);
+      }
+      Instruction::LdImport(name) => {
+        self.op(10);
+        self.str(name);
+      }
+
+      Instruction::StLoc(local) => self.local(11, *local),
+      Instruction::Pop => self.op(12),
+
+      Instruction::Label(label) => self.label(13, *label),
+
+      Instruction::Jmp(label) => self.label(14, *label),
+      Instruction::JmpIf(label) => self.label(15, *label),
+      Instruction::JmpEq(label) => self.label(16, *label),
+      Instruction::JmpNEq(label) => self.label(17, *label),
+      Instruction::JmpLt(label) => self.label(18, *label),
+      Instruction::JmpGt(label) => self.label(19, *label),
+      Instruction::JmpLtEq(label) => self.label(20, *label),
+      Instruction::JmpGtEq(label) => self.label(21, *label),
+
+      Instruction::Call(label, argc) => {
+        self.label(22, *label);
+        self.usize(*argc);
+      }
+      Instruction::CallF(name, argc) => {
+        self.op(23);
+        self.str(name);
+        self.usize(*argc);
+      }
+      Instruction::CallDyn(argc) => {
+        self.op(24);
+        self.usize(*argc);
+      }
+      Instruction::Ret => self.op(25),
+
+      Instruction::Add => self.op(26),
+      Instruction::Sub => self.op(27),
+      Instruction::Mul => self.op(28),
+      Instruction::Div => self.op(29),
+      Instruction::Mod => self.op(30),
+      Instruction::Pow => self.op(31),
+
+      Instruction::Eq => self.op(32),
+      Instruction::NEq => self.op(33),
+      Instruction::Lt => self.op(34),
+      Instruction::Gt => self.op(35),
+      Instruction::LtEq => self.op(36),
+      Instruction::GtEq => self.op(37),
+
+      Instruction::Not => self.op(38),
+
+      Instruction::BOr => self.op(39),
+      Instruction::BNot => self.op(40),
+      Instruction::BAnd => self.op(41),
+      Instruction::LShift => self.op(42),
+      Instruction::RShift => self.op(43),
+
+      Instruction::Swap => self.op(44),
+      Instruction::JmpIfNot(label) => self.label(46, *label),
+      Instruction::Halt => self.op(47),
+      Instruction::Neg => self.op(48),
+      Instruction::LdGlobal(name) => {
+        self.op(49);
+        self.str(name);
+      }
+      Instruction::StGlobal(name) => {
+        self.op(50);
+        self.str(name);
+      }
+      Instruction::Tailcall(label, argc) => {
+        self.label(51, *label);
+        self.usize(*argc);
+      }
+      Instruction::Dup => self.op(53),
+      Instruction::Over => self.op(54),
+      Instruction::MkArray(len) => {
+        self.op(55);
+        self.usize(*len);
+      }
+      Instruction::LdArg(index) => {
+        self.op(56);
+        self.0.push(*index);
+      }
+      Instruction::StArg(index) => {
+        self.op(57);
+        self.0.push(*index);
+      }
+      Instruction::CallBuiltin(index, argc) => {
+        self.op(58);
+        self.0.extend_from_slice(&index.to_le_bytes());
+        self.usize(*argc);
+      }
+      Instruction::LdBuiltin(index) => {
+        self.op(52);
+        self.0.extend_from_slice(&index.to_le_bytes());
+      }
+      Instruction::LdConst(index) => {
+        self.op(45);
+        self.0.extend_from_slice(&index.to_le_bytes());
+      }
+    }
+  }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+  fn take(&mut self, len: usize) -> BytecodeResult<&'a [u8]> {
+    if self.0.len() < len {
+      return Err(BytecodeError::UnexpectedEof);
+    }
+
+    let (bytes, rest) = self.0.split_at(len);
+    self.0 = rest;
+
+    Ok(bytes)
+  }
+
+  fn array<const N: usize>(&mut self) -> BytecodeResult<[u8; N]> {
+    Ok(self.take(N)?.try_into().expect("Exactly N bytes"))
+  }
+
+  fn u8(&mut self) -> BytecodeResult<u8> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn u16(&mut self) -> BytecodeResult<u16> {
+    Ok(u16::from_le_bytes(self.array()?))
+  }
+
+  fn u32(&mut self) -> BytecodeResult<u32> {
+    Ok(u32::from_le_bytes(self.array()?))
+  }
+
+  fn usize(&mut self) -> BytecodeResult<usize> {
+    Ok(u64::from_le_bytes(self.array()?) as usize)
+  }
+
+  fn f64(&mut self) -> BytecodeResult<f64> {
+    Ok(f64::from_le_bytes(self.array()?))
+  }
+
+  fn uuid(&mut self) -> BytecodeResult<Uuid> {
+    Ok(Uuid::from_u128(u128::from_le_bytes(self.array()?)))
+  }
+
+  fn label(&mut self) -> BytecodeResult<Label> {
+    Ok(Label::new(self.uuid()?))
+  }
+
+  fn local(&mut self) -> BytecodeResult<Local> {
+    Ok(Local::new(self.uuid()?))
+  }
+
+  fn str(&mut self) -> BytecodeResult<&'a str> {
+    let len = self.u32()? as usize;
+
+    std::str::from_utf8(self.take(len)?).map_err(|_| BytecodeError::BadString)
+  }
+
+  fn constant(&mut self) -> BytecodeResult<Constant<'a>> {
+    Ok(match self.take(1)?[0] {
+      0 => Constant::Number(self.f64()?),
+      1 => Constant::String(Cow::Borrowed(self.str()?)),
+      tag => return Err(BytecodeError::BadConstant(tag)),
+    })
+  }
+
+  fn instruction(&mut self) -> BytecodeResult<Instruction<'a>> {
+    Ok(match self.take(1)?[0] {
+      0 => Instruction::Nop,
+
+      1 => Instruction::LdNull,
+      2 => Instruction::LdTrue,
+      3 => Instruction::LdFalse,
+      4 => Instruction::LdStr(Cow::Borrowed(self.str()?)),
+      5 => Instruction::LdF64(self.f64()?),
+      6 => Instruction::LdLoc(self.local()?),
+      7 => Instruction::LdAddr(self.usize()?),
+      8 => Instruction::LdFn(self.label()?, self.usize()?),
+      9 => Instruction::MkClosure(self.label()?, self.usize()?, self.usize()?),
+      10 => Instruction::LdImport(self.str()?),
+
+      11 => Instruction::StLoc(self.local()?),
+      12 => Instruction::Pop,
+
+      13 => Instruction::Label(self.label()?),
+
+      14 => Instruction::Jmp(self.label()?),
+      15 => Instruction::JmpIf(self.label()?),
+      16 => Instruction::JmpEq(self.label()?),
+      17 => Instruction::JmpNEq(self.label()?),
+      18 => Instruction::JmpLt(self.label()?),
+      19 => Instruction::JmpGt(self.label()?),
+      20 => Instruction::JmpLtEq(self.label()?),
+      21 => Instruction::JmpGtEq(self.label()?),
+
+      22 => Instruction::Call(self.label()?, self.usize()?),
+      23 => Instruction::CallF(self.str()?, self.usize()?),
+      24 => Instruction::CallDyn(self.usize()?),
+      25 => Instruction::Ret,
+
+      26 => Instruction::Add,
+      27 => Instruction::Sub,
+      28 => Instruction::Mul,
+      29 => Instruction::Div,
+      30 => Instruction::Mod,
+      31 => Instruction::Pow,
+
+      32 => Instruction::Eq,
+      33 => Instruction::NEq,
+      34 => Instruction::Lt,
+      35 => Instruction::Gt,
+      36 => Instruction::LtEq,
+      37 => Instruction::GtEq,
+
+      38 => Instruction::Not,
+
+      39 => Instruction::BOr,
+      40 => Instruction::BNot,
+      41 => Instruction::BAnd,
+      42 => Instruction::LShift,
+      43 => Instruction::RShift,
+
+      44 => Instruction::Swap,
+      45 => Instruction::LdConst(self.u16()?),
+      46 => Instruction::JmpIfNot(self.label()?),
+      47 => Instruction::Halt,
+      48 => Instruction::Neg,
+      49 => Instruction::LdGlobal(self.str()?),
+      50 => Instruction::StGlobal(self.str()?),
+      51 => Instruction::Tailcall(self.label()?, self.usize()?),
+      52 => Instruction::LdBuiltin(self.u16()?),
+      53 => Instruction::Dup,
+      54 => Instruction::Over,
+      55 => Instruction::MkArray(self.usize()?),
+      56 => Instruction::LdArg(self.u8()?),
+      57 => Instruction::StArg(self.u8()?),
+      58 => Instruction::CallBuiltin(self.u16()?, self.usize()?),
+
+      op => return Err(BytecodeError::BadOpcode(op)),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{deserialize, serialize, VERSION};
+  use crate::ir::{
+    compile,
+    error::BytecodeError,
+    instr::{Constant, Instruction, Label},
+    scope::Local,
+    CompiledProgram,
+  };
+  use std::borrow::Cow;
+
+  fn program(code: Vec<Instruction<'_>>) -> CompiledProgram<'_> {
+    CompiledProgram {
+      code,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn test_round_trip() {
+    let label = Label::default();
+    let program = CompiledProgram {
+      code: vec![
+        Instruction::Nop,
+        Instruction::LdConst(1),
+        Instruction::LdStr(Cow::Borrowed("value")),
+        Instruction::LdF64(1.5),
+        Instruction::LdLoc(Local::default()),
+        Instruction::MkClosure(label, 2, 1),
+        Instruction::Label(label),
+        Instruction::JmpGtEq(label),
+        Instruction::CallF("print", 1),
+        Instruction::RShift,
+        Instruction::Halt,
+        Instruction::Neg,
+        Instruction::LdGlobal("x"),
+        Instruction::StGlobal("x"),
+        Instruction::Tailcall(label, 2),
+        Instruction::LdBuiltin(7),
+        Instruction::Dup,
+        Instruction::Over,
+        Instruction::MkArray(3),
+        Instruction::LdArg(1),
+        Instruction::StArg(0),
+        Instruction::CallBuiltin(7, 2),
+      ],
+      consts: vec![
+        Constant::Number(2.5),
+        Constant::String(Cow::Borrowed("const")),
+      ],
+      ..Default::default()
+    };
+
+    let bytes = serialize(&program);
+    let result = deserialize(&bytes).unwrap();
+
+    assert_eq!(result.code, program.code);
+    assert_eq!(result.consts, program.consts);
+
+    let program = compile("(fun f (n) (if (< n 1) 0 (f (- n 1)))) (f 3)").unwrap();
+    let bytes = serialize(&program);
+    let result = deserialize(&bytes).unwrap();
+
+    assert_eq!(result.code, program.code);
+    assert_eq!(result.consts, program.consts);
+  }
+
+  #[test]
+  fn test_bad_header() {
+    let mut bytes = serialize(&program(vec![Instruction::Nop]));
+    bytes[0] = b'X';
+
+    assert_eq!(deserialize(&bytes).unwrap_err(), BytecodeError::BadMagic);
+    assert_eq!(deserialize(b"CH").unwrap_err(), BytecodeError::BadMagic);
+
+    let mut bytes = serialize(&program(vec![Instruction::Nop]));
+    bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+
+    assert_eq!(
+      deserialize(&bytes).unwrap_err(),
+      BytecodeError::UnsupportedVersion(VERSION + 1)
+    );
+
+    let mut bytes = serialize(&program(vec![Instruction::LdF64(1.0)]));
+    bytes.pop();
+
+    assert_eq!(
+      deserialize(&bytes).unwrap_err(),
+      BytecodeError::UnexpectedEof
+    );
+
+    let mut bytes = serialize(&CompiledProgram {
+      consts: vec![Constant::Number(1.0)],
+      ..Default::default()
+    });
+    bytes[10] = 7;
+
+    assert_eq!(
+      deserialize(&bytes).unwrap_err(),
+      BytecodeError::BadConstant(7)
+    );
+  }
+}
@@ -0,0 +1,131 @@
+use super::error::{HirError, HirResult};
+use crate::ast::{
+  Assign, Call, CompoundAssign, Const, Define, Expr, Param, RefParam, RefVar, Visitor,
+};
+
+/// Names referenced by a function body but not bound by its parameters or defines.
+///
+/// Variables and parameters live in separate namespaces so they're collected separately.
+/// Names are in order of first reference.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FreeVars<'buf> {
+  pub vars: Vec<&'buf str>,
+  pub params: Vec<&'buf str>,
+}
+
+pub fn get_free_vars<'buf>(bound: &[Param<'buf>], body: &Expr<'buf>) -> HirResult<FreeVars<'buf>> {
+  let mut free = Captures::default();
+
+  free.visit(body)?;
+
+  let Captures { refs, defines } = free;
+  let FreeVars { vars, params } = refs;
+
+  Ok(FreeVars {
+    vars: vars
+      .into_iter()
+      .filter(|name| !defines.contains(name))
+      .collect(),
+    params: params
+      .into_iter()
+      .filter(|name| !bound.iter().any(|param| param.name == *name))
+      .collect(),
+  })
+}
+
+#[derive(Default)]
+struct Captures<'buf> {
+  refs: FreeVars<'buf>,
+  defines: Vec<&'buf str>,
+}
+
+impl<'buf> Captures<'buf> {
+  fn push_var(&mut self, name: &'buf str) {
+    if !self.refs.vars.contains(&name) {
+      self.refs.vars.push(name);
+    }
+  }
+
+  fn push_param(&mut self, name: &'buf str) {
+    if !self.refs.params.contains(&name) {
+      self.refs.params.push(name);
+    }
+  }
+}
+
+impl<'buf> Visitor<'buf> for Captures<'buf> {
+  type Error = HirError;
+
+  fn visit_var(&mut self, expr: &RefVar<'buf>) -> Result<(), Self::Error> {
+    self.push_var(expr.0);
+
+    Ok(())
+  }
+
+  fn visit_param(&mut self, expr: &RefParam<'buf>) -> Result<(), Self::Error> {
+    self.push_param(expr.0);
+
+    Ok(())
+  }
+
+  fn visit_call(&mut self, expr: &Call<'buf>) -> Result<(), Self::Error> {
+    // A call may go through a variable or parameter holding a callable
+    self.push_var(expr.name);
+    self.push_param(expr.name);
+
+    if let Some(args) = &expr.args {
+      self.visit(args)?;
+    }
+
+    Ok(())
+  }
+
+  fn visit_assign(&mut self, expr: &Assign<'buf>) -> Result<(), Self::Error> {
+    // Either a variable or a parameter may be assigned
+    self.push_var(expr.ident);
+    self.push_param(expr.ident);
+    self.visit(&expr.expr)
+  }
+
+  fn visit_compound_assign(&mut self, expr: &CompoundAssign<'buf>) -> Result<(), Self::Error> {
+    self.push_var(expr.ident);
+    self.push_param(expr.ident);
+    self.visit(&expr.expr)
+  }
+
+  fn visit_const(&mut self, expr: &Const<'buf>) -> Result<(), Self::Error> {
+    self.defines.push(expr.ident);
+    self.visit(&expr.expr)
+  }
+
+  fn visit_define(&mut self, expr: &Define<'buf>) -> Result<(), Self::Error> {
+    self.defines.push(expr.ident);
+    self.visit(&expr.expr)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{get_free_vars, FreeVars};
+  use crate::ast::{Expr, Parser};
+
+  #[test]
+  fn test_free_vars() {
+    let expr = Parser::new("(fun (x) ((var y $z) (+ x (* n $y))))")
+      .parse()
+      .unwrap();
+
+    let lambda = match expr {
+      Expr::Lambda(lambda) => lambda,
+      _ => panic!("Expected `Expr::Lambda(..)`"),
+    };
+
+    assert_eq!(
+      get_free_vars(&lambda.params, &lambda.body).unwrap(),
+      FreeVars {
+        vars: vec!["z"],
+        params: vec!["n"],
+      }
+    );
+  }
+}
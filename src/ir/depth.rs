@@ -0,0 +1,442 @@
+//! Stack usage of lowered expressions.
+
+use super::{captures::get_free_vars, literal_truthiness};
+use crate::ast::{
+  Assign, BinaryOp, BinaryOperator, BoolLit, Call, Compound, CompoundAssign, Cond, Const, Define,
+  Expr, Function, If, Lambda, Noop, NumberLit, Param, Quote, RefParam, RefVar, Return, StringLit,
+  UnaryOperator, Visitor,
+};
+use std::{
+  collections::{HashMap, HashSet},
+  convert::Infallible,
+};
+
+/// Get the most values the lowering of `expr` holds on the stack at once, or `None` if a call
+/// may recurse so the depth has no bound.
+///
+/// Function bodies are measured once, starting from their arguments, and added to the caller's
+/// depth at each call.  A call through a value may reach any lambda or function referenced by
+/// name, so it adds the deepest of those.  Since locals aren't resolved, every call counts a slot
+/// for a callable loaded from a local and every function captures each name it doesn't bind, so
+/// the depth may be more than the lowering needs but never less.
+pub fn max_stack_depth(expr: &Expr<'_>) -> Option<usize> {
+  let mut program = Program::default();
+
+  program.visit(expr).ok();
+  program.measure(expr, &mut HashMap::new())
+}
+
+/// Something called, either a function by name or whatever a value holds.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Callee<'buf> {
+  Named(&'buf str),
+  Value,
+}
+
+/// Depth of each callee measured so far, `None` while its body is being measured.
+type Measured<'buf> = HashMap<Callee<'buf>, Option<Option<usize>>>;
+
+/// The functions and lambdas of a program, anywhere they're defined.
+#[derive(Default)]
+struct Program<'buf> {
+  /// Functions by name, more than one where scopes reuse a name.
+  fns: HashMap<&'buf str, Vec<Function<'buf>>>,
+  lambdas: Vec<Lambda<'buf>>,
+  /// Names referenced as values, which may be functions.
+  refs: HashSet<&'buf str>,
+}
+
+impl<'buf> Program<'buf> {
+  /// Get the most values `expr` holds on the stack, including what it calls.
+  fn measure(&self, expr: &Expr<'buf>, measured: &mut Measured<'buf>) -> Option<usize> {
+    let mut depth = Depth {
+      program: self,
+      measured,
+      depth: 0,
+      max: 0,
+      unbounded: false,
+    };
+
+    depth.visit(expr).ok();
+
+    if depth.unbounded {
+      None
+    } else {
+      Some(depth.max)
+    }
+  }
+
+  /// Get the most values a call holds on the stack, from its first argument.  A callee reached
+  /// again while its body is being measured recurses, so has no bound.
+  fn callee_depth(&self, callee: Callee<'buf>, measured: &mut Measured<'buf>) -> Option<usize> {
+    match measured.get(&callee) {
+      Some(depth) => return depth.flatten(),
+      None => measured.insert(callee, None),
+    };
+
+    let mut bodies = Vec::new();
+
+    match callee {
+      Callee::Named(name) => bodies.extend(self.fns[name].iter().map(|f| (&f.params, &f.body))),
+      Callee::Value => {
+        let fns = self
+          .refs
+          .iter()
+          .filter_map(|name| self.fns.get(name))
+          .flatten();
+
+        bodies.extend(fns.map(|f| (&f.params, &f.body)));
+        bodies.extend(self.lambdas.iter().map(|f| (&f.params, &f.body)));
+      }
+    }
+
+    let depth = bodies.into_iter().try_fold(0, |max, (params, body)| {
+      Some(max.max(self.body_depth(params, body, measured)?))
+    });
+
+    measured.insert(callee, Some(depth));
+    depth
+  }
+
+  /// Get the most values a function body holds on the stack, including its arguments and
+  /// captures which stay on the stack for the whole call.
+  fn body_depth(
+    &self,
+    params: &[Param<'buf>],
+    body: &Expr<'buf>,
+    measured: &mut Measured<'buf>,
+  ) -> Option<usize> {
+    let captured = get_free_vars(params, body)
+      .map(|free| free.vars.len() + free.params.len())
+      .unwrap_or_default();
+
+    Some(params.len() + captured + self.measure(body, measured)?)
+  }
+}
+
+impl<'buf> Visitor<'buf> for Program<'buf> {
+  type Error = Infallible;
+
+  fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
+    self.fns.entry(expr.name).or_default().push(expr.clone());
+    self.visit(&expr.body)
+  }
+
+  fn visit_lambda(&mut self, expr: &Lambda<'buf>) -> Result<(), Self::Error> {
+    self.lambdas.push(expr.clone());
+    self.visit(&expr.body)
+  }
+
+  fn visit_param(&mut self, expr: &RefParam<'buf>) -> Result<(), Self::Error> {
+    self.refs.insert(expr.0);
+
+    Ok(())
+  }
+}
+
+struct Depth<'a, 'buf> {
+  program: &'a Program<'buf>,
+  measured: &'a mut Measured<'buf>,
+  /// Values on the stack.
+  depth: usize,
+  /// Most values on the stack so far.
+  max: usize,
+  /// Whether a call may recurse.
+  unbounded: bool,
+}
+
+impl<'buf> Depth<'_, 'buf> {
+  fn push(&mut self, count: usize) {
+    self.depth += count;
+    self.max = self.max.max(self.depth);
+  }
+
+  fn pop(&mut self, count: usize) {
+    self.depth -= count;
+  }
+
+  /// Visit an expression and pop the value it leaves, if any.
+  fn visit_discarded(&mut self, expr: &Expr<'buf>) {
+    let depth = self.depth;
+
+    self.visit(expr).ok();
+    self.depth = depth;
+  }
+
+  /// Visit an expression which leaves a single value, like branches of an `if`.
+  fn visit_branch(&mut self, expr: Option<&Expr<'buf>>) {
+    let depth = self.depth;
+
+    if let Some(expr) = expr {
+      self.visit(expr).ok();
+    }
+
+    self.depth = depth;
+    self.push(1);
+    self.pop(1);
+  }
+
+  /// Visit quoted data, whose lists hold their items until they're built into an array.
+  fn visit_datum(&mut self, expr: &Expr<'buf>) {
+    let depth = self.depth;
+
+    if let Expr::Compound(items) = expr {
+      for item in &items.0 {
+        self.visit_datum(item);
+      }
+    }
+
+    self.depth = depth;
+    self.push(1);
+  }
+
+  /// Visit a condition followed by the jump popping its operands.
+  fn visit_condition(&mut self, expr: &Expr<'buf>) {
+    let depth = self.depth;
+
+    match expr {
+      Expr::BinaryOp(binary) if matches!(binary.op, BinaryOperator::Eq | BinaryOperator::Lt) => {
+        self.visit(&binary.lhs).ok();
+        self.visit(&binary.rhs).ok();
+      }
+      Expr::UnaryOp(unary) if unary.op == UnaryOperator::Not => {
+        self.visit(&unary.expr).ok();
+      }
+      expr => {
+        self.visit(expr).ok();
+      }
+    }
+
+    self.depth = depth;
+  }
+}
+
+impl<'buf> Visitor<'buf> for Depth<'_, 'buf> {
+  type Error = Infallible;
+
+  fn visit_compound(&mut self, expr: &Compound<'buf>) -> Result<(), Self::Error> {
+    if let Some((last, rest)) = expr.0.split_last() {
+      for expr in rest {
+        self.visit_discarded(expr);
+
+        if let Expr::Return(_) = expr {
+          return Ok(());
+        }
+      }
+
+      self.visit(last)?;
+    }
+
+    Ok(())
+  }
+
+  fn visit_noop(&mut self, _: &Noop) -> Result<(), Self::Error> {
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_string(&mut self, _: &StringLit<'buf>) -> Result<(), Self::Error> {
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_number(&mut self, _: &NumberLit) -> Result<(), Self::Error> {
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_bool(&mut self, _: &BoolLit) -> Result<(), Self::Error> {
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_quote(&mut self, expr: &Quote<'buf>) -> Result<(), Self::Error> {
+    self.visit_datum(&expr.0);
+
+    Ok(())
+  }
+
+  fn visit_if(&mut self, expr: &If<'buf>) -> Result<(), Self::Error> {
+    match literal_truthiness(&expr.condition) {
+      Some(true) => self.visit_branch(Some(&expr.body)),
+      Some(false) => self.visit_branch(expr.fallthrough.as_ref()),
+      None => {
+        self.visit_condition(&expr.condition);
+        self.visit_branch(expr.fallthrough.as_ref());
+        self.visit_branch(Some(&expr.body));
+      }
+    }
+
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_cond(&mut self, expr: &Cond<'buf>) -> Result<(), Self::Error> {
+    for (condition, _) in &expr.arms {
+      self.visit_condition(condition);
+    }
+
+    self.visit_branch(expr.default.as_ref());
+
+    for (_, body) in &expr.arms {
+      self.visit_branch(Some(body));
+    }
+
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_call(&mut self, expr: &Call<'buf>) -> Result<(), Self::Error> {
+    let depth = self.depth;
+
+    // Every argument is kept on the stack along with a callable loaded from a local
+    if let Some(args) = &expr.args {
+      match args {
+        Expr::Compound(args) => {
+          for arg in &args.0 {
+            self.visit(arg)?;
+          }
+        }
+        arg => self.visit(arg)?,
+      }
+    }
+
+    self.push(1);
+
+    // A function's arguments are measured with its body, but a value may be a builtin which
+    // calls back with its own arguments above these
+    let (callee, base) = if self.program.fns.contains_key(expr.name) {
+      (Callee::Named(expr.name), depth)
+    } else {
+      (Callee::Value, self.depth)
+    };
+
+    match self.program.callee_depth(callee, self.measured) {
+      Some(callee) => {
+        self.depth = base;
+        self.push(callee);
+      }
+      None => self.unbounded = true,
+    }
+
+    self.depth = depth;
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_assign(&mut self, expr: &Assign<'buf>) -> Result<(), Self::Error> {
+    self.visit_discarded(&expr.expr);
+
+    Ok(())
+  }
+
+  fn visit_compound_assign(&mut self, expr: &CompoundAssign<'buf>) -> Result<(), Self::Error> {
+    let depth = self.depth;
+
+    self.visit(&expr.expr)?;
+    self.push(1);
+    self.depth = depth;
+
+    Ok(())
+  }
+
+  fn visit_const(&mut self, expr: &Const<'buf>) -> Result<(), Self::Error> {
+    self.visit_discarded(&expr.expr);
+
+    Ok(())
+  }
+
+  fn visit_define(&mut self, expr: &Define<'buf>) -> Result<(), Self::Error> {
+    self.visit_discarded(&expr.expr);
+
+    Ok(())
+  }
+
+  /// Bodies are measured where they're called.
+  fn visit_function(&mut self, _: &Function<'buf>) -> Result<(), Self::Error> {
+    Ok(())
+  }
+
+  fn visit_lambda(&mut self, expr: &Lambda<'buf>) -> Result<(), Self::Error> {
+    // At most every free name is captured, the closure leaves a single value
+    let captured = get_free_vars(&expr.params, &expr.body)
+      .map(|free| free.vars.len() + free.params.len())
+      .unwrap_or_default();
+
+    self.push(captured.max(1));
+    self.pop(captured.max(1));
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_return(&mut self, expr: &Return<'buf>) -> Result<(), Self::Error> {
+    self.visit_discarded(&expr.0);
+
+    Ok(())
+  }
+
+  fn visit_binary(&mut self, expr: &BinaryOp<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.lhs)?;
+    self.visit(&expr.rhs)?;
+    self.pop(1);
+
+    Ok(())
+  }
+
+  fn visit_var(&mut self, _: &RefVar<'buf>) -> Result<(), Self::Error> {
+    self.push(1);
+
+    Ok(())
+  }
+
+  fn visit_param(&mut self, _: &RefParam<'buf>) -> Result<(), Self::Error> {
+    self.push(1);
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::max_stack_depth;
+  use crate::ast::Parser;
+
+  #[test]
+  fn test_max_stack_depth() {
+    let depth = |script| max_stack_depth(&Parser::new(script).parse().unwrap()).unwrap();
+
+    assert_eq!(depth("1"), 1);
+    assert_eq!(depth("(var x 1) (+ $x 2)"), 2);
+    assert_eq!(depth("(+ 1 (* 2 (- 3 4)))"), 4);
+    assert_eq!(depth("(if (< 1 2) (+ 1 (+ 2 3)) 4)"), 3);
+    assert_eq!(depth("(print 1 (+ 2 3) 4)"), 4);
+    assert_eq!(depth("(fun f (a b c) (+ a b)) (f 1 2 3)"), 5);
+    assert_eq!(depth("(quote (1 (2 3) 4))"), 3);
+    assert_eq!(
+      depth("(fun f (a) (+ a (+ a (+ a a)))) (var v 1) (+ 1 (+ 2 (+ 3 (f $v))))"),
+      8
+    );
+    assert_eq!(
+      depth("(fun g (a) (+ 1 (f a))) (fun f (a) (+ a 1)) (g 1)"),
+      7
+    );
+    assert_eq!(depth("(var f (fun (a) (+ a (+ a a)))) (+ 1 (f 2))"), 7);
+  }
+
+  #[test]
+  fn test_max_stack_depth_recursive() {
+    let depth = |script| max_stack_depth(&Parser::new(script).parse().unwrap());
+
+    assert_eq!(depth("(fun f (n) (if (< n 1) 0 (f (- n 1)))) (f 3)"), None);
+    assert_eq!(depth("(fun f (n) (g n)) (fun g (n) (f n)) (f 1)"), None);
+    assert_eq!(depth("(fun f (n) (f n))"), Some(0));
+  }
+}
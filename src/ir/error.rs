@@ -0,0 +1,160 @@
+use super::typecheck::Type;
+use crate::{
+  ast::BinaryOperator,
+  diagnostic::{Diagnostic, IntoDiagnostic},
+  types::Span,
+};
+use std::{error::Error, fmt::Display, io, path::PathBuf};
+
+pub type HirResult<T> = Result<T, HirError>;
+pub type ModuleResult<T> = Result<T, ModuleError>;
+
+#[derive(Debug, Clone)]
+pub enum HirError {
+  /// A function name is defined by more than one module.
+  DuplicateSymbol(String),
+  /// A variable or parameter defined more than once in the same scope.
+  DuplicateVariable(String),
+  /// A constant was reassigned.
+  AssignToConst { name: String },
+  /// Assigned a name which isn't a variable or parameter in scope, or referenced one which isn't
+  /// a variable, parameter or function.
+  UndefinedVariable(String),
+  /// An import that wasn't loaded alongside the program.
+  UnresolvedImport(String),
+  /// A `return` outside of a function body.
+  ReturnOutsideFunction,
+  /// A `return` in an operand, argument or condition, where it would leave the values around
+  /// it on the stack.
+  NestedReturn,
+  /// A function called with a different number of arguments than it has parameters.
+  ArityMismatch {
+    name: String,
+    expected: usize,
+    actual: usize,
+  },
+  /// A function with more parameters, including captured ones, than an argument index holds.
+  TooManyParams(usize),
+}
+
+impl Display for HirError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+impl Error for HirError {}
+
+/// A likely mistake which doesn't stop compilation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HirWarning {
+  /// A variable is defined but never read.
+  UnusedVariable(String),
+  /// An instruction, at this offset, which can never run.
+  UnreachableCode(usize),
+}
+
+impl Display for HirWarning {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      HirWarning::UnusedVariable(name) => write!(f, "Unused variable `{}`", name),
+      HirWarning::UnreachableCode(offset) => write!(f, "Unreachable instruction at {}", offset),
+    }
+  }
+}
+
+/// A variable used where it isn't defined, found by [`resolve`](super::resolve::resolve).
+#[derive(Debug, Clone)]
+pub struct ResolveError<'buf> {
+  pub name: &'buf str,
+  /// Location of the innermost expression using the variable.
+  pub span: Span<'buf>,
+}
+
+impl Display for ResolveError<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Undefined variable `{}` at {:?}", self.name, self.span)
+  }
+}
+
+impl Error for ResolveError<'_> {}
+
+impl<'buf> IntoDiagnostic<'buf> for ResolveError<'buf> {
+  fn into_diagnostic(self) -> Diagnostic<'buf> {
+    Diagnostic::error(format!("Undefined variable `{}`", self.name), self.span)
+  }
+}
+
+/// An operation found to be invalid by [`typecheck`](super::typecheck::typecheck).
+#[derive(Debug, Clone, PartialEq)]
+pub enum TypeError {
+  /// An operand of a unary (`op` of `None`) or binary operator.
+  Operand {
+    op: Option<BinaryOperator>,
+    expected: Type,
+    actual: Type,
+  },
+  /// An argument not matching its parameter's annotation.
+  Argument {
+    function: String,
+    param: String,
+    expected: Type,
+    actual: Type,
+  },
+  /// A value not matching its variable's annotation.
+  Binding {
+    name: String,
+    expected: Type,
+    actual: Type,
+  },
+}
+
+impl Display for TypeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+impl Error for TypeError {}
+
+pub type BytecodeResult<T> = Result<T, BytecodeError>;
+
+/// An error which can be returned when deserializing bytecode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+  /// The bytes don't start with [`MAGIC`](super::bytecode::MAGIC).
+  BadMagic,
+  /// Serialized by a different version of the instruction set.
+  UnsupportedVersion(u16),
+  /// An unknown instruction opcode.
+  BadOpcode(u8),
+  /// An unknown constant pool entry tag.
+  BadConstant(u8),
+  /// A string operand isn't valid UTF-8.
+  BadString,
+  UnexpectedEof,
+}
+
+impl Display for BytecodeError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+impl Error for BytecodeError {}
+
+/// An error which can be returned when loading a program and its imports.
+#[derive(Debug)]
+pub enum ModuleError {
+  Io(PathBuf, io::Error),
+  Parse(PathBuf, String),
+  Cycle(PathBuf),
+}
+
+impl Display for ModuleError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", self)
+  }
+}
+
+impl Error for ModuleError {}
@@ -1,8 +1,31 @@
-use super::instr::Label;
+use super::{
+  captures::{get_free_vars, FreeVars},
+  error::{HirError, HirResult},
+  instr::Label,
+};
 use crate::ast::{Expr, Function, Visitor};
 use std::collections::HashMap;
 
-pub fn get_fns(expr: &Expr<'_>) -> Result<HashMap<String, Label>, ()> {
+/// A function known before code generation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FunctionDef {
+  pub label: Label,
+  pub arity: usize,
+}
+
+pub fn get_fns(expr: &Expr<'_>) -> HirResult<HashMap<String, FunctionDef>> {
+  Ok(
+    get_fns_with_free_vars(expr)?
+      .into_iter()
+      .map(|(name, (def, _))| (name, def))
+      .collect(),
+  )
+}
+
+/// Get the functions defined by `expr` along with the names their bodies don't bind.
+pub fn get_fns_with_free_vars<'buf>(
+  expr: &Expr<'buf>,
+) -> HirResult<HashMap<String, (FunctionDef, FreeVars<'buf>)>> {
   let mut fns = Functions(Default::default());
 
   fns.visit(expr)?;
@@ -10,16 +33,22 @@ pub fn get_fns(expr: &Expr<'_>) -> Result<HashMap<String, Label>, ()> {
   Ok(fns.0)
 }
 
-struct Functions(HashMap<String, Label>);
+struct Functions<'buf>(HashMap<String, (FunctionDef, FreeVars<'buf>)>);
 
-impl<'buf> Visitor<'buf> for Functions {
-  type Error = ();
+impl<'buf> Visitor<'buf> for Functions<'buf> {
+  type Error = HirError;
 
+  /// Functions defined in the body are scoped to it so aren't collected.
   fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
-    let label = Label::default();
+    let def = FunctionDef {
+      label: Label::default(),
+      arity: expr.params.len(),
+    };
+
+    let free = get_free_vars(&expr.params, &expr.body)?;
 
-    self.0.insert(expr.name.to_string(), label);
+    self.0.insert(expr.name.to_string(), (def, free));
 
-    self.visit(&expr.body)
+    Ok(())
   }
 }
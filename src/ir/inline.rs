@@ -0,0 +1,230 @@
+//! Compile-time evaluation of calls to pure functions.
+
+use super::literal_truthiness;
+use crate::ast::{
+  Assign, BinaryOperator, BoolLit, Call, CompoundAssign, Const, Define, Expr, Function, Import,
+  Lambda, Noop, NumberLit, Param, RefParam, RefVar, Return, StringLit, UnaryOperator, Visitor,
+};
+use std::collections::HashMap;
+
+/// Get the functions defined by `expr` whose result only depends on their arguments.
+///
+/// Purity is conservative, a pure function only uses literals, its parameters, operators and
+/// conditionals. Calls of any kind, variables, assignments and nested functions are impure.
+pub fn get_pure_fns<'buf>(expr: &Expr<'buf>) -> HashMap<String, Function<'buf>> {
+  let mut fns = PureFns(HashMap::new());
+
+  fns.visit(expr).ok();
+  fns.0
+}
+
+/// Evaluate a call to a pure function with literal arguments.
+///
+/// Returns `None` when an argument isn't a literal or the body uses an operation which can't be
+/// evaluated ahead of time, such as a bitwise operator or one which would fail at runtime.
+pub fn eval_call<'buf>(def: &Function<'buf>, args: &[Expr<'buf>]) -> Option<Expr<'buf>> {
+  if def.params.len() != args.len() || !args.iter().all(is_literal) {
+    return None;
+  }
+
+  let args = def
+    .params
+    .iter()
+    .map(|param| param.name)
+    .zip(args)
+    .collect::<HashMap<_, _>>();
+
+  eval(&def.body, &args)
+}
+
+fn is_literal(expr: &Expr<'_>) -> bool {
+  matches!(
+    expr,
+    Expr::Noop(_) | Expr::Number(_) | Expr::String(_) | Expr::Bool(_)
+  )
+}
+
+fn eval<'buf>(expr: &Expr<'buf>, args: &HashMap<&str, &Expr<'buf>>) -> Option<Expr<'buf>> {
+  match expr {
+    expr if is_literal(expr) => Some(expr.clone()),
+    Expr::RefParam(RefParam(name)) => args.get(name).map(|arg| (*arg).clone()),
+    Expr::If(expr) => match literal_truthiness(&eval(&expr.condition, args)?)? {
+      true => eval(&expr.body, args),
+      false => match &expr.fallthrough {
+        Some(fallthrough) => eval(fallthrough, args),
+        None => Some(Noop.into()),
+      },
+    },
+    Expr::Cond(expr) => {
+      for (condition, body) in &expr.arms {
+        if literal_truthiness(&eval(condition, args)?)? {
+          return eval(body, args);
+        }
+      }
+
+      match &expr.default {
+        Some(default) => eval(default, args),
+        None => Some(Noop.into()),
+      }
+    }
+    // Every expression is evaluated so one which can't be doesn't disappear
+    Expr::Compound(expr) => {
+      let mut values = expr
+        .0
+        .iter()
+        .map(|expr| eval(expr, args))
+        .collect::<Option<Vec<_>>>()?;
+
+      Some(values.pop().unwrap_or_else(|| Noop.into()))
+    }
+    Expr::UnaryOp(expr) => eval_unary(expr.op, eval(&expr.expr, args)?),
+    Expr::BinaryOp(expr) => eval_binary(expr.op, eval(&expr.lhs, args)?, eval(&expr.rhs, args)?),
+    _ => None,
+  }
+}
+
+fn eval_unary<'buf>(op: UnaryOperator, value: Expr<'buf>) -> Option<Expr<'buf>> {
+  match (op, value) {
+    (UnaryOperator::Neg, Expr::Number(NumberLit(value))) => Some(NumberLit(-value).into()),
+    (UnaryOperator::Not, value) => Some(BoolLit(!literal_truthiness(&value)?).into()),
+    _ => None,
+  }
+}
+
+fn eval_binary<'buf>(op: BinaryOperator, lhs: Expr<'buf>, rhs: Expr<'buf>) -> Option<Expr<'buf>> {
+  use BinaryOperator::*;
+
+  let value: Expr = match (lhs, rhs) {
+    (Expr::Number(NumberLit(a)), Expr::Number(NumberLit(b))) => match op {
+      Add => NumberLit(a + b).into(),
+      Sub => NumberLit(a - b).into(),
+      Mul => NumberLit(a * b).into(),
+      Div => NumberLit(a / b).into(),
+      Mod => NumberLit(a % b).into(),
+      Pow => NumberLit(a.powf(b)).into(),
+      Eq => BoolLit(a == b).into(),
+      NEq => BoolLit(a != b).into(),
+      Lt => BoolLit(a < b).into(),
+      LtEq => BoolLit(a <= b).into(),
+      Gt => BoolLit(a > b).into(),
+      GtEq => BoolLit(a >= b).into(),
+      BOr | BAnd | LShift | RShift => return None,
+    },
+    (Expr::String(StringLit(a)), Expr::String(StringLit(b))) => match op {
+      Eq => BoolLit(a == b).into(),
+      NEq => BoolLit(a != b).into(),
+      _ => return None,
+    },
+    (Expr::Bool(BoolLit(a)), Expr::Bool(BoolLit(b))) => match op {
+      Eq => BoolLit(a == b).into(),
+      NEq => BoolLit(a != b).into(),
+      _ => return None,
+    },
+    _ => return None,
+  };
+
+  Some(value)
+}
+
+struct PureFns<'buf>(HashMap<String, Function<'buf>>);
+
+impl<'buf> Visitor<'buf> for PureFns<'buf> {
+  type Error = ();
+
+  /// Functions defined in the body are scoped to it so aren't collected.
+  fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
+    if Purity(&expr.params).visit(&expr.body).is_ok() {
+      self.0.insert(expr.name.to_string(), expr.clone());
+    }
+
+    Ok(())
+  }
+}
+
+/// Fails on the first expression which may depend on or change anything but the parameters.
+struct Purity<'a, 'buf>(&'a [Param<'buf>]);
+
+impl<'buf> Visitor<'buf> for Purity<'_, 'buf> {
+  type Error = ();
+
+  fn visit_call(&mut self, _: &Call<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_assign(&mut self, _: &Assign<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_compound_assign(&mut self, _: &CompoundAssign<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_const(&mut self, _: &Const<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_define(&mut self, _: &Define<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_function(&mut self, _: &Function<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_import(&mut self, _: &Import<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_lambda(&mut self, _: &Lambda<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_return(&mut self, _: &Return<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  fn visit_var(&mut self, _: &RefVar<'buf>) -> Result<(), Self::Error> {
+    Err(())
+  }
+
+  /// A name other than a parameter calls a function or built-in.
+  fn visit_param(&mut self, expr: &RefParam<'buf>) -> Result<(), Self::Error> {
+    match self.0.iter().any(|param| param.name == expr.0) {
+      true => Ok(()),
+      false => Err(()),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{eval_call, get_pure_fns};
+  use crate::ast::{BoolLit, Expr, NumberLit, Parser};
+
+  #[test]
+  fn test_pure_fns() {
+    let expr = Parser::new(
+      "(fun sq (x) (* x x)) \
+       (fun sign (x) (cond ((< x 0) (- 1)) ((equal x 0) 0) (else 1))) \
+       (fun show (x) (print x)) \
+       (fun global (x) (+ x $y)) \
+       (fun set (x) (+= x 1))",
+    )
+    .parse()
+    .unwrap();
+
+    let fns = get_pure_fns(&expr);
+    let mut names = fns.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+
+    assert_eq!(names, vec!["sign", "sq"]);
+
+    let num = |value| Expr::from(NumberLit(value));
+
+    assert_eq!(eval_call(&fns["sq"], &[num(4.0)]), Some(num(16.0)));
+    assert_eq!(eval_call(&fns["sign"], &[num(-3.0)]), Some(num(-1.0)));
+    assert_eq!(eval_call(&fns["sign"], &[num(0.0)]), Some(num(0.0)));
+    assert_eq!(eval_call(&fns["sq"], &[BoolLit(true).into()]), None);
+    assert_eq!(eval_call(&fns["sq"], &[]), None);
+  }
+}
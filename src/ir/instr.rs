@@ -1,28 +1,88 @@
 use super::scope::Local;
 use crate::util::uuid::Uuid;
-use std::borrow::Cow;
+use std::{borrow::Cow, fmt::Display};
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Label(Uuid);
 
-#[derive(Debug, Clone)]
+impl Label {
+  pub fn new(id: Uuid) -> Self {
+    Self(id)
+  }
+
+  pub fn into_inner(self) -> Uuid {
+    self.0
+  }
+}
+
+impl Display for Label {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "L{:x}", self.0.as_u128())
+  }
+}
+
+/// A literal stored once in a program's constant pool.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant<'a> {
+  Number(f64),
+  String(Cow<'a, str>),
+}
+
+/// A VM instruction.
+///
+/// Binary operators and conditional jumps pop their lhs first then their rhs, so the lhs must be
+/// on top of the stack. Operands are evaluated lhs first and put in that order with [`Swap`].
+///
+/// [`Swap`]: Instruction::Swap
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction<'a> {
   Nop,
 
   LdNull,
   LdTrue,
   LdFalse,
+  /// Load an entry of the constant pool.
+  LdConst(u16),
+  /// Load an inline string, used once the constant pool is full.
   LdStr(Cow<'a, str>),
+  /// Load an inline number, used once the constant pool is full.
   LdF64(f64),
   LdLoc(Local),
+  /// Load an argument of the current call by its index in the call's argument window.
+  LdArg(u8),
   LdAddr(usize),
+  LdFn(Label, usize),
+  MkClosure(Label, usize, usize),
+  /// Pop a number of values into an array, in the order they were pushed.
+  MkArray(usize),
   LdImport(&'a str),
+  /// Load a built-in by its index in the machine's built-in table, linked from [`LdImport`].
+  ///
+  /// [`LdImport`]: Instruction::LdImport
+  LdBuiltin(u16),
+  /// Load a top-level variable by name.
+  LdGlobal(&'a str),
 
   StLoc(Local),
+  /// Store an argument of the current call by its index in the call's argument window.
+  StArg(u8),
+  /// Store a top-level variable by name, kept by the machine between runs.
+  StGlobal(&'a str),
+  Pop,
+  /// Exchange the top two values of the stack.
+  Swap,
+  /// Copy the top value of the stack.
+  Dup,
+  /// Copy the second value from the top of the stack to the top.
+  Over,
 
   Label(Label),
 
   Jmp(Label),
+  /// Pop a value and jump when it's truthy.
+  JmpIf(Label),
+  /// Pop a value and jump when it isn't truthy.
+  JmpIfNot(Label),
   JmpEq(Label),
   JmpNEq(Label),
   JmpLt(Label),
@@ -30,9 +90,18 @@ pub enum Instruction<'a> {
   JmpLtEq(Label),
   JmpGtEq(Label),
 
-  Call(Label),
-  CallF(&'a str),
+  Call(Label, usize),
+  /// Call from tail position, reusing the caller's frame instead of pushing one.
+  Tailcall(Label, usize),
+  CallF(&'a str, usize),
+  /// Call a built-in by its index in the machine's built-in table, linked from [`CallF`].
+  ///
+  /// [`CallF`]: Instruction::CallF
+  CallBuiltin(u16, usize),
+  CallDyn(usize),
   Ret,
+  /// Stop running, leaving the stack as is.
+  Halt,
 
   Add,
   Sub,
@@ -40,6 +109,8 @@ pub enum Instruction<'a> {
   Div,
   Mod,
   Pow,
+  /// Negate a number.
+  Neg,
 
   Eq,
   NEq,
@@ -48,9 +119,190 @@ pub enum Instruction<'a> {
   LtEq,
   GtEq,
 
+  /// Negate the truthiness of a value, giving a boolean.
+  Not,
+
   BOr,
+  /// Complement the bits of a number as a 32-bit integer.
   BNot,
   BAnd,
   LShift,
   RShift,
 }
+
+impl Instruction<'_> {
+  /// The name of the variant, without operands.
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Nop => "Nop",
+      Self::LdNull => "LdNull",
+      Self::LdTrue => "LdTrue",
+      Self::LdFalse => "LdFalse",
+      Self::LdConst(_) => "LdConst",
+      Self::LdStr(_) => "LdStr",
+      Self::LdF64(_) => "LdF64",
+      Self::LdLoc(_) => "LdLoc",
+      Self::LdArg(_) => "LdArg",
+      Self::LdAddr(_) => "LdAddr",
+      Self::LdFn(..) => "LdFn",
+      Self::MkClosure(..) => "MkClosure",
+      Self::MkArray(_) => "MkArray",
+      Self::LdImport(_) => "LdImport",
+      Self::LdBuiltin(_) => "LdBuiltin",
+      Self::LdGlobal(_) => "LdGlobal",
+      Self::StLoc(_) => "StLoc",
+      Self::StArg(_) => "StArg",
+      Self::StGlobal(_) => "StGlobal",
+      Self::Pop => "Pop",
+      Self::Swap => "Swap",
+      Self::Dup => "Dup",
+      Self::Over => "Over",
+      Self::Label(_) => "Label",
+      Self::Jmp(_) => "Jmp",
+      Self::JmpIf(_) => "JmpIf",
+      Self::JmpIfNot(_) => "JmpIfNot",
+      Self::JmpEq(_) => "JmpEq",
+      Self::JmpNEq(_) => "JmpNEq",
+      Self::JmpLt(_) => "JmpLt",
+      Self::JmpGt(_) => "JmpGt",
+      Self::JmpLtEq(_) => "JmpLtEq",
+      Self::JmpGtEq(_) => "JmpGtEq",
+      Self::Call(..) => "Call",
+      Self::Tailcall(..) => "Tailcall",
+      Self::CallF(..) => "CallF",
+      Self::CallBuiltin(..) => "CallBuiltin",
+      Self::CallDyn(_) => "CallDyn",
+      Self::Ret => "Ret",
+      Self::Halt => "Halt",
+      Self::Add => "Add",
+      Self::Sub => "Sub",
+      Self::Mul => "Mul",
+      Self::Div => "Div",
+      Self::Mod => "Mod",
+      Self::Pow => "Pow",
+      Self::Neg => "Neg",
+      Self::Eq => "Eq",
+      Self::NEq => "NEq",
+      Self::Lt => "Lt",
+      Self::Gt => "Gt",
+      Self::LtEq => "LtEq",
+      Self::GtEq => "GtEq",
+      Self::Not => "Not",
+      Self::BOr => "BOr",
+      Self::BNot => "BNot",
+      Self::BAnd => "BAnd",
+      Self::LShift => "LShift",
+      Self::RShift => "RShift",
+    }
+  }
+}
+
+/// Compact mnemonics such as `ld.f64 3.5`, `jmp.eq L2` or `add`.
+impl Display for Instruction<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Self::Nop => write!(f, "nop"),
+      Self::LdNull => write!(f, "ld.null"),
+      Self::LdTrue => write!(f, "ld.true"),
+      Self::LdFalse => write!(f, "ld.false"),
+      Self::LdConst(index) => write!(f, "ld.const {}", index),
+      Self::LdStr(value) => write!(f, "ld.str {:?}", value),
+      Self::LdF64(value) => write!(f, "ld.f64 {}", value),
+      Self::LdLoc(local) => write!(f, "ld.loc {}", local),
+      Self::LdArg(index) => write!(f, "ld.arg {}", index),
+      Self::LdAddr(addr) => write!(f, "ld.addr {}", addr),
+      Self::LdFn(label, arity) => write!(f, "ld.fn {} {}", label, arity),
+      Self::MkClosure(label, arity, captured) => {
+        write!(f, "mk.closure {} {} {}", label, arity, captured)
+      }
+      Self::MkArray(len) => write!(f, "mk.array {}", len),
+      Self::LdImport(name) => write!(f, "ld.import {}", name),
+      Self::LdBuiltin(index) => write!(f, "ld.builtin {}", index),
+      Self::LdGlobal(name) => write!(f, "ld.global {}", name),
+      Self::StLoc(local) => write!(f, "st.loc {}", local),
+      Self::StArg(index) => write!(f, "st.arg {}", index),
+      Self::StGlobal(name) => write!(f, "st.global {}", name),
+      Self::Pop => write!(f, "pop"),
+      Self::Swap => write!(f, "swap"),
+      Self::Dup => write!(f, "dup"),
+      Self::Over => write!(f, "over"),
+      Self::Label(label) => write!(f, "{}:", label),
+      Self::Jmp(label) => write!(f, "jmp {}", label),
+      Self::JmpIf(label) => write!(f, "jmp.if {}", label),
+      Self::JmpIfNot(label) => write!(f, "jmp.ifnot {}", label),
+      Self::JmpEq(label) => write!(f, "jmp.eq {}", label),
+      Self::JmpNEq(label) => write!(f, "jmp.neq {}", label),
+      Self::JmpLt(label) => write!(f, "jmp.lt {}", label),
+      Self::JmpGt(label) => write!(f, "jmp.gt {}", label),
+      Self::JmpLtEq(label) => write!(f, "jmp.lteq {}", label),
+      Self::JmpGtEq(label) => write!(f, "jmp.gteq {}", label),
+      Self::Call(label, argc) => write!(f, "call {} {}", label, argc),
+      Self::Tailcall(label, argc) => write!(f, "tailcall {} {}", label, argc),
+      Self::CallF(name, argc) => write!(f, "call.f {} {}", name, argc),
+      Self::CallBuiltin(index, argc) => write!(f, "call.builtin {} {}", index, argc),
+      Self::CallDyn(argc) => write!(f, "call.dyn {}", argc),
+      Self::Ret => write!(f, "ret"),
+      Self::Halt => write!(f, "halt"),
+      Self::Add => write!(f, "add"),
+      Self::Sub => write!(f, "sub"),
+      Self::Mul => write!(f, "mul"),
+      Self::Div => write!(f, "div"),
+      Self::Mod => write!(f, "mod"),
+      Self::Pow => write!(f, "pow"),
+      Self::Neg => write!(f, "neg"),
+      Self::Eq => write!(f, "eq"),
+      Self::NEq => write!(f, "neq"),
+      Self::Lt => write!(f, "lt"),
+      Self::Gt => write!(f, "gt"),
+      Self::LtEq => write!(f, "lteq"),
+      Self::GtEq => write!(f, "gteq"),
+      Self::Not => write!(f, "not"),
+      Self::BOr => write!(f, "bor"),
+      Self::BNot => write!(f, "bnot"),
+      Self::BAnd => write!(f, "band"),
+      Self::LShift => write!(f, "lshift"),
+      Self::RShift => write!(f, "rshift"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Instruction, Label};
+  use crate::{ir::scope::Local, util::uuid::Uuid};
+  use std::borrow::Cow;
+
+  #[test]
+  fn test_display() {
+    let label = Label::new(Uuid::from_u128(2));
+    let local = Local::new(Uuid::from_u128(0x1f));
+
+    let cases = [
+      (Instruction::LdF64(3.5), "ld.f64 3.5"),
+      (
+        Instruction::LdStr(Cow::Borrowed("a \"b\"")),
+        "ld.str \"a \\\"b\\\"\"",
+      ),
+      (Instruction::LdConst(4), "ld.const 4"),
+      (Instruction::LdBuiltin(3), "ld.builtin 3"),
+      (Instruction::LdLoc(local), "ld.loc %1f"),
+      (Instruction::StLoc(local), "st.loc %1f"),
+      (Instruction::LdArg(1), "ld.arg 1"),
+      (Instruction::StArg(0), "st.arg 0"),
+      (Instruction::StGlobal("x"), "st.global x"),
+      (Instruction::MkClosure(label, 1, 2), "mk.closure L2 1 2"),
+      (Instruction::MkArray(3), "mk.array 3"),
+      (Instruction::Label(label), "L2:"),
+      (Instruction::JmpEq(label), "jmp.eq L2"),
+      (Instruction::Call(label, 3), "call L2 3"),
+      (Instruction::CallF("print", 1), "call.f print 1"),
+      (Instruction::CallBuiltin(2, 1), "call.builtin 2 1"),
+      (Instruction::Add, "add"),
+      (Instruction::LShift, "lshift"),
+    ];
+
+    for (instr, expected) in &cases {
+      assert_eq!(format!("{}", instr), *expected);
+    }
+  }
+}
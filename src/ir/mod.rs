@@ -1,52 +1,298 @@
 //! High-level intermediate representation.
 
+pub mod bytecode;
+pub mod captures;
+pub mod depth;
+pub mod error;
 pub mod functions;
+pub mod inline;
 pub mod instr;
+pub mod module;
+pub mod reachable;
+pub mod resolve;
 pub mod scope;
+pub mod typecheck;
 
 use self::{
-  functions::get_fns,
-  instr::{Instruction, Label},
-  scope::{Local, Scope, ScopeId},
+  captures::get_free_vars,
+  error::{HirError, HirResult, HirWarning},
+  functions::{get_fns, get_fns_with_free_vars, FunctionDef},
+  inline::{eval_call, get_pure_fns},
+  instr::{Constant, Instruction, Label},
+  module::Sources,
+  scope::{Local, LocalName, Scope, ScopeId},
 };
-use crate::ast::{
-  Assign, BinaryOp, BinaryOperator, Call, Define, Expr, Function, If, NumberLit, Parser, RefParam,
-  RefVar, StringLit, UnaryOp, UnaryOperator, Visitor,
+use crate::{
+  ast::{
+    Assign, BinaryOp, BinaryOperator, BoolLit, Call, Compound, CompoundAssign, Cond, Const, Define,
+    Expr, Function, If, Import, Lambda, Loc, Noop, NumberLit, Parser, Quote, RefParam, RefVar,
+    Return, StringLit, UnaryOp, UnaryOperator, Visitor,
+  },
+  types::Span,
 };
-use std::collections::HashMap;
+use std::{
+  collections::{HashMap, HashSet},
+  convert::TryFrom,
+};
+
+/// Compiled instructions along with where each came from.
+#[derive(Debug, Clone, Default)]
+pub struct CompiledProgram<'a> {
+  pub code: Vec<Instruction<'a>>,
+  /// Unique literals loaded by [`Instruction::LdConst`].
+  pub consts: Vec<Constant<'a>>,
+  /// Source location of the instruction at the same index of `code`.
+  pub spans: Vec<Span<'a>>,
+  /// Likely mistakes which didn't stop compilation.
+  pub warnings: Vec<HirWarning>,
+  /// Name of each local loaded by [`Instruction::LdLoc`] and stored by [`Instruction::StLoc`].
+  pub locals: HashMap<Local, LocalName<'a>>,
+}
+
+impl CompiledProgram<'_> {
+  /// Render one instruction per line, naming locals such as `st.var x`.
+  pub fn disassemble(&self) -> String {
+    self
+      .code
+      .iter()
+      .map(|instr| match instr {
+        Instruction::LdLoc(local) | Instruction::StLoc(local) => {
+          let op = match instr {
+            Instruction::LdLoc(_) => "ld",
+            _ => "st",
+          };
+
+          match self.locals.get(local) {
+            Some(LocalName::Param(name)) => format!("{}.param {}", op, name),
+            Some(LocalName::Var(name)) => format!("{}.var {}", op, name),
+            None => instr.to_string(),
+          }
+        }
+        instr => instr.to_string(),
+      })
+      .collect::<Vec<_>>()
+      .join("\n")
+  }
+}
 
-pub fn compile<'buf>(script: &'buf str) -> Result<Vec<Instruction<'buf>>, ()> {
+pub fn compile<'buf>(script: &'buf str) -> HirResult<CompiledProgram<'buf>> {
   let expr = Parser::new(script).parse().expect("Failed to parse");
 
-  compile_expr(&expr)
+  compile_modules(vec![Module {
+    prefix: None,
+    expr,
+    imports: HashMap::new(),
+  }])
 }
 
-pub fn compile_expr<'buf>(expr: &Expr<'buf>) -> Result<Vec<Instruction<'buf>>, ()> {
+pub fn compile_expr<'buf>(expr: &Expr<'buf>) -> HirResult<CompiledProgram<'buf>> {
+  compile_modules(vec![Module {
+    prefix: None,
+    expr: expr.clone(),
+    imports: HashMap::new(),
+  }])
+}
+
+/// Compile a program and the modules it imports.
+///
+/// Functions of imported modules are prefixed with the module name, `add` defined in
+/// `lib.chal` is called as `lib.add`.
+pub fn compile_sources<'buf>(sources: &'buf Sources) -> HirResult<CompiledProgram<'buf>> {
+  let modules = sources
+    .0
+    .iter()
+    .enumerate()
+    .map(|(id, source)| Module {
+      // Functions of the entry file aren't prefixed
+      prefix: Some(source.name.as_str()).filter(|_| id > 0),
+      expr: Parser::new(&source.buf)
+        .parse()
+        .expect("Parsed while loading"),
+      imports: source.imports.clone(),
+    })
+    .collect();
+
+  compile_modules(modules)
+}
+
+fn compile_modules(modules: Vec<Module<'_>>) -> HirResult<CompiledProgram<'_>> {
+  let mut functions = HashMap::new();
+  let mut pure_fns = Vec::new();
+
+  for module in &modules {
+    let prefixed = |name: String| match module.prefix {
+      Some(prefix) => format!("{}.{}", prefix, name),
+      None => name,
+    };
+
+    pure_fns.extend(
+      get_pure_fns(&module.expr)
+        .into_iter()
+        .map(|(name, def)| (prefixed(name), def)),
+    );
+
+    for (name, def) in get_fns(&module.expr)? {
+      let name = match module.prefix {
+        Some(prefix) => format!("{}.{}", prefix, name),
+        None => name,
+      };
+
+      if functions.insert(name.clone(), def).is_some() {
+        return Err(HirError::DuplicateSymbol(name));
+      }
+    }
+  }
+
+  let pure_fns = pure_fns
+    .into_iter()
+    .filter_map(|(name, def)| Some((functions.get(&name)?.label, def)))
+    .collect();
+
+  let expr = modules[0].expr.clone();
   let mut hir = Hir {
     scope: ScopeId::new(0),
     scopes: vec![Scope::new()],
-    functions: get_fns(expr)?,
+    module: 0,
+    modules,
+    imported: vec![0],
+    functions,
+    pure_fns,
+    captures: HashMap::new(),
+    fn_depth: 0,
+    tail_fn: None,
+    stmt: false,
     instructions: Vec::new(),
+    consts: Vec::new(),
+    spans: Vec::new(),
+    span: Span::eof(),
+    locals: HashMap::new(),
+    args: HashMap::new(),
   };
 
-  hir.visit(expr)?;
+  hir.visit(&expr)?;
+
+  let warnings = hir.unused_vars();
+
+  Ok(CompiledProgram {
+    code: hir.instructions,
+    consts: hir.consts,
+    spans: hir.spans,
+    warnings,
+    locals: hir.locals,
+  })
+}
+
+/// A parsed source file.
+#[derive(Debug, Clone)]
+struct Module<'a> {
+  prefix: Option<&'a str>,
+  expr: Expr<'a>,
+  imports: HashMap<String, usize>,
+}
+
+/// Returns `true` if `expr` doesn't leave a value on the stack.
+fn is_stmt(expr: &Expr<'_>) -> bool {
+  match expr {
+    Expr::Assign(_)
+    | Expr::Return(_)
+    | Expr::CompoundAssign(_)
+    | Expr::Const(_)
+    | Expr::Define(_)
+    | Expr::Function(_)
+    | Expr::Import(_) => true,
+    Expr::Compound(expr) => expr.0.last().is_none_or(is_stmt),
+    _ => false,
+  }
+}
+
+/// Returns `true` if being a statement carries over to some of the expressions of `expr`.
+fn passes_stmt(expr: &Expr<'_>) -> bool {
+  matches!(
+    expr,
+    Expr::If(_) | Expr::Cond(_) | Expr::Compound(_) | Expr::Return(_)
+  )
+}
+
+/// Returns `true` if `lhs` and `rhs` load the same variable or parameter.
+fn is_same_ref(lhs: &Expr<'_>, rhs: &Expr<'_>) -> bool {
+  match (lhs, rhs) {
+    (Expr::RefVar(lhs), Expr::RefVar(rhs)) => lhs.0 == rhs.0,
+    (Expr::RefParam(lhs), Expr::RefParam(rhs)) => lhs.0 == rhs.0,
+    _ => false,
+  }
+}
+
+/// Get whether `expr` is a literal that's always truthy or always falsy, following
+/// [`Value::is_truthy`].
+///
+/// [`Value::is_truthy`]: crate::vm::types::Value::is_truthy
+fn literal_truthiness(expr: &Expr<'_>) -> Option<bool> {
+  match expr {
+    Expr::Noop(_) => Some(false),
+    Expr::Bool(BoolLit(value)) => Some(*value),
+    Expr::Number(NumberLit(value)) => Some(*value != 0.0 && !value.is_nan()),
+    Expr::String(StringLit(value)) => Some(!value.is_empty()),
+    _ => None,
+  }
+}
 
-  Ok(hir.instructions)
+/// Get the instruction evaluating `op` on the two topmost stack values.
+fn binary_instruction<'a>(op: BinaryOperator) -> Instruction<'a> {
+  match op {
+    BinaryOperator::Add => Instruction::Add,
+    BinaryOperator::Sub => Instruction::Sub,
+    BinaryOperator::Mul => Instruction::Mul,
+    BinaryOperator::Div => Instruction::Div,
+    BinaryOperator::Mod => Instruction::Mod,
+    BinaryOperator::Pow => Instruction::Pow,
+
+    BinaryOperator::BOr => Instruction::BOr,
+    BinaryOperator::BAnd => Instruction::BAnd,
+    BinaryOperator::LShift => Instruction::LShift,
+    BinaryOperator::RShift => Instruction::RShift,
+
+    BinaryOperator::Eq => Instruction::Eq,
+    BinaryOperator::NEq => Instruction::NEq,
+    BinaryOperator::Lt => Instruction::Lt,
+    BinaryOperator::LtEq => Instruction::LtEq,
+    BinaryOperator::Gt => Instruction::Gt,
+    BinaryOperator::GtEq => Instruction::GtEq,
+  }
 }
 
 #[derive(Debug, Clone)]
 pub struct Hir<'a> {
   scope: ScopeId,
   scopes: Vec<Scope>,
-  functions: HashMap<String, Label>,
+  module: usize,
+  modules: Vec<Module<'a>>,
+  imported: Vec<usize>,
+  functions: HashMap<String, FunctionDef>,
+  /// Top-level functions whose calls with literal arguments are evaluated while compiling.
+  pure_fns: HashMap<Label, Function<'a>>,
+  /// Parameters of enclosing functions passed to nested functions after their arguments.
+  captures: HashMap<Label, Vec<&'a str>>,
+  /// Number of function bodies being emitted.
+  fn_depth: usize,
+  /// Function whose self calls are emitted as [`Instruction::Tailcall`], set only while the
+  /// expression being entered is in tail position of the function.
+  tail_fn: Option<&'a str>,
+  /// Set only while the expression being entered is a statement or the value of its call, where
+  /// no other values of the call are left on the stack for a `return` to strand.
+  stmt: bool,
   instructions: Vec<Instruction<'a>>,
+  consts: Vec<Constant<'a>>,
+  /// Source location of each instruction.
+  spans: Vec<Span<'a>>,
+  /// Location of the expression being emitted.
+  span: Span<'a>,
+  /// Name of each local, kept for disassembly.
+  locals: HashMap<Local, LocalName<'a>>,
+  /// Index of each parameter in the argument window of its call, see [`Instruction::LdArg`].
+  args: HashMap<Local, u8>,
 }
 
 impl<'a> Hir<'a> {
-  fn scope(&self) -> &Scope {
-    self.scope_by(self.scope)
-  }
-
   fn scope_mut(&mut self) -> &mut Scope {
     self.scope_by_mut(self.scope)
   }
@@ -69,185 +315,672 @@ impl<'a> Hir<'a> {
   }
 
   fn push_scope(&mut self) -> ScopeId {
-    let scope = Scope::new();
+    let mut scope = Scope::new();
     let scope_id = ScopeId::new(self.scopes.len());
 
+    scope.parent = Some(self.scope);
+
+    self.scope_mut().children.push(scope_id);
     self.scopes.push(scope);
+    self.scope = scope_id;
 
     scope_id
   }
 
-  fn push_var(&mut self, name: &'a str) -> Local {
+  /// Define a variable in the current scope, nested scopes may shadow it but it can't be
+  /// defined twice in the same one.
+  fn push_var(&mut self, name: &'a str) -> HirResult<Local> {
     let scope = self.scope_mut();
     let local_id = Local::default();
 
-    if scope.vars.insert(name.to_string(), local_id).is_some() {
-      todo!("Duplicate variable `{}` defined", name);
+    if scope.vars.contains_key(name) {
+      return Err(HirError::DuplicateVariable(name.to_string()));
     }
 
-    local_id
+    scope.vars.insert(name.to_string(), local_id);
+    self.locals.insert(local_id, LocalName::Var(name));
+
+    Ok(local_id)
   }
 
-  fn push_param(&mut self, name: &'a str) -> Local {
+  fn push_param(&mut self, name: &'a str) -> HirResult<Local> {
     let scope = self.scope_mut();
     let local_id = Local::default();
 
-    if scope.params.insert(name.to_string(), local_id).is_some() {
-      todo!("Duplicate variable `{}` defined", name);
+    if scope.params.contains_key(name) {
+      return Err(HirError::DuplicateVariable(name.to_string()));
+    }
+
+    scope.params.insert(name.to_string(), local_id);
+    self.locals.insert(local_id, LocalName::Param(name));
+
+    Ok(local_id)
+  }
+
+  /// Read parameters from the argument window of the call, in the order they're pushed.
+  fn bind_args(&mut self, params: &[Local]) -> HirResult<()> {
+    for (index, local) in params.iter().enumerate() {
+      let index = u8::try_from(index).map_err(|_| HirError::TooManyParams(params.len()))?;
+
+      self.args.insert(*local, index);
+    }
+
+    Ok(())
+  }
+
+  /// Get a variable or parameter to be reassigned, variables shadow parameters.
+  fn get_assignable(&self, name: &str) -> HirResult<(ScopeId, Local)> {
+    if let Some((scope, local)) = self.get_var(name) {
+      if self.scope_by(scope).consts.contains(name) {
+        return Err(HirError::AssignToConst {
+          name: name.to_string(),
+        });
+      }
+
+      return Ok((scope, local));
     }
 
-    local_id
+    self
+      .get_param(name)
+      .ok_or_else(|| HirError::UndefinedVariable(name.to_string()))
+  }
+
+  /// Get a variable and the scope it was defined in.
+  fn get_var(&self, name: &str) -> Option<(ScopeId, Local)> {
+    self.find_local(name, |scope| &scope.vars)
   }
 
-  fn get_var_id(&self, name: &str) -> Option<Local> {
-    let mut scope = self.scope();
+  /// Get a parameter and the scope it was defined in.
+  fn get_param(&self, name: &str) -> Option<(ScopeId, Local)> {
+    self.find_local(name, |scope| &scope.params)
+  }
+
+  fn find_local<F>(&self, name: &str, locals: F) -> Option<(ScopeId, Local)>
+  where
+    F: Fn(&Scope) -> &HashMap<String, Local>,
+  {
+    let mut id = self.scope;
 
     loop {
-      if scope.vars.contains_key(name) {
-        return scope.vars.get(name).copied();
+      let scope = self.scope_by(id);
+
+      if let Some(local) = locals(scope).get(name) {
+        return Some((id, *local));
       }
 
-      match &scope.parent {
-        Some(parent) => scope = self.scope_by(*parent),
-        None => break,
-      };
+      id = scope.parent?;
+    }
+  }
+
+  /// Load a variable or parameter defined in `scope`, variables of the first scope are globals
+  /// and parameters are arguments of the call.
+  fn push_load(&mut self, name: &'a str, scope: ScopeId, local: Local) {
+    match (self.args.get(&local), scope.into_inner()) {
+      (Some(index), _) => self.push(Instruction::LdArg(*index)),
+      (None, 0) => self.push(Instruction::LdGlobal(name)),
+      (None, _) => self.push(Instruction::LdLoc(local)),
+    }
+  }
+
+  /// Store a variable or parameter defined in `scope`, variables of the first scope are globals
+  /// and parameters are arguments of the call.
+  fn push_store(&mut self, name: &'a str, scope: ScopeId, local: Local) {
+    match (self.args.get(&local), scope.into_inner()) {
+      (Some(index), _) => self.push(Instruction::StArg(*index)),
+      (None, 0) => self.push(Instruction::StGlobal(name)),
+      (None, _) => self.push(Instruction::StLoc(local)),
     }
+  }
 
-    None
+  /// Returns `true` if `id` is the top-level scope whose locals are always reachable.
+  fn is_root_scope(&self, id: ScopeId) -> bool {
+    self.scope_by(id).parent.is_none()
   }
 
-  fn get_param_id(&self, name: &str) -> Option<Local> {
-    let mut scope = self.scope();
+  /// Get a function visible from the current scope and module.
+  fn get_fn(&self, name: &str) -> Option<FunctionDef> {
+    let mut id = self.scope;
 
     loop {
-      if scope.params.contains_key(name) {
-        return scope.params.get(name).copied();
+      let scope = self.scope_by(id);
+
+      if let Some(def) = scope.fns.get(name) {
+        return Some(*def);
       }
 
-      match &scope.parent {
-        Some(parent) => scope = self.scope_by(*parent),
+      match scope.parent {
+        Some(parent) => id = parent,
         None => break,
+      }
+    }
+
+    self.modules[self.module]
+      .prefix
+      .and_then(|prefix| self.functions.get(&format!("{}.{}", prefix, name)))
+      .or_else(|| self.functions.get(name))
+      .copied()
+  }
+
+  /// Evaluate a call to a pure function whose arguments are all literals.
+  fn eval_pure_call(&self, expr: &Call<'a>) -> Option<Expr<'a>> {
+    let def = self.pure_fns.get(&self.get_fn(expr.name)?.label)?;
+
+    match &expr.args {
+      Some(Expr::Compound(args)) => eval_call(def, &args.0),
+      Some(arg) => eval_call(def, std::slice::from_ref(arg)),
+      None => eval_call(def, &[]),
+    }
+  }
+
+  /// Define the functions of a function body in the current scope.
+  ///
+  /// Nested functions capture the parameters of enclosing functions they reference.
+  fn push_fns(&mut self, body: &Expr<'a>) -> HirResult<()> {
+    let fns = get_fns_with_free_vars(body)?;
+    let mut captures = fns
+      .iter()
+      .map(|(name, (_, free))| {
+        let params = free
+          .params
+          .iter()
+          .copied()
+          .filter(
+            |name| matches!(self.get_param(name), Some((scope, _)) if !self.is_root_scope(scope)),
+          )
+          .collect::<Vec<_>>();
+
+        (name.clone(), params)
+      })
+      .collect::<HashMap<_, _>>();
+
+    // Calling a sibling loads its captures so the caller has to capture them as well
+    loop {
+      let mut changed = false;
+
+      for (name, (_, free)) in &fns {
+        for callee in &free.params {
+          let inherited = match captures.get(*callee) {
+            Some(inherited) => inherited.clone(),
+            None => continue,
+          };
+
+          let own = captures.get_mut(name).expect("Captures of every function");
+
+          for param in inherited {
+            if !own.contains(&param) {
+              own.push(param);
+              changed = true;
+            }
+          }
+        }
+      }
+
+      if !changed {
+        break;
+      }
+    }
+
+    for (name, (def, _)) in fns {
+      match captures.remove(&name) {
+        Some(captures) if !captures.is_empty() => {
+          self.captures.insert(def.label, captures);
+        }
+        _ => {}
+      }
+
+      self.scope_mut().fns.insert(name, def);
+    }
+
+    Ok(())
+  }
+
+  /// Load the parameters captured by the function at `label`, returning how many there are.
+  fn push_captures(&mut self, label: Label) -> usize {
+    let captures = self.captures.get(&label).cloned().unwrap_or_default();
+
+    for name in &captures {
+      let (scope, local) = self
+        .get_param(name)
+        .expect("Captured parameter of an enclosing function");
+
+      self.push_load(name, scope, local);
+    }
+
+    captures.len()
+  }
+
+  /// Find variables of every scope which are never loaded, ignoring names starting with `_`.
+  fn unused_vars(&self) -> Vec<HirWarning> {
+    let mut loaded = HashSet::new();
+    let mut loaded_globals = HashSet::new();
+
+    for instruction in &self.instructions {
+      match instruction {
+        Instruction::LdLoc(local) => loaded.insert(*local),
+        Instruction::LdGlobal(name) => loaded_globals.insert(*name),
+        _ => false,
       };
     }
 
-    None
+    self
+      .scopes
+      .iter()
+      .enumerate()
+      .flat_map(|(id, scope)| {
+        let mut names = scope
+          .vars
+          .iter()
+          .filter(|(name, local)| match id {
+            0 => !loaded_globals.contains(name.as_str()),
+            _ => !loaded.contains(local),
+          })
+          .filter(|(name, _)| !name.starts_with('_'))
+          .map(|(name, _)| name.clone())
+          .collect::<Vec<_>>();
+
+        // Scope tables are unordered
+        names.sort();
+        names
+      })
+      .map(HirWarning::UnusedVariable)
+      .collect()
+  }
+
+  /// Emit `condition` followed by a jump to `label` when the condition holds.
+  fn visit_condition(&mut self, condition: &Expr<'a>, label: Label) -> HirResult<()> {
+    match condition {
+      Expr::BinaryOp(binary) if binary.op == BinaryOperator::Eq => self.at(&binary.loc, |hir| {
+        hir.visit_operands(binary)?;
+        hir.push(Instruction::JmpEq(label));
+
+        Ok(())
+      })?,
+      Expr::BinaryOp(binary) if binary.op == BinaryOperator::Lt => self.at(&binary.loc, |hir| {
+        hir.visit_operands(binary)?;
+        hir.push(Instruction::JmpLt(label));
+
+        Ok(())
+      })?,
+      Expr::UnaryOp(unary) if unary.op == UnaryOperator::Not => self.at(&unary.loc, |hir| {
+        hir.visit(&unary.expr)?;
+        hir.push(Instruction::JmpIfNot(label));
+
+        Ok(())
+      })?,
+      expr => {
+        self.visit(expr)?;
+        self.push(Instruction::JmpIf(label));
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Emit the operands of `expr` in evaluation order, leaving the lhs on top.
+  fn visit_operands(&mut self, expr: &BinaryOp<'a>) -> HirResult<()> {
+    self.visit(&expr.lhs)?;
+
+    // Loading the same variable twice gives the same value in either order
+    if is_same_ref(&expr.lhs, &expr.rhs) {
+      self.push(Instruction::Dup);
+      return Ok(());
+    }
+
+    self.visit(&expr.rhs)?;
+    self.push(Instruction::Swap);
+
+    Ok(())
+  }
+
+  /// Emit a branch of an `if` or `cond` in its own scope leaving exactly one value, in tail
+  /// position if the whole expression is in tail position of `tail` and a statement if it's
+  /// one.
+  fn visit_branch(
+    &mut self,
+    expr: Option<&Expr<'a>>,
+    tail: Option<&'a str>,
+    stmt: bool,
+  ) -> HirResult<()> {
+    self.push_scope();
+
+    if let Some(expr) = expr {
+      self.tail_fn = tail;
+      self.stmt = stmt;
+      self.visit_tail(expr)?;
+    }
+
+    if expr.is_none_or(is_stmt) {
+      self.push(Instruction::LdNull);
+    }
+
+    self.pop_scope();
+
+    Ok(())
+  }
+
+  /// Emit instructions attributed to `loc` unless a nested expression has its own location.
+  fn at<F>(&mut self, loc: &Loc<'a>, f: F) -> HirResult<()>
+  where
+    F: FnOnce(&mut Self) -> HirResult<()>,
+  {
+    let outer = std::mem::replace(&mut self.span, loc.0.clone());
+    let result = f(self);
+    self.span = outer;
+
+    result
+  }
+
+  /// Load a literal, sharing the pool entry of an equal literal.
+  fn push_const(&mut self, constant: Constant<'a>) {
+    let consts = &mut self.consts;
+    let index = consts
+      .iter()
+      .position(|other| *other == constant)
+      .or_else(|| {
+        // Indices past `u16::MAX` can't be encoded, later literals are loaded inline
+        (consts.len() <= u16::MAX as usize).then(|| {
+          consts.push(constant.clone());
+          consts.len() - 1
+        })
+      });
+
+    match (index, constant) {
+      (Some(index), _) => self.push(Instruction::LdConst(index as u16)),
+      (None, Constant::Number(value)) => self.push(Instruction::LdF64(value)),
+      (None, Constant::String(value)) => self.push(Instruction::LdStr(value)),
+    }
+  }
+
+  /// Load quoted data, building an array from the items of each list.
+  fn push_datum(&mut self, expr: &Expr<'a>) -> Result<(), HirError> {
+    match expr {
+      Expr::Compound(items) => {
+        for item in &items.0 {
+          self.push_datum(item)?;
+        }
+
+        self.push(Instruction::MkArray(items.0.len()));
+
+        Ok(())
+      }
+      expr => self.visit(expr),
+    }
   }
 
   fn push(&mut self, instruction: Instruction<'a>) {
     self.instructions.push(instruction);
+    self.spans.push(self.span.clone());
   }
 }
 
 impl<'buf> Visitor<'buf> for Hir<'buf> {
-  type Error = ();
+  type Error = HirError;
+
+  /// Values of all but the last expression are discarded, expressions after a `return` are
+  /// unreachable so aren't emitted.
+  fn visit_compound(&mut self, expr: &Compound<'buf>) -> Result<(), Self::Error> {
+    let tail = self.tail_fn.take();
+    let stmt = std::mem::take(&mut self.stmt);
+
+    if let Some((last, rest)) = expr.0.split_last() {
+      for expr in rest {
+        self.stmt = stmt;
+        self.visit_stmt(expr)?;
+
+        if let Expr::Return(_) = expr {
+          return Ok(());
+        }
+
+        if !is_stmt(expr) {
+          self.push(Instruction::Pop);
+        }
+      }
+
+      self.tail_fn = tail;
+      self.stmt = stmt;
+      self.visit_tail(last)?;
+    }
+
+    Ok(())
+  }
+
+  /// Only `if`, `cond`, sequences and `return` pass on being a statement, to their branches,
+  /// expressions and value respectively.
+  fn visit_stmt(&mut self, expr: &Expr<'buf>) -> Result<(), Self::Error> {
+    if !passes_stmt(expr) {
+      self.stmt = false;
+    }
+
+    self.visit(expr)
+  }
+
+  /// Only `if`, `cond`, sequences, `return` and calls pass on being in tail position, to their
+  /// branches, last expression, value and self calls respectively.
+  fn visit_tail(&mut self, expr: &Expr<'buf>) -> Result<(), Self::Error> {
+    if !passes_stmt(expr) {
+      self.stmt = false;
+    }
+
+    match expr {
+      Expr::If(_) | Expr::Cond(_) | Expr::Compound(_) | Expr::Return(_) | Expr::Call(_) => {}
+      _ => self.tail_fn = None,
+    }
+
+    self.visit(expr)
+  }
+
+  fn visit_return(&mut self, expr: &Return<'buf>) -> Result<(), Self::Error> {
+    if self.fn_depth == 0 {
+      return Err(HirError::ReturnOutsideFunction);
+    }
+
+    // Values below an operand or argument would be left under the returned value
+    if !std::mem::take(&mut self.stmt) {
+      return Err(HirError::NestedReturn);
+    }
+
+    self.stmt = true;
+    self.visit_tail(&expr.0)?;
+    self.push(Instruction::Ret);
+
+    Ok(())
+  }
+
+  fn visit_quote(&mut self, expr: &Quote<'buf>) -> Result<(), Self::Error> {
+    self.push_datum(&expr.0)
+  }
 
   fn visit_var(&mut self, var: &RefVar<'buf>) -> Result<(), Self::Error> {
-    match self.get_var_id(var.0) {
-      Some(local) => {
-        self.push(Instruction::LdLoc(local));
-        Ok(())
-      }
-      None => todo!("Undefined variable `{}`", var.0),
+    match self.get_var(var.0) {
+      Some((scope, local)) => self.push_load(var.0, scope, local),
+      // Left to a global stored by an earlier run of the machine
+      None => self.push(Instruction::LdGlobal(var.0)),
     }
+
+    Ok(())
   }
 
   fn visit_param(&mut self, param: &RefParam<'buf>) -> Result<(), Self::Error> {
-    match self.get_param_id(param.0) {
-      Some(local) => {
-        self.push(Instruction::LdLoc(local));
+    if let Some((scope, local)) = self.get_param(param.0) {
+      self.push_load(param.0, scope, local);
+      return Ok(());
+    }
+
+    // A bare function name references the function itself, `(name)` calls it
+    match self.get_fn(param.0) {
+      Some(def) => {
+        match self.push_captures(def.label) {
+          0 => self.push(Instruction::LdFn(def.label, def.arity)),
+          captured => self.push(Instruction::MkClosure(def.label, def.arity, captured)),
+        }
+
         Ok(())
       }
-      None => todo!("Undefined parameter `{}`", param.0),
+      None => Err(HirError::UndefinedVariable(param.0.to_string())),
     }
   }
 
   fn visit_assign(&mut self, expr: &Assign<'buf>) -> Result<(), Self::Error> {
-    let local = self
-      .get_var_id(expr.ident)
-      .unwrap_or_else(|| panic!("Undefined parameter `{}`", expr.ident));
+    let (scope, local) = self.get_assignable(expr.ident)?;
 
     self.visit(&expr.expr)?;
-    self.push(Instruction::StLoc(local));
+    self.push_store(expr.ident, scope, local);
+
+    Ok(())
+  }
+
+  fn visit_compound_assign(&mut self, expr: &CompoundAssign<'buf>) -> Result<(), Self::Error> {
+    let (scope, local) = self.get_assignable(expr.ident)?;
+
+    self.visit(&expr.expr)?;
+    self.push_load(expr.ident, scope, local);
+    self.push(binary_instruction(expr.op));
+    self.push_store(expr.ident, scope, local);
+
+    Ok(())
+  }
+
+  fn visit_const(&mut self, expr: &Const<'buf>) -> Result<(), Self::Error> {
+    let local = self.push_var(expr.ident)?;
+    self.scope_mut().consts.insert(expr.ident.to_string());
+
+    self.visit(&expr.expr)?;
+    self.push_store(expr.ident, self.scope, local);
 
     Ok(())
   }
 
   fn visit_define(&mut self, expr: &Define<'buf>) -> Result<(), Self::Error> {
-    let local = self.push_var(expr.ident);
+    let local = self.push_var(expr.ident)?;
 
     self.visit(&expr.expr)?;
-    self.push(Instruction::StLoc(local));
+    self.push_store(expr.ident, self.scope, local);
+
+    Ok(())
+  }
+
+  /// An empty group `()` is a null value.
+  fn visit_noop(&mut self, _: &Noop) -> Result<(), Self::Error> {
+    self.push(Instruction::LdNull);
 
     Ok(())
   }
 
   fn visit_number(&mut self, lit: &NumberLit) -> Result<(), Self::Error> {
-    self.push(Instruction::LdF64(lit.0));
+    self.push_const(Constant::Number(lit.0));
+
+    Ok(())
+  }
+
+  fn visit_bool(&mut self, lit: &BoolLit) -> Result<(), Self::Error> {
+    match lit.0 {
+      true => self.push(Instruction::LdTrue),
+      false => self.push(Instruction::LdFalse),
+    }
 
     Ok(())
   }
 
   fn visit_string(&mut self, lit: &StringLit<'buf>) -> Result<(), Self::Error> {
-    self.push(Instruction::LdStr(lit.0.clone()));
+    self.push_const(Constant::String(lit.0.clone()));
 
     Ok(())
   }
 
   fn visit_unary(&mut self, expr: &UnaryOp<'buf>) -> Result<(), Self::Error> {
-    match &expr.op {
-      UnaryOperator::Neg => {
-        self.visit(&expr.expr)?;
-        self.push(Instruction::LdF64(-1.0));
-        self.push(Instruction::Mul);
-      }
-      UnaryOperator::BNot => {
-        self.visit(&expr.expr)?;
-        self.push(Instruction::BNot);
+    self.at(&expr.loc, |hir| {
+      match &expr.op {
+        UnaryOperator::Neg => {
+          hir.visit(&expr.expr)?;
+          hir.push(Instruction::Neg);
+        }
+        UnaryOperator::Not => {
+          hir.visit(&expr.expr)?;
+          hir.push(Instruction::Not);
+        }
+        UnaryOperator::BNot => {
+          hir.visit(&expr.expr)?;
+          hir.push(Instruction::BNot);
+        }
+        _ => panic!("AddInc/SubInc unary expressions were a mistake."),
       }
-      _ => panic!("AddInc/SubInc unary expressions were a mistake."),
-    }
 
-    Ok(())
+      Ok(())
+    })
   }
 
   fn visit_binary(&mut self, expr: &BinaryOp<'buf>) -> Result<(), Self::Error> {
-    self.visit(&expr.rhs)?;
-    self.visit(&expr.lhs)?;
-    self.push(match &expr.op {
-      BinaryOperator::Add => Instruction::Add,
-      BinaryOperator::Sub => Instruction::Sub,
-      BinaryOperator::Mul => Instruction::Mul,
-      BinaryOperator::Div => Instruction::Div,
-      BinaryOperator::Mod => Instruction::Mod,
-      BinaryOperator::Pow => Instruction::Pow,
-
-      BinaryOperator::BOr => Instruction::BOr,
-      BinaryOperator::BAnd => Instruction::BAnd,
-      BinaryOperator::LShift => Instruction::LShift,
-      BinaryOperator::RShift => Instruction::RShift,
-
-      BinaryOperator::Eq => Instruction::Eq,
-      BinaryOperator::NEq => Instruction::NEq,
-      BinaryOperator::Lt => Instruction::Lt,
-      BinaryOperator::LtEq => Instruction::LtEq,
-      BinaryOperator::Gt => Instruction::Gt,
-      BinaryOperator::GtEq => Instruction::GtEq,
-    });
+    self.at(&expr.loc, |hir| {
+      hir.visit_operands(expr)?;
+      hir.push(binary_instruction(expr.op));
 
-    Ok(())
+      Ok(())
+    })
   }
 
   fn visit_call(&mut self, expr: &Call<'buf>) -> Result<(), Self::Error> {
-    for arg in &expr.args {
-      self.visit(arg)?;
-    }
+    let tail = self.tail_fn.take() == Some(expr.name);
+
+    self.at(&expr.loc, |hir| {
+      if let Some(def) = hir.get_fn(expr.name) {
+        let actual = match &expr.args {
+          Some(Expr::Compound(args)) => args.0.len(),
+          Some(_) => 1,
+          None => 0,
+        };
+
+        if actual != def.arity {
+          return Err(HirError::ArityMismatch {
+            name: expr.name.to_string(),
+            expected: def.arity,
+            actual,
+          });
+        }
+      }
 
-    match self.functions.get(expr.name).cloned() {
-      Some(label) => self.push(Instruction::Call(label)),
-      None => self.push(Instruction::CallF(expr.name)),
-    }
+      if let Some(value) = hir.eval_pure_call(expr) {
+        return hir.visit(&value);
+      }
 
-    Ok(())
+      // Every argument is kept on the stack unlike the values of a sequence
+      let argc = match &expr.args {
+        Some(Expr::Compound(args)) => {
+          for arg in &args.0 {
+            hir.visit(arg)?;
+          }
+
+          args.0.len()
+        }
+        Some(arg) => {
+          hir.visit(arg)?;
+          1
+        }
+        None => 0,
+      };
+
+      if let Some(def) = hir.get_fn(expr.name) {
+        let argc = argc + hir.push_captures(def.label);
+
+        if tail {
+          hir.push(Instruction::Tailcall(def.label, argc));
+        } else {
+          hir.push(Instruction::Call(def.label, argc));
+        }
+
+        return Ok(());
+      }
+
+      // Calling a variable or parameter invokes the callable value it holds
+      match hir.get_var(expr.name).or_else(|| hir.get_param(expr.name)) {
+        Some((scope, local)) => {
+          hir.push_load(expr.name, scope, local);
+          hir.push(Instruction::CallDyn(argc));
+        }
+        None => hir.push(Instruction::CallF(expr.name, argc)),
+      }
+
+      Ok(())
+    })
   }
 
   /// # Example
@@ -258,71 +991,250 @@ impl<'buf> Visitor<'buf> for Hir<'buf> {
   ///   LdF64(0.0)
   ///   JmpEq(body_label)
   ///     LdStr("Not equal")
-  ///     CallF("println")
+  ///     CallF("println", 1)
   ///     Jmp(end_label)
   ///   Label(body_label)
   ///     LdStr("Equal")
-  ///     CallF("println")
+  ///     CallF("println", 1)
   ///   Label(end_label)
   /// ```
   fn visit_if(&mut self, expr: &If<'buf>) -> Result<(), Self::Error> {
+    let tail = self.tail_fn.take();
+    let stmt = std::mem::take(&mut self.stmt);
+
+    // A literal condition always takes the same branch so only that one is emitted
+    if let Some(truthy) = literal_truthiness(&expr.condition) {
+      return match truthy {
+        true => self.visit_branch(Some(&expr.body), tail, stmt),
+        false => self.visit_branch(expr.fallthrough.as_ref(), tail, stmt),
+      };
+    }
+
     let end_label = Label::default();
     let body_label = Label::default();
 
-    match &expr.condition {
-      Expr::BinaryOp(binary) if binary.op == BinaryOperator::Eq => {
-        self.visit(&binary.rhs)?;
-        self.visit(&binary.lhs)?;
-        self.push(Instruction::JmpEq(body_label));
-      }
-      Expr::BinaryOp(binary) if binary.op == BinaryOperator::Lt => {
-        self.visit(&binary.rhs)?;
-        self.visit(&binary.lhs)?;
-        self.push(Instruction::JmpLt(body_label));
-      }
-      expr => {
-        self.visit(expr)?;
-        self.push(Instruction::LdTrue);
-        self.push(Instruction::JmpEq(body_label));
+    self.visit_condition(&expr.condition, body_label)?;
+
+    // Both branches leave a value so `if` can be used as an expression
+    self.visit_branch(expr.fallthrough.as_ref(), tail, stmt)?;
+    self.push(Instruction::Jmp(end_label));
+    self.push(Instruction::Label(body_label));
+    self.visit_branch(Some(&expr.body), tail, stmt)?;
+    self.push(Instruction::Label(end_label));
+
+    Ok(())
+  }
+
+  /// # Example
+  ///
+  /// Layout for a cond with two arms and a default
+  /// ```
+  ///   <condition 0>
+  ///   JmpEq(arm_0_label)
+  ///   <condition 1>
+  ///   JmpEq(arm_1_label)
+  ///     <default>
+  ///     Jmp(end_label)
+  ///   Label(arm_0_label)
+  ///     <body 0>
+  ///     Jmp(end_label)
+  ///   Label(arm_1_label)
+  ///     <body 1>
+  ///   Label(end_label)
+  /// ```
+  fn visit_cond(&mut self, expr: &Cond<'buf>) -> Result<(), Self::Error> {
+    let tail = self.tail_fn.take();
+    let stmt = std::mem::take(&mut self.stmt);
+    let end_label = Label::default();
+    let arm_labels = expr
+      .arms
+      .iter()
+      .map(|_| Label::default())
+      .collect::<Vec<_>>();
+
+    for ((condition, _), label) in expr.arms.iter().zip(&arm_labels) {
+      self.visit_condition(condition, *label)?;
+    }
+
+    self.visit_branch(expr.default.as_ref(), tail, stmt)?;
+    self.push(Instruction::Jmp(end_label));
+
+    for (i, ((_, body), label)) in expr.arms.iter().zip(&arm_labels).enumerate() {
+      self.push(Instruction::Label(*label));
+      self.visit_branch(Some(body), tail, stmt)?;
+
+      if i + 1 < expr.arms.len() {
+        self.push(Instruction::Jmp(end_label));
       }
     }
 
-    if let Some(fallthrough) = &expr.fallthrough {
-      self.push_scope();
-      self.visit(fallthrough)?;
-      self.pop_scope();
-      self.push(Instruction::Jmp(end_label));
+    self.push(Instruction::Label(end_label));
+
+    Ok(())
+  }
+
+  fn visit_import(&mut self, expr: &Import<'buf>) -> Result<(), Self::Error> {
+    let id = self.modules[self.module]
+      .imports
+      .get(expr.0.as_ref())
+      .copied()
+      .ok_or_else(|| HirError::UnresolvedImport(expr.0.to_string()))?;
+
+    // A module's top-level is only emitted by its first import
+    if self.imported.contains(&id) {
+      return Ok(());
     }
 
-    self.push(Instruction::Label(body_label));
+    let expr = self.modules[id].expr.clone();
+    let module = std::mem::replace(&mut self.module, id);
 
+    self.imported.push(id);
     self.push_scope();
-    self.visit(&expr.body)?;
+    self.visit(&expr)?;
     self.pop_scope();
-    self.push(Instruction::Label(end_label));
+    self.module = module;
 
     Ok(())
   }
 
-  fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
+  /// # Example
+  ///
+  /// Layout for a lambda capturing the variable `n` and taking `x`, which the body loads with
+  /// `LdArg(0)`
+  /// ```
+  ///   Jmp(end_label)
+  ///   Label(fn_label)
+  ///     StLoc(inner_n)
+  ///     <body>
+  ///     Ret
+  ///   Label(end_label)
+  ///   LdLoc(outer_n)
+  ///   MkClosure(fn_label, 1, 1)
+  /// ```
+  fn visit_lambda(&mut self, expr: &Lambda<'buf>) -> Result<(), Self::Error> {
+    let free = get_free_vars(&expr.params, &expr.body)?;
+
+    // Top-level variables stay reachable from any frame so only function locals are captured.
+    let vars = free
+      .vars
+      .into_iter()
+      .filter_map(|name| match self.get_var(name) {
+        Some((scope, local)) if !self.is_root_scope(scope) => Some((name, local)),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+
+    let params = free
+      .params
+      .into_iter()
+      .filter_map(|name| match self.get_param(name) {
+        Some((scope, local)) if !self.is_root_scope(scope) => Some((name, local)),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+
+    let end_label = Label::default();
+    let fn_label = Label::default();
+
+    self.push(Instruction::Jmp(end_label));
+    self.push(Instruction::Label(fn_label));
     self.push_scope();
 
+    // Captured parameters are pushed after the arguments and stay in the argument window,
+    // captured variables are pushed last and stored as locals of the call
+    let mut args = Vec::new();
+
+    for param in &expr.params {
+      args.push(self.push_param(param.name)?);
+    }
+
+    for (name, _) in &params {
+      args.push(self.push_param(name)?);
+    }
+
+    self.bind_args(&args)?;
+
+    let locals = vars
+      .iter()
+      .map(|(name, _)| self.push_var(name))
+      .collect::<HirResult<Vec<_>>>()?;
+
+    locals.into_iter().rev().for_each(|local| {
+      self.push(Instruction::StLoc(local));
+    });
+
+    self.fn_depth += 1;
+    self.stmt = true;
+    self.visit_tail(&expr.body)?;
+
+    if is_stmt(&expr.body) {
+      self.push(Instruction::LdNull);
+    }
+
+    self.fn_depth -= 1;
+    self.push(Instruction::Ret);
+    self.pop_scope();
+    self.push(Instruction::Label(end_label));
+
+    let captured = vars.len() + params.len();
+
+    for (name, local) in params {
+      let (scope, _) = self.get_param(name).expect("Captured parameter in scope");
+
+      self.push_load(name, scope, local);
+    }
+
+    for (_, local) in vars {
+      self.push(Instruction::LdLoc(local));
+    }
+
+    self.push(Instruction::MkClosure(
+      fn_label,
+      expr.params.len(),
+      captured,
+    ));
+
+    Ok(())
+  }
+
+  fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
     let end_label = Label::default();
     let fn_label = self
-      .functions
-      .get(expr.name)
-      .cloned()
+      .get_fn(expr.name)
+      .map(|def| def.label)
       .expect("Function defined after HIR initial scan");
+    let captures = self.captures.get(&fn_label).cloned().unwrap_or_default();
+
+    self.push_scope();
 
     self.push(Instruction::Jmp(end_label));
     self.push(Instruction::Label(fn_label));
 
-    expr.params.iter().for_each(|param| {
-      let local = self.push_param(param);
-      self.push(Instruction::StLoc(local));
-    });
+    // Arguments are pushed in order followed by captured parameters, all of which are left in
+    // the argument window of the call
+    let params = expr
+      .params
+      .iter()
+      .map(|param| param.name)
+      .chain(captures)
+      .map(|name| self.push_param(name))
+      .collect::<HirResult<Vec<_>>>()?;
+
+    self.bind_args(&params)?;
+
+    self.push_fns(&expr.body)?;
+    self.fn_depth += 1;
+    // Self calls at the end of the body re-enter the function reusing the current frame
+    self.tail_fn = Some(expr.name);
+    self.stmt = true;
+    self.visit_tail(&expr.body)?;
+
+    // A body ending in a statement returns null the same as a branch does
+    if is_stmt(&expr.body) {
+      self.push(Instruction::LdNull);
+    }
 
-    self.visit(&expr.body)?;
+    self.fn_depth -= 1;
     self.push(Instruction::Ret);
     self.push(Instruction::Label(end_label));
 
@@ -334,16 +1246,396 @@ impl<'buf> Visitor<'buf> for Hir<'buf> {
 
 #[cfg(test)]
 mod tests {
+  use super::{
+    compile,
+    error::{HirError, HirWarning},
+    instr::{Constant, Instruction},
+    module::Sources,
+  };
   use crate::ast::Parser;
 
+  #[test]
+  fn test_unused_vars() {
+    let program = compile("(var used 1) (var unused 2) (var _ignored 3) (print $used)").unwrap();
+
+    assert_eq!(
+      program.warnings,
+      vec![HirWarning::UnusedVariable("unused".to_string())]
+    );
+
+    let program = compile("(var x 1) (+= x 1) (fun f (a) (var y a) $y)").unwrap();
+
+    assert!(program.warnings.is_empty());
+  }
+
+  #[test]
+  fn test_consts() {
+    let program = compile("(print \"a\") (print \"a\" 1)").unwrap();
+
+    assert_eq!(
+      program.consts,
+      vec![Constant::String("a".into()), Constant::Number(1.0)]
+    );
+    assert_eq!(
+      program
+        .code
+        .iter()
+        .filter(|instr| **instr == Instruction::LdConst(0))
+        .count(),
+      2
+    );
+  }
+
+  #[test]
+  fn test_literal_condition() {
+    let program = compile("(if 1 (print \"a\") (print \"b\"))").unwrap();
+
+    assert_eq!(program.consts, vec![Constant::String("a".into())]);
+    assert_eq!(
+      program.code,
+      vec![Instruction::LdConst(0), Instruction::CallF("print", 1)]
+    );
+
+    let program = compile("(if 0 (print \"a\") (print \"b\"))").unwrap();
+
+    assert_eq!(program.consts, vec![Constant::String("b".into())]);
+    assert_eq!(
+      program.code,
+      vec![Instruction::LdConst(0), Instruction::CallF("print", 1)]
+    );
+  }
+
+  #[test]
+  fn test_compile_neg() {
+    let program = compile("(- 5)").unwrap();
+
+    assert_eq!(
+      program.code,
+      vec![Instruction::LdConst(0), Instruction::Neg]
+    );
+    assert_eq!(program.consts, vec![Constant::Number(5.0)]);
+  }
+
+  #[test]
+  fn test_compile_pure_call() {
+    let program = compile("(fun sq (x)(* x x))(sq 4)").unwrap();
+
+    assert!(!program
+      .code
+      .iter()
+      .any(|instr| matches!(instr, Instruction::Call(..))));
+
+    match program.code.last() {
+      Some(Instruction::LdConst(id)) => {
+        assert_eq!(program.consts[*id as usize], Constant::Number(16.0))
+      }
+      instr => panic!("expected a constant, found {:?}", instr),
+    }
+
+    // Calls reaching built-ins are left alone
+    let program = compile("(fun show (x) (print x)) (show 4)").unwrap();
+
+    assert!(matches!(program.code.last(), Some(Instruction::Call(..))));
+  }
+
+  #[test]
+  fn test_compile_builtin_argc() {
+    let program = compile("(random_int 1 (+ 2 3))").unwrap();
+
+    assert_eq!(
+      program.code.last(),
+      Some(&Instruction::CallF("random_int", 2))
+    );
+  }
+
+  #[test]
+  fn test_compile_nested_fn() {
+    let program = compile(
+      "(fun outer (n) \
+         (fun inner (x) (+ x n)) \
+         (fun twice (x) (inner (inner x))) \
+         (twice 1)) \
+       (outer 10)",
+    )
+    .unwrap();
+
+    // Both nested functions are passed `n` after their argument
+    assert_eq!(
+      program
+        .code
+        .iter()
+        .filter(|instr| matches!(instr, Instruction::Call(_, 2)))
+        .count(),
+      3
+    );
+  }
+
+  #[test]
+  fn test_compile_import_duplicate() {
+    let mut sources = Sources::load(concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/data/import/main.chal"
+    ))
+    .unwrap();
+
+    // Both modules are named `lib`
+    sources.0[0].name = "lib".to_string();
+    sources.0.push(sources.0[1].clone());
+    sources.0[0].buf = "(import \"lib.chal\") (fun lib.add (a b) 0)".to_string();
+
+    assert!(matches!(
+      super::compile_sources(&sources),
+      Err(HirError::DuplicateSymbol(name)) if name == "lib.add"
+    ));
+  }
+
+  #[test]
+  fn test_compile_assign_param() {
+    let program = compile("(fun f (n) (+= n 10) n) (f 1)").unwrap();
+
+    let args = program
+      .code
+      .iter()
+      .filter(|instr| matches!(instr, Instruction::LdArg(_) | Instruction::StArg(_)))
+      .collect::<Vec<_>>();
+
+    // The parameter is loaded, stored and returned in place
+    assert_eq!(
+      args,
+      [
+        &Instruction::LdArg(0),
+        &Instruction::StArg(0),
+        &Instruction::LdArg(0)
+      ]
+    );
+    assert!(matches!(
+      compile("(+= y 1)"),
+      Err(HirError::UndefinedVariable(name)) if name == "y"
+    ));
+
+    // A bare name has to be in scope rather than being left to a built-in
+    assert!(matches!(
+      compile("(var x nosuch)"),
+      Err(HirError::UndefinedVariable(name)) if name == "nosuch"
+    ));
+  }
+
+  #[test]
+  fn test_compile_globals() {
+    let program = compile("(var x 1) (fun f () (var y $x) $y) (f)").unwrap();
+    let stores = program
+      .code
+      .iter()
+      .filter(|instr| matches!(instr, Instruction::StLoc(_) | Instruction::StGlobal(_)))
+      .collect::<Vec<_>>();
+
+    // Only the top-level variable is a global
+    assert!(matches!(
+      stores[..],
+      [Instruction::StGlobal("x"), Instruction::StLoc(_)]
+    ));
+    assert!(program.code.contains(&Instruction::LdGlobal("x")));
+  }
+
+  #[test]
+  fn test_compile_same_operands() {
+    let program = compile("(fun f (x) (* x x)) (f 3)").unwrap();
+    let body = program
+      .code
+      .iter()
+      .skip_while(|instr| !matches!(instr, Instruction::Label(_)))
+      .skip(1)
+      .take_while(|instr| **instr != Instruction::Ret)
+      .collect::<Vec<_>>();
+
+    // The parameter is loaded once and copied rather than loaded twice and swapped
+    assert!(matches!(
+      body[..],
+      [Instruction::LdArg(0), Instruction::Dup, Instruction::Mul]
+    ));
+
+    let program = compile("(fun f (x y) (* x y)) (f 3 4)").unwrap();
+
+    assert!(program.code.contains(&Instruction::Swap));
+  }
+
+  #[test]
+  fn test_disassemble() {
+    let program = compile("(fun f (a) (var x a) $x) (f 1)").unwrap();
+    let lines = program.disassemble();
+    let lines = lines
+      .lines()
+      .filter(|line| line.contains(".arg") || line.contains(".var"))
+      .collect::<Vec<_>>();
+
+    assert_eq!(lines, vec!["ld.arg 0", "st.var x", "ld.var x"]);
+  }
+
+  #[test]
+  fn test_compile_assign_const() {
+    let expr = Parser::new("(const x 1) (+= x 2)").parse().unwrap();
+
+    assert!(matches!(
+      super::compile_expr(&expr),
+      Err(HirError::AssignToConst { name }) if name == "x"
+    ));
+
+    let expr = Parser::new("(const x 1) ($x(add 2))").parse().unwrap();
+
+    assert!(matches!(
+      super::compile_expr(&expr),
+      Err(HirError::AssignToConst { name }) if name == "x"
+    ));
+
+    let expr = Parser::new("(var x 1) (+= x 2) ($x(add 2))")
+      .parse()
+      .unwrap();
+
+    assert!(super::compile_expr(&expr).is_ok());
+  }
+
+  #[test]
+  fn test_compile_redefine() {
+    for script in &[
+      "(const x 1) (var x 2)",
+      "(var x 1) (const x 2)",
+      "(fun f (a a) a)",
+    ] {
+      assert!(matches!(
+        compile(script),
+        Err(HirError::DuplicateVariable(name)) if name == "x" || name == "a"
+      ));
+    }
+
+    // A nested scope can shadow a constant with a variable which can be reassigned
+    assert!(compile("(const x 1) (if $x ((var x 2) (+= x 1) $x))").is_ok());
+
+    // The constant is back in scope once the nested one ends
+    assert!(matches!(
+      compile("(const x 1) (if $x (var x 2)) (+= x 1)"),
+      Err(HirError::AssignToConst { name }) if name == "x"
+    ));
+  }
+
+  #[test]
+  fn test_compile_arity() {
+    assert!(matches!(
+      compile("(fun f (a) a) (f 1 2)"),
+      Err(HirError::ArityMismatch { name, expected: 1, actual: 2 }) if name == "f"
+    ));
+    assert!(matches!(
+      compile("(fun f (a b) a) (f)"),
+      Err(HirError::ArityMismatch {
+        expected: 2,
+        actual: 0,
+        ..
+      })
+    ));
+
+    // `(g)` calls a function without parameters while a bare `g` references it
+    let program = compile("(fun g () (println 5)) (g) (var h g)").unwrap();
+
+    assert!(program
+      .code
+      .iter()
+      .any(|instr| matches!(instr, Instruction::Call(_, 0))));
+    assert!(program
+      .code
+      .iter()
+      .any(|instr| matches!(instr, Instruction::LdFn(_, 0))));
+  }
+
+  #[test]
+  fn test_compile_tail_calls() {
+    let tail_calls = |script: &str| {
+      compile(script)
+        .unwrap()
+        .code
+        .iter()
+        .filter(|instr| matches!(instr, Instruction::Tailcall(..)))
+        .count()
+    };
+
+    assert_eq!(tail_calls("(fun f (n) (f n))"), 1);
+    assert_eq!(tail_calls("(fun f (n) (if n (f n) (f n)))"), 2);
+    assert_eq!(tail_calls("(fun f (n) ((var x n) (f $x)))"), 1);
+    assert_eq!(
+      tail_calls("(fun f (n) (cond (n (f n)) (else (return (f n)))))"),
+      2
+    );
+
+    // Not in tail position
+    assert_eq!(tail_calls("(fun f (n) (+ 1 (f n)))"), 0);
+    assert_eq!(tail_calls("(fun f (n) (f (f n)))"), 1);
+    assert_eq!(tail_calls("(fun f (n) ((if n (f n) 0) 1))"), 0);
+    assert_eq!(tail_calls("(fun f (n) (if (if n (f n) 0) 1 0))"), 0);
+    // Prior values are popped
+    assert_eq!(tail_calls("(fun f (n) (1 (f n)))"), 1);
+    // Not a self call
+    assert_eq!(tail_calls("(fun g (n) n) (fun f (n) (g n))"), 0);
+  }
+
+  #[test]
+  fn test_compile_return() {
+    assert!(matches!(
+      compile("(return 1)"),
+      Err(HirError::ReturnOutsideFunction)
+    ));
+
+    // Nothing after the `return` is emitted
+    let program = compile("(fun f (n) (return n) (print \"unreachable\")) (f 1)").unwrap();
+
+    assert_eq!(program.consts, vec![Constant::Number(1.0)]);
+    assert!(!program
+      .code
+      .iter()
+      .any(|instr| matches!(instr, Instruction::CallF(..))));
+  }
+
+  #[test]
+  fn test_compile_nested_return() {
+    for script in &[
+      "(fun f (n) (+ 1 (return n))) (f 2)",
+      "(fun f (n) (g 1 (return n))) (fun g (a b) a) (f 2)",
+      "(fun f (n) (+ 1 (if n (return n) 0))) (f 2)",
+      "(fun f (n) (+ 1 ((return n) 2))) (f 2)",
+      "(fun f (n) ((+ 1 (return n)) 2)) (f 2)",
+    ] {
+      assert!(
+        matches!(compile(script), Err(HirError::NestedReturn)),
+        "{}",
+        script
+      );
+    }
+
+    // Statements, branches of statements and the last value of the body can return
+    for script in &[
+      "(fun f (n) (return n)) (f 2)",
+      "(fun f (n) ((return n) 1)) (f 2)",
+      "(fun f (n) (if n (return n)) 1) (f 2)",
+      "(fun f (n) (cond (n (return n)) (else 0))) (f 2)",
+      "(fun f (n) (if n ((var x 1) (return $x)) 0)) (f 2)",
+    ] {
+      assert!(compile(script).is_ok(), "{}", script);
+    }
+  }
+
+  #[test]
+  fn test_compile_unresolved_import() {
+    let expr = Parser::new("(import \"lib.chal\")").parse().unwrap();
+
+    assert!(matches!(
+      super::compile_expr(&expr),
+      Err(HirError::UnresolvedImport(_))
+    ));
+  }
+
   #[test]
   fn test_compile() {
     let expr = Parser::new(include_str!("../../data/recursion.chal"))
       .parse()
       .unwrap();
 
-    let instr = super::compile_expr(&expr).unwrap();
-
-    println!("{:?}", instr);
+    super::compile_expr(&expr).unwrap();
   }
 }
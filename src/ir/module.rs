@@ -0,0 +1,133 @@
+//! Loading of imported source files.
+
+use super::error::{ModuleError, ModuleResult};
+use crate::ast::{Import, Parser, Visitor};
+use std::{
+  collections::HashMap,
+  fs,
+  path::{Path, PathBuf},
+};
+
+/// A loaded source file.
+#[derive(Debug, Clone)]
+pub struct Source {
+  pub path: PathBuf,
+  /// Namespace prefix of the module's functions, derived from the file name.
+  pub name: String,
+  pub buf: String,
+  /// Index into [`Sources`] of each import path as written in `buf`.
+  pub imports: HashMap<String, usize>,
+}
+
+/// A program and every module it transitively imports.
+///
+/// The entry file is always the first source.
+#[derive(Debug, Clone, Default)]
+pub struct Sources(pub Vec<Source>);
+
+impl Sources {
+  /// Load `path` and its imports, resolving import paths relative to the importing file.
+  pub fn load<P: AsRef<Path>>(path: P) -> ModuleResult<Self> {
+    let mut sources = Self::default();
+
+    sources.load_file(path.as_ref(), &mut Vec::new())?;
+
+    Ok(sources)
+  }
+
+  fn load_file(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> ModuleResult<usize> {
+    let path = path
+      .canonicalize()
+      .map_err(|err| ModuleError::Io(path.to_path_buf(), err))?;
+
+    if stack.contains(&path) {
+      return Err(ModuleError::Cycle(path));
+    }
+
+    // Modules imported more than once are only loaded once
+    if let Some(id) = self.0.iter().position(|source| source.path == path) {
+      return Ok(id);
+    }
+
+    let buf = fs::read_to_string(&path).map_err(|err| ModuleError::Io(path.clone(), err))?;
+    let imports = get_imports(&buf).map_err(|err| ModuleError::Parse(path.clone(), err))?;
+
+    let id = self.0.len();
+    let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    self.0.push(Source {
+      name: path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default(),
+      path: path.clone(),
+      buf,
+      imports: HashMap::new(),
+    });
+
+    stack.push(path);
+
+    for import in imports {
+      let import_id = self.load_file(&dir.join(&import), stack)?;
+      self.0[id].imports.insert(import, import_id);
+    }
+
+    stack.pop();
+
+    Ok(id)
+  }
+}
+
+/// Get the import paths of a source file.
+fn get_imports(buf: &str) -> Result<Vec<String>, String> {
+  let expr = Parser::new(buf).parse().map_err(|err| err.to_string())?;
+  let mut imports = Imports::default();
+
+  // Infallible
+  let _ = imports.visit(&expr);
+
+  Ok(imports.0)
+}
+
+#[derive(Default)]
+struct Imports(Vec<String>);
+
+impl<'buf> Visitor<'buf> for Imports {
+  type Error = ();
+
+  fn visit_import(&mut self, expr: &Import<'buf>) -> Result<(), Self::Error> {
+    self.0.push(expr.0.to_string());
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Sources;
+  use crate::ir::error::ModuleError;
+
+  #[test]
+  fn test_load() {
+    let sources = Sources::load(concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/data/import/main.chal"
+    ))
+    .unwrap();
+
+    assert_eq!(sources.0.len(), 2);
+    assert_eq!(sources.0[0].name, "main");
+    assert_eq!(sources.0[1].name, "lib");
+    assert_eq!(sources.0[0].imports["lib.chal"], 1);
+  }
+
+  #[test]
+  fn test_load_cycle() {
+    let sources = Sources::load(concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/data/import/cycle_a.chal"
+    ));
+
+    assert!(matches!(sources, Err(ModuleError::Cycle(_))));
+  }
+}
@@ -0,0 +1,104 @@
+//! Reachability of lowered instructions.
+
+use super::{
+  error::HirWarning,
+  instr::{Instruction, Label},
+};
+use std::collections::HashMap;
+
+/// Warn about each instruction of `code` which can't run when starting from the first.
+///
+/// Control flows to the next instruction unless it returns, halts or jumps unconditionally, and
+/// to the label of every jump, call or function value. Labels only mark jump targets, so they're
+/// never reported.
+///
+/// Lowering leaves jumps and placeholder values after returns and tail calls, so this is meant
+/// for checking generated or hand-written code rather than compiled scripts.
+pub fn get_unreachable(code: &[Instruction<'_>]) -> Vec<HirWarning> {
+  let labels = code
+    .iter()
+    .enumerate()
+    .filter_map(|(offset, instr)| match instr {
+      Instruction::Label(label) => Some((*label, offset)),
+      _ => None,
+    })
+    .collect::<HashMap<Label, usize>>();
+
+  let mut reached = vec![false; code.len()];
+  let mut pending = vec![0];
+
+  while let Some(offset) = pending.pop() {
+    if offset >= code.len() || reached[offset] {
+      continue;
+    }
+
+    reached[offset] = true;
+
+    let (target, falls_through) = match &code[offset] {
+      Instruction::Ret | Instruction::Halt => (None, false),
+      Instruction::Jmp(label) | Instruction::Tailcall(label, _) => (Some(label), false),
+      Instruction::JmpIf(label)
+      | Instruction::JmpIfNot(label)
+      | Instruction::JmpEq(label)
+      | Instruction::JmpNEq(label)
+      | Instruction::JmpLt(label)
+      | Instruction::JmpGt(label)
+      | Instruction::JmpLtEq(label)
+      | Instruction::JmpGtEq(label)
+      | Instruction::Call(label, _)
+      | Instruction::LdFn(label, _)
+      | Instruction::MkClosure(label, ..) => (Some(label), true),
+      _ => (None, true),
+    };
+
+    if let Some(target) = target.and_then(|label| labels.get(label)) {
+      pending.push(*target);
+    }
+
+    if falls_through {
+      pending.push(offset + 1);
+    }
+  }
+
+  reached
+    .iter()
+    .enumerate()
+    .filter(|(offset, reached)| !**reached && !matches!(code[*offset], Instruction::Label(_)))
+    .map(|(offset, _)| HirWarning::UnreachableCode(offset))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::get_unreachable;
+  use crate::ir::{
+    error::HirWarning,
+    instr::{Instruction, Label},
+  };
+
+  #[test]
+  fn test_unreachable() {
+    let label = Label::default();
+    let end = Label::default();
+
+    let code = [
+      Instruction::Jmp(end),
+      Instruction::Label(label),
+      Instruction::Ret,
+      Instruction::LdF64(1.0),
+      Instruction::Label(end),
+      Instruction::Call(label, 0),
+      Instruction::Halt,
+      Instruction::Pop,
+    ];
+
+    assert_eq!(
+      get_unreachable(&code),
+      vec![
+        HirWarning::UnreachableCode(3),
+        HirWarning::UnreachableCode(7)
+      ]
+    );
+    assert!(get_unreachable(&[Instruction::LdF64(1.0), Instruction::Ret]).is_empty());
+  }
+}
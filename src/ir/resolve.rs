@@ -0,0 +1,405 @@
+//! Name resolution ahead of code generation.
+
+use super::error::ResolveError;
+use crate::{
+  ast::{
+    Assign, BinaryOp, Call, CompoundAssign, Cond, Const, Define, Expr, Function, If, Lambda, Loc,
+    Param, RefVar, UnaryOp, Visitor,
+  },
+  types::Span,
+};
+use std::{collections::HashSet, convert::Infallible};
+
+/// Find every variable referenced or assigned outside the scope it's defined in.
+///
+/// Parameter references aren't checked since `(name)` calls a built-in when `name` isn't a
+/// parameter or function.
+pub fn resolve<'buf>(expr: &Expr<'buf>) -> Result<(), Vec<ResolveError<'buf>>> {
+  let mut resolver = Resolver {
+    scopes: vec![HashSet::new()],
+    params: Vec::new(),
+    span: Span::eof(),
+    errors: Vec::new(),
+  };
+
+  resolver.visit(expr).ok();
+
+  match resolver.errors.is_empty() {
+    true => Ok(()),
+    false => Err(resolver.errors),
+  }
+}
+
+/// Rename a variable and every reference resolving to it, such as for an editor's rename.
+///
+/// The variable is defined by the last `var` or `const` whose keyword starts at or before `at`,
+/// so `at` can point to the keyword or the name of the form defining it. Variables of the same
+/// name defined in inner scopes, and references to them, are left as they are. Without such a
+/// definition the expression is returned unchanged.
+pub fn rename<'buf>(expr: &Expr<'buf>, at: &Span<'_>, new: &'buf str) -> Expr<'buf> {
+  let mut definitions = Definitions(Vec::new());
+
+  definitions.visit(expr).ok();
+
+  let mut expr = expr.clone();
+  let target = definitions
+    .0
+    .into_iter()
+    .filter(|(offset, _)| *offset <= at.begin().offset)
+    .max_by_key(|(offset, _)| *offset);
+
+  if let Some((target, name)) = target {
+    Renamer {
+      name,
+      new,
+      target,
+      scopes: vec![None],
+    }
+    .rename(&mut expr);
+  }
+
+  expr
+}
+
+/// Offset and name of each `var` and `const`.
+struct Definitions<'buf>(Vec<(usize, &'buf str)>);
+
+impl<'buf> Visitor<'buf> for Definitions<'buf> {
+  type Error = Infallible;
+
+  fn visit_const(&mut self, expr: &Const<'buf>) -> Result<(), Self::Error> {
+    self.0.push((expr.loc.0.begin().offset, expr.ident));
+    self.visit(&expr.expr)
+  }
+
+  fn visit_define(&mut self, expr: &Define<'buf>) -> Result<(), Self::Error> {
+    self.0.push((expr.loc.0.begin().offset, expr.ident));
+    self.visit(&expr.expr)
+  }
+}
+
+/// Renames the references to a single definition, scoped the same as code generation.
+struct Renamer<'a, 'buf> {
+  name: &'a str,
+  new: &'buf str,
+  /// Offset of the definition being renamed.
+  target: usize,
+  /// Whether the name in each enclosing scope, innermost last, is the definition being renamed.
+  /// `None` for scopes which don't define the name.
+  scopes: Vec<Option<bool>>,
+}
+
+impl<'buf> Renamer<'_, 'buf> {
+  /// Returns `true` if `name` resolves to the definition being renamed.
+  fn is_target(&self, name: &str) -> bool {
+    name == self.name && self.scopes.iter().rev().find_map(|scope| *scope) == Some(true)
+  }
+
+  fn define(&mut self, ident: &mut &'buf str, loc: &Loc<'_>) {
+    if *ident == self.name {
+      let is_target = loc.0.begin().offset == self.target;
+
+      *self.scopes.last_mut().expect("Root scope") = Some(is_target);
+
+      if is_target {
+        *ident = self.new;
+      }
+    }
+  }
+
+  fn rename_scoped(&mut self, expr: &mut Expr<'buf>) {
+    self.scopes.push(None);
+    self.rename(expr);
+    self.scopes.pop();
+  }
+
+  fn rename(&mut self, expr: &mut Expr<'buf>) {
+    match expr {
+      Expr::If(expr) => {
+        self.rename(&mut expr.condition);
+        self.rename_scoped(&mut expr.body);
+
+        if let Some(expr) = &mut expr.fallthrough {
+          self.rename_scoped(expr);
+        }
+      }
+      Expr::Cond(expr) => {
+        for (condition, body) in &mut expr.arms {
+          self.rename(condition);
+          self.rename_scoped(body);
+        }
+
+        if let Some(expr) = &mut expr.default {
+          self.rename_scoped(expr);
+        }
+      }
+      Expr::Call(expr) => {
+        if let Some(args) = &mut expr.args {
+          self.rename(args);
+        }
+      }
+      Expr::Assign(expr) => {
+        if self.is_target(expr.ident) {
+          expr.ident = self.new;
+        }
+
+        self.rename(&mut expr.expr);
+      }
+      Expr::CompoundAssign(expr) => {
+        if self.is_target(expr.ident) {
+          expr.ident = self.new;
+        }
+
+        self.rename(&mut expr.expr);
+      }
+      Expr::Const(expr) => {
+        self.rename(&mut expr.expr);
+        self.define(&mut expr.ident, &expr.loc);
+      }
+      Expr::Define(expr) => {
+        self.rename(&mut expr.expr);
+        self.define(&mut expr.ident, &expr.loc);
+      }
+      Expr::Function(expr) => self.rename_scoped(&mut expr.body),
+      Expr::Lambda(expr) => self.rename_scoped(&mut expr.body),
+      Expr::Return(expr) => self.rename(&mut expr.0),
+      Expr::UnaryOp(expr) => self.rename(&mut expr.expr),
+      Expr::BinaryOp(expr) => {
+        self.rename(&mut expr.lhs);
+        self.rename(&mut expr.rhs);
+      }
+      Expr::RefVar(var) => {
+        if self.is_target(var.0) {
+          var.0 = self.new;
+        }
+      }
+      Expr::Compound(expr) => {
+        for expr in &mut expr.0 {
+          self.rename(expr);
+        }
+      }
+      Expr::Noop(_)
+      | Expr::String(_)
+      | Expr::Number(_)
+      | Expr::Bool(_)
+      | Expr::Import(_)
+      | Expr::Quote(_)
+      | Expr::RefParam(_) => {}
+    }
+  }
+}
+
+struct Resolver<'buf> {
+  /// Variables defined by each enclosing scope, innermost last.
+  scopes: Vec<HashSet<&'buf str>>,
+  /// Parameters of each enclosing function.
+  params: Vec<&'buf str>,
+  /// Location of the innermost expression with one.
+  span: Span<'buf>,
+  errors: Vec<ResolveError<'buf>>,
+}
+
+impl<'buf> Resolver<'buf> {
+  fn check(&mut self, name: &'buf str) {
+    if !self.scopes.iter().any(|scope| scope.contains(name)) {
+      self.errors.push(ResolveError {
+        name,
+        span: self.span.clone(),
+      });
+    }
+  }
+
+  fn define(&mut self, name: &'buf str) {
+    self.scopes.last_mut().expect("Root scope").insert(name);
+  }
+
+  fn visit_scoped(&mut self, expr: &Expr<'buf>) {
+    self.scopes.push(HashSet::new());
+    self.visit(expr).ok();
+    self.scopes.pop();
+  }
+
+  /// Visit a function body in a new scope with its parameters assignable.
+  fn visit_fn(&mut self, params: &[Param<'buf>], body: &Expr<'buf>) {
+    let len = self.params.len();
+
+    self.params.extend(params.iter().map(|param| param.name));
+    self.visit_scoped(body);
+    self.params.truncate(len);
+  }
+
+  fn check_assign(&mut self, name: &'buf str) {
+    if !self.params.contains(&name) {
+      self.check(name);
+    }
+  }
+
+  fn at<F>(&mut self, loc: &Loc<'buf>, f: F)
+  where
+    F: FnOnce(&mut Self),
+  {
+    let outer = std::mem::replace(&mut self.span, loc.0.clone());
+    f(self);
+    self.span = outer;
+  }
+}
+
+impl<'buf> Visitor<'buf> for Resolver<'buf> {
+  type Error = Infallible;
+
+  fn visit_if(&mut self, expr: &If<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.condition)?;
+    self.visit_scoped(&expr.body);
+
+    if let Some(expr) = &expr.fallthrough {
+      self.visit_scoped(expr);
+    }
+
+    Ok(())
+  }
+
+  fn visit_cond(&mut self, expr: &Cond<'buf>) -> Result<(), Self::Error> {
+    for (condition, body) in &expr.arms {
+      self.visit(condition)?;
+      self.visit_scoped(body);
+    }
+
+    if let Some(expr) = &expr.default {
+      self.visit_scoped(expr);
+    }
+
+    Ok(())
+  }
+
+  fn visit_call(&mut self, expr: &Call<'buf>) -> Result<(), Self::Error> {
+    self.at(&expr.loc, |resolver| {
+      if let Some(args) = &expr.args {
+        resolver.visit(args).ok();
+      }
+    });
+
+    Ok(())
+  }
+
+  fn visit_assign(&mut self, expr: &Assign<'buf>) -> Result<(), Self::Error> {
+    self.check_assign(expr.ident);
+    self.visit(&expr.expr)
+  }
+
+  fn visit_compound_assign(&mut self, expr: &CompoundAssign<'buf>) -> Result<(), Self::Error> {
+    self.check_assign(expr.ident);
+    self.visit(&expr.expr)
+  }
+
+  fn visit_const(&mut self, expr: &Const<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.expr)?;
+    self.define(expr.ident);
+
+    Ok(())
+  }
+
+  fn visit_define(&mut self, expr: &Define<'buf>) -> Result<(), Self::Error> {
+    self.visit(&expr.expr)?;
+    self.define(expr.ident);
+
+    Ok(())
+  }
+
+  fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
+    self.visit_fn(&expr.params, &expr.body);
+
+    Ok(())
+  }
+
+  fn visit_lambda(&mut self, expr: &Lambda<'buf>) -> Result<(), Self::Error> {
+    self.visit_fn(&expr.params, &expr.body);
+
+    Ok(())
+  }
+
+  fn visit_unary(&mut self, expr: &UnaryOp<'buf>) -> Result<(), Self::Error> {
+    self.at(&expr.loc, |resolver| {
+      resolver.visit(&expr.expr).ok();
+    });
+
+    Ok(())
+  }
+
+  fn visit_binary(&mut self, expr: &BinaryOp<'buf>) -> Result<(), Self::Error> {
+    self.at(&expr.loc, |resolver| {
+      resolver.visit(&expr.lhs).ok();
+      resolver.visit(&expr.rhs).ok();
+    });
+
+    Ok(())
+  }
+
+  fn visit_var(&mut self, expr: &RefVar<'buf>) -> Result<(), Self::Error> {
+    self.check(expr.0);
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{rename, resolve};
+  use crate::{
+    ast::Parser,
+    types::{Position, Span},
+  };
+
+  #[test]
+  fn test_resolve() {
+    let expr = Parser::new(
+      "(var x 1) \
+       (fun f (a) (var y a) (+ $y $x)) \
+       (if (equal $x 1) (var z 2) (print $z)) \n\
+       (print $w)",
+    )
+    .parse()
+    .unwrap();
+
+    let errors = resolve(&expr).unwrap_err();
+    let errors = errors
+      .iter()
+      .map(|err| (err.name, format!("{:?}", err.span)))
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      errors,
+      vec![
+        ("z", "line 1, column 71".to_string()),
+        ("w", "line 2, column 2".to_string()),
+      ]
+    );
+
+    let expr = Parser::new("(var x 1) (+= x 1) ($x (f $x)) (fun g (a) (+= a $x))")
+      .parse()
+      .unwrap();
+
+    assert!(resolve(&expr).is_ok());
+  }
+
+  #[test]
+  fn test_rename() {
+    let src = "(var x 1) (fun f (x) (var x x) $x) (print x $x) ($x (add 1)) (+= x 2)";
+    let expr = Parser::new(src).parse().unwrap();
+    let at = |needle| {
+      let pos = Position::default().extend_str(&src[..src.find(needle).unwrap()]);
+      Span::new(pos, pos, src)
+    };
+    let parse = |src| Parser::new(src).parse().unwrap();
+
+    // Only the top-level variable is renamed, not the parameter nor the variable shadowing it
+    assert_eq!(
+      rename(&expr, &at("x 1)"), "y"),
+      parse("(var y 1) (fun f (x) (var x x) $x) (print x $y) ($y (add 1)) (+= y 2)")
+    );
+    assert_eq!(
+      rename(&expr, &at("x x)"), "y"),
+      parse("(var x 1) (fun f (x) (var y x) $y) (print x $x) ($x (add 1)) (+= x 2)")
+    );
+    assert_eq!(rename(&expr, &at("(var x 1)"), "y"), expr);
+  }
+}
@@ -1,7 +1,11 @@
 //! Scoped variable and local tracking.
 
+use super::functions::FunctionDef;
 use crate::util::uuid::Uuid;
-use std::collections::HashMap;
+use std::{
+  collections::{HashMap, HashSet},
+  fmt::Display,
+};
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ScopeId(usize);
@@ -19,10 +23,37 @@ impl ScopeId {
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Local(Uuid);
 
+impl Local {
+  pub fn new(id: Uuid) -> Self {
+    Self(id)
+  }
+
+  pub fn into_inner(self) -> Uuid {
+    self.0
+  }
+}
+
+impl Display for Local {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "%{:x}", self.0.as_u128())
+  }
+}
+
+/// The name a local was defined with, telling parameters from variables in disassembly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalName<'a> {
+  Param(&'a str),
+  Var(&'a str),
+}
+
 #[derive(Debug, Clone)]
 pub struct Scope {
   pub vars: HashMap<String, Local>,
   pub params: HashMap<String, Local>,
+  /// Names of `vars` which can't be reassigned.
+  pub consts: HashSet<String>,
+  /// Functions defined in the body of the function owning the scope.
+  pub fns: HashMap<String, FunctionDef>,
 
   pub parent: Option<ScopeId>,
   pub children: Vec<ScopeId>,
@@ -33,6 +64,8 @@ impl Scope {
     Self {
       vars: Default::default(),
       params: Default::default(),
+      consts: Default::default(),
+      fns: Default::default(),
       parent: None,
       children: Default::default(),
     }
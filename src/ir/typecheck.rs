@@ -0,0 +1,384 @@
+//! Conservative static type checking.
+
+use super::error::TypeError;
+use crate::ast::{
+  Assign, BinaryOp, BinaryOperator, BoolLit, Call, Cond, Const, Define, Expr, Function, If, Lambda,
+  NumberLit, Param, RefParam, RefVar, StringLit, TypeName, UnaryOp, UnaryOperator, Visitor,
+};
+use std::{collections::HashMap, convert::Infallible};
+
+/// The type of a value as far as it's known before running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+  Number,
+  String,
+  Bool,
+  /// Anything not annotated or inferred from a literal, always passes.
+  Unknown,
+}
+
+impl Type {
+  /// Returns `true` if a value of type `self` may be used where `other` is expected.
+  pub fn accepts(self, other: Type) -> bool {
+    self == Type::Unknown || other == Type::Unknown || self == other
+  }
+}
+
+impl From<Option<TypeName<'_>>> for Type {
+  fn from(name: Option<TypeName<'_>>) -> Self {
+    match name.map(|name| name.0) {
+      Some("number") => Type::Number,
+      Some("string") => Type::String,
+      Some("bool") => Type::Bool,
+      _ => Type::Unknown,
+    }
+  }
+}
+
+/// Flag operations which can't succeed with the annotated or literal types of their operands.
+///
+/// Values of unknown type are assumed to be correct.
+pub fn typecheck(expr: &Expr<'_>) -> Result<(), Vec<TypeError>> {
+  let mut checker = Checker {
+    scopes: vec![Scope::default()],
+    ..Default::default()
+  };
+
+  Signatures(&mut checker.functions).visit(expr).ok();
+  checker.visit(expr).ok();
+
+  match checker.errors.is_empty() {
+    true => Ok(()),
+    false => Err(checker.errors),
+  }
+}
+
+#[derive(Default)]
+struct Scope<'buf> {
+  vars: HashMap<&'buf str, Type>,
+  params: HashMap<&'buf str, Type>,
+}
+
+#[derive(Default)]
+struct Checker<'buf> {
+  /// Type of the last visited expression.
+  ty: Option<Type>,
+  scopes: Vec<Scope<'buf>>,
+  functions: HashMap<&'buf str, Vec<Param<'buf>>>,
+  errors: Vec<TypeError>,
+}
+
+impl<'buf> Checker<'buf> {
+  fn infer(&mut self, expr: &Expr<'buf>) -> Type {
+    self.ty = None;
+    self.visit(expr).ok();
+    self.ty.take().unwrap_or(Type::Unknown)
+  }
+
+  fn scope_mut(&mut self) -> &mut Scope<'buf> {
+    self.scopes.last_mut().expect("Root scope")
+  }
+
+  fn find<F>(&self, name: &str, locals: F) -> Type
+  where
+    F: for<'s> Fn(&'s Scope<'buf>) -> &'s HashMap<&'buf str, Type>,
+  {
+    self
+      .scopes
+      .iter()
+      .rev()
+      .find_map(|scope| locals(scope).get(name).copied())
+      .unwrap_or(Type::Unknown)
+  }
+
+  fn expect(&mut self, expected: Type, actual: Type, err: impl FnOnce() -> TypeError) {
+    if !expected.accepts(actual) {
+      self.errors.push(err());
+    }
+  }
+
+  fn check_body(&mut self, params: &[Param<'buf>], body: &Expr<'buf>) {
+    self.scopes.push(Scope {
+      vars: HashMap::new(),
+      params: params
+        .iter()
+        .map(|param| (param.name, param.ty.into()))
+        .collect(),
+    });
+
+    self.infer(body);
+    self.scopes.pop();
+  }
+}
+
+impl<'buf> Visitor<'buf> for Checker<'buf> {
+  type Error = Infallible;
+
+  fn visit_string(&mut self, _: &StringLit<'buf>) -> Result<(), Self::Error> {
+    self.ty = Some(Type::String);
+
+    Ok(())
+  }
+
+  fn visit_number(&mut self, _: &NumberLit) -> Result<(), Self::Error> {
+    self.ty = Some(Type::Number);
+
+    Ok(())
+  }
+
+  fn visit_bool(&mut self, _: &BoolLit) -> Result<(), Self::Error> {
+    self.ty = Some(Type::Bool);
+
+    Ok(())
+  }
+
+  fn visit_if(&mut self, expr: &If<'buf>) -> Result<(), Self::Error> {
+    self.infer(&expr.condition);
+    self.infer(&expr.body);
+
+    if let Some(expr) = &expr.fallthrough {
+      self.infer(expr);
+    }
+
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_cond(&mut self, expr: &Cond<'buf>) -> Result<(), Self::Error> {
+    for (condition, body) in &expr.arms {
+      self.infer(condition);
+      self.infer(body);
+    }
+
+    if let Some(expr) = &expr.default {
+      self.infer(expr);
+    }
+
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_call(&mut self, expr: &Call<'buf>) -> Result<(), Self::Error> {
+    let args = match &expr.args {
+      Some(Expr::Compound(args)) => args.0.iter().collect(),
+      Some(arg) => vec![arg],
+      None => vec![],
+    };
+
+    let params = self.functions.get(expr.name).cloned();
+
+    for (i, arg) in args.into_iter().enumerate() {
+      let actual = self.infer(arg);
+
+      if let Some(param) = params.as_ref().and_then(|params| params.get(i)) {
+        self.expect(param.ty.into(), actual, || TypeError::Argument {
+          function: expr.name.to_string(),
+          param: param.name.to_string(),
+          expected: param.ty.into(),
+          actual,
+        });
+      }
+    }
+
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_assign(&mut self, expr: &Assign<'buf>) -> Result<(), Self::Error> {
+    let expected = self.find(expr.ident, |scope| &scope.vars);
+    let actual = self.infer(&expr.expr);
+
+    self.expect(expected, actual, || TypeError::Binding {
+      name: expr.ident.to_string(),
+      expected,
+      actual,
+    });
+
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_const(&mut self, expr: &Const<'buf>) -> Result<(), Self::Error> {
+    let expected = Type::from(expr.ty);
+    let actual = self.infer(&expr.expr);
+
+    self.expect(expected, actual, || TypeError::Binding {
+      name: expr.ident.to_string(),
+      expected,
+      actual,
+    });
+
+    // Constants are never reassigned so the initializer's type is theirs
+    let ty = match expected {
+      Type::Unknown => actual,
+      expected => expected,
+    };
+
+    self.scope_mut().vars.insert(expr.ident, ty);
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_define(&mut self, expr: &Define<'buf>) -> Result<(), Self::Error> {
+    let expected = Type::from(expr.ty);
+    let actual = self.infer(&expr.expr);
+
+    self.expect(expected, actual, || TypeError::Binding {
+      name: expr.ident.to_string(),
+      expected,
+      actual,
+    });
+
+    self.scope_mut().vars.insert(expr.ident, expected);
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
+    self.check_body(&expr.params, &expr.body);
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_lambda(&mut self, expr: &Lambda<'buf>) -> Result<(), Self::Error> {
+    self.check_body(&expr.params, &expr.body);
+    self.ty = None;
+
+    Ok(())
+  }
+
+  fn visit_unary(&mut self, expr: &UnaryOp<'buf>) -> Result<(), Self::Error> {
+    let actual = self.infer(&expr.expr);
+
+    self.ty = Some(match expr.op {
+      UnaryOperator::Not => Type::Bool,
+      _ => {
+        self.expect(Type::Number, actual, || TypeError::Operand {
+          op: None,
+          expected: Type::Number,
+          actual,
+        });
+
+        Type::Number
+      }
+    });
+
+    Ok(())
+  }
+
+  fn visit_binary(&mut self, expr: &BinaryOp<'buf>) -> Result<(), Self::Error> {
+    let lhs = self.infer(&expr.lhs);
+    let rhs = self.infer(&expr.rhs);
+
+    self.ty = Some(match expr.op {
+      BinaryOperator::Eq | BinaryOperator::NEq => Type::Bool,
+      // Booleans are ordered with `false` before `true`
+      BinaryOperator::Lt | BinaryOperator::LtEq | BinaryOperator::Gt | BinaryOperator::GtEq
+        if lhs == Type::Bool && rhs == Type::Bool =>
+      {
+        Type::Bool
+      }
+      op => {
+        for actual in [lhs, rhs] {
+          self.expect(Type::Number, actual, || TypeError::Operand {
+            op: Some(op),
+            expected: Type::Number,
+            actual,
+          });
+        }
+
+        match op {
+          BinaryOperator::Lt | BinaryOperator::LtEq | BinaryOperator::Gt | BinaryOperator::GtEq => {
+            Type::Bool
+          }
+          _ => Type::Number,
+        }
+      }
+    });
+
+    Ok(())
+  }
+
+  fn visit_var(&mut self, expr: &RefVar<'buf>) -> Result<(), Self::Error> {
+    self.ty = Some(self.find(expr.0, |scope| &scope.vars));
+
+    Ok(())
+  }
+
+  fn visit_param(&mut self, expr: &RefParam<'buf>) -> Result<(), Self::Error> {
+    self.ty = Some(self.find(expr.0, |scope| &scope.params));
+
+    Ok(())
+  }
+}
+
+/// Collects the parameters of named functions so calls can be checked before the definition.
+struct Signatures<'a, 'buf>(&'a mut HashMap<&'buf str, Vec<Param<'buf>>>);
+
+impl<'buf> Visitor<'buf> for Signatures<'_, 'buf> {
+  type Error = Infallible;
+
+  fn visit_function(&mut self, expr: &Function<'buf>) -> Result<(), Self::Error> {
+    self.0.insert(expr.name, expr.params.clone());
+    self.visit(&expr.body)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{typecheck, Type};
+  use crate::{
+    ast::{BinaryOperator, Parser},
+    ir::error::TypeError,
+  };
+
+  #[test]
+  fn test_typecheck_mismatch() {
+    let expr = Parser::new(
+      "(fun greet (name:string) (+ name 1)) \
+       (fun double (n:number) (* n 2)) \
+       (double \"two\")",
+    )
+    .parse()
+    .unwrap();
+
+    assert_eq!(
+      typecheck(&expr).unwrap_err(),
+      vec![
+        TypeError::Operand {
+          op: Some(BinaryOperator::Add),
+          expected: Type::Number,
+          actual: Type::String,
+        },
+        TypeError::Argument {
+          function: "double".to_string(),
+          param: "n".to_string(),
+          expected: Type::Number,
+          actual: Type::String,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_typecheck_clean() {
+    let expr = Parser::new(
+      "(fun add (a:number b) (+ a b)) \
+       (var x:number (add 1 2)) \
+       (var s:string \"hi\") \
+       (const c 3) \
+       (add $x (* $c 2))",
+    )
+    .parse()
+    .unwrap();
+
+    assert_eq!(typecheck(&expr), Ok(()));
+  }
+}
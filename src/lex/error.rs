@@ -1,4 +1,7 @@
-use crate::types::Span;
+use crate::{
+  diagnostic::{Diagnostic, IntoDiagnostic},
+  types::Span,
+};
 use std::{error::Error, fmt::Display, num::ParseFloatError};
 
 pub type LexResult<'buf, T> = Result<T, LexError<'buf>>;
@@ -12,7 +15,27 @@ pub enum LexError<'buf> {
   Unexpected(String, Span<'buf>),
 }
 
-impl LexError<'_> {
+impl<'buf> LexError<'buf> {
+  /// Get where the error occurred.
+  pub fn span(&self) -> &Span<'buf> {
+    match self {
+      LexError::BadIdent(_, span)
+      | LexError::BadString(_, span)
+      | LexError::BadNumber(_, span)
+      | LexError::Unexpected(_, span) => span,
+    }
+  }
+
+  /// Move the error to another span, such as a [detached](Span::detach) one.
+  pub fn with_span<'a>(self, span: Span<'a>) -> LexError<'a> {
+    match self {
+      LexError::BadIdent(message, _) => LexError::BadIdent(message, span),
+      LexError::BadString(message, _) => LexError::BadString(message, span),
+      LexError::BadNumber(err, _) => LexError::BadNumber(err, span),
+      LexError::Unexpected(message, _) => LexError::Unexpected(message, span),
+    }
+  }
+
   /// Creates an unexpected character error.
   pub fn unexpected_char(span: Span<'_>) -> LexError<'_> {
     LexError::Unexpected("Unexpected character".to_string(), span)
@@ -43,6 +66,19 @@ impl LexError<'_> {
   }
 }
 
+impl<'buf> IntoDiagnostic<'buf> for LexError<'buf> {
+  fn into_diagnostic(self) -> Diagnostic<'buf> {
+    match self {
+      LexError::BadNumber(err, span) => {
+        Diagnostic::error("Invalid number", span).with_note(err.to_string())
+      }
+      LexError::BadIdent(message, span)
+      | LexError::BadString(message, span)
+      | LexError::Unexpected(message, span) => Diagnostic::error(message, span),
+    }
+  }
+}
+
 impl Display for LexError<'_> {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     write!(f, "{:?}", self)
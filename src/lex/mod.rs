@@ -9,6 +9,9 @@ pub use token::*;
 use crate::types::{Position, Span};
 use std::{borrow::Cow, iter::Peekable};
 
+/// Characters allowed in identifiers after their first character, besides alphanumerics and `_`.
+pub const IDENT_CHARS: &[char] = &['-', '?', '!'];
+
 /// An iterator over the tokens of a str.
 ///
 /// # Lifetimes
@@ -17,6 +20,12 @@ use std::{borrow::Cow, iter::Peekable};
 pub struct Lexer<'buf> {
   buf: &'buf str,
   chars: Peekable<LexerChars<'buf>>,
+  /// Token lexed by [`Lexer::peek`] and not yet consumed.
+  peeked: Option<Option<LexResult<'buf, Token<'buf>>>>,
+  /// Number of `(` consumed without a matching `)`.
+  depth: usize,
+  /// Characters allowed in identifiers after their first character.
+  ident_chars: Vec<char>,
 }
 
 impl<'buf> Lexer<'buf> {
@@ -28,7 +37,84 @@ impl<'buf> Lexer<'buf> {
     Self {
       buf,
       chars: LexerChars::new(buf).peekable(),
+      peeked: None,
+      depth: 0,
+      ident_chars: IDENT_CHARS.to_vec(),
+    }
+  }
+
+  /// Set the characters allowed in identifiers after their first character, replacing
+  /// [`IDENT_CHARS`].
+  ///
+  /// Since they're never leading, `-foo` is still subtraction and `foo-bar` a single name.
+  pub fn with_ident_chars(mut self, chars: &[char]) -> Self {
+    self.ident_chars = chars.to_vec();
+    self
+  }
+
+  /// Lex `buf` into each token's kind and 1-based line and column, for syntax highlighting.
+  ///
+  /// Lexing stops at the first error which is returned as [`TokenKind::Error`].
+  pub fn collect_spanned(buf: &'buf str) -> Vec<(TokenKind<'buf>, (usize, usize))> {
+    let mut tokens = Vec::new();
+
+    for token in Lexer::new(buf) {
+      match token {
+        Ok(Token(span, kind)) => tokens.push((kind, (span.begin().line, span.begin().column))),
+        Err(err) => {
+          let pos = err.span().begin();
+          tokens.push((TokenKind::Error, (pos.line, pos.column)));
+
+          break;
+        }
+      }
+    }
+
+    tokens
+  }
+
+  /// Render tokens back into source separated by spaces, lexing it gives the same token kinds.
+  pub fn render(tokens: &[Token<'_>]) -> String {
+    tokens
+      .iter()
+      .map(|token| token.1.to_string())
+      .collect::<Vec<_>>()
+      .join(" ")
+  }
+
+  /// Get the next token without consuming it.
+  pub fn peek(&mut self) -> Option<&LexResult<'buf, Token<'buf>>> {
+    if self.peeked.is_none() {
+      self.peeked = Some(self.lex_next());
+    }
+
+    self.peeked.as_ref().and_then(Option::as_ref)
+  }
+
+  /// Consume the next token, returning the peeked token first if there is one.
+  pub fn next_token(&mut self) -> Option<LexResult<'buf, Token<'buf>>> {
+    let token = match self.peeked.take() {
+      Some(token) => token,
+      None => self.lex_next(),
+    };
+
+    match &token {
+      Some(Ok(Token(_, TokenKind::LParen))) => self.depth += 1,
+      Some(Ok(Token(_, TokenKind::RParen))) => self.depth = self.depth.saturating_sub(1),
+      _ => {}
     }
+
+    token
+  }
+
+  /// Get the number of `(` consumed without a matching `)`.
+  pub fn depth(&self) -> usize {
+    self.depth
+  }
+
+  fn lex_next(&mut self) -> Option<LexResult<'buf, Token<'buf>>> {
+    self.eat_whitespace_and_comments();
+    self.lex_token().transpose()
   }
 
   fn span_at(&mut self, beg: Position) -> Span<'buf> {
@@ -83,6 +169,14 @@ impl<'buf> Lexer<'buf> {
 
           self.chars.next();
         }
+        // Qualified names such as `module.function`
+        Some((_, '.')) if has_alpha_or_underscore => {
+          self.chars.next();
+        }
+        // Names such as `empty?` or `foo-bar`
+        Some((_, ch)) if has_alpha_or_underscore && self.ident_chars.contains(ch) => {
+          self.chars.next();
+        }
         Some((end, _)) => return Ok(&self.buf[beg.offset..end.offset]),
         None => return Ok(&self.buf[beg.offset..]),
       }
@@ -151,7 +245,7 @@ impl<'buf> Lexer<'buf> {
   }
 
   /// Consume next token and return.
-  fn next_token(&mut self) -> LexResult<'buf, Option<Token<'buf>>> {
+  fn lex_token(&mut self) -> LexResult<'buf, Option<Token<'buf>>> {
     // Position before token start
     let (beg, kind) = match self.chars.next() {
       // Prioritize parens
@@ -172,13 +266,12 @@ impl<'buf> Lexer<'buf> {
       Some((pos, ch)) if ch.is_numeric() => (pos, TokenKind::Number(self.eat_number(pos)?)),
 
       // Simple operators
-      Some((pos, '*')) => (pos, TokenKind::Mul),
-      Some((pos, '/')) => (pos, TokenKind::Div),
       Some((pos, '^')) => (pos, TokenKind::Pow),
       Some((pos, '%')) => (pos, TokenKind::Mod),
       Some((pos, '|')) => (pos, TokenKind::BOr),
       Some((pos, '&')) => (pos, TokenKind::BAnd),
       Some((pos, '!')) => (pos, TokenKind::BNot),
+      Some((pos, ':')) => (pos, TokenKind::Colon),
 
       // Complex operators
       Some((pos, '+')) => (
@@ -189,6 +282,11 @@ impl<'buf> Lexer<'buf> {
             self.chars.next();
             TokenKind::AddInc
           }
+          Some((_, '=')) => {
+            // Consume peeked `=`
+            self.chars.next();
+            TokenKind::AddAssign
+          }
           _ => TokenKind::Add,
         },
       ),
@@ -200,9 +298,36 @@ impl<'buf> Lexer<'buf> {
             self.chars.next();
             TokenKind::SubInc
           }
+          Some((_, '=')) => {
+            // Consume peeked `=`
+            self.chars.next();
+            TokenKind::SubAssign
+          }
           _ => TokenKind::Sub,
         },
       ),
+      Some((pos, '*')) => (
+        pos,
+        match self.chars.peek() {
+          Some((_, '=')) => {
+            // Consume peeked `=`
+            self.chars.next();
+            TokenKind::MulAssign
+          }
+          _ => TokenKind::Mul,
+        },
+      ),
+      Some((pos, '/')) => (
+        pos,
+        match self.chars.peek() {
+          Some((_, '=')) => {
+            // Consume peeked `=`
+            self.chars.next();
+            TokenKind::DivAssign
+          }
+          _ => TokenKind::Div,
+        },
+      ),
       Some((pos, '<')) => (
         pos,
         match self.chars.peek() {
@@ -256,15 +381,36 @@ impl<'buf> Iterator for Lexer<'buf> {
   type Item = LexResult<'buf, Token<'buf>>;
 
   fn next(&mut self) -> Option<Self::Item> {
-    self.eat_whitespace_and_comments();
-    self.next_token().transpose()
+    self.next_token()
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::Lexer;
-  use crate::{lex::LexError, types::Position};
+  use crate::{
+    lex::{LexError, TokenKind},
+    types::Position,
+  };
+
+  #[test]
+  pub fn test_collect_spanned() {
+    assert_eq!(
+      Lexer::collect_spanned("(+ 1 2)"),
+      vec![
+        (TokenKind::LParen, (1, 1)),
+        (TokenKind::Add, (1, 2)),
+        (TokenKind::Number(1.0), (1, 4)),
+        (TokenKind::Number(2.0), (1, 6)),
+        (TokenKind::RParen, (1, 7)),
+      ]
+    );
+
+    assert_eq!(
+      Lexer::collect_spanned("(\n  \"a)"),
+      vec![(TokenKind::LParen, (1, 1)), (TokenKind::Error, (2, 3))]
+    );
+  }
 
   #[test]
   pub fn test_eat_whitespace_end_at_non_whitespace() {
@@ -325,19 +471,57 @@ mod tests {
 
   #[test]
   pub fn test_eat_ident_end_at_non_var() {
-    let mut lexer = Lexer::new("$aeiöu_0123!");
+    let mut lexer = Lexer::new("$aeiöu_0123)");
     // Consume leading `$` character
     assert_eq!(lexer.chars.next().unwrap().1, '$');
     // Consume var ident
     let var = lexer
       .eat_ident(Position::default().extend('$'), false)
       .unwrap();
-    assert_eq!(lexer.chars.next().unwrap().1, '!');
+    assert_eq!(lexer.chars.next().unwrap().1, ')');
 
     // Check last character in buffer
     assert_eq!(var, "aeiöu_0123");
   }
 
+  #[test]
+  pub fn test_eat_ident_qualified() {
+    let mut lexer = Lexer::new("lib.add)");
+    let ident = lexer.eat_ident(Position::default(), false).unwrap();
+
+    assert_eq!(ident, "lib.add");
+    assert_eq!(lexer.chars.next().unwrap().1, ')');
+  }
+
+  #[test]
+  pub fn test_eat_ident_chars() {
+    let idents = |src| {
+      Lexer::new(src)
+        .map(|token| token.unwrap().1)
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(idents("empty?"), vec![TokenKind::Ident("empty?")]);
+    assert_eq!(idents("foo-bar"), vec![TokenKind::Ident("foo-bar")]);
+    assert_eq!(idents("$done!"), vec![TokenKind::Var("done!")]);
+    assert_eq!(
+      idents("(- x)"),
+      vec![
+        TokenKind::LParen,
+        TokenKind::Sub,
+        TokenKind::Ident("x"),
+        TokenKind::RParen
+      ]
+    );
+
+    let tokens = Lexer::new("foo-bar")
+      .with_ident_chars(&['?'])
+      .map(|token| token.unwrap().1)
+      .collect::<Vec<_>>();
+
+    assert_eq!(tokens[0], TokenKind::Ident("foo"));
+  }
+
   #[test]
   pub fn test_eat_ident_has_alpha_or_underscore_fail() {
     let mut lexer = Lexer::new("0");
@@ -426,6 +610,66 @@ mod tests {
     assert_eq!(lexer.chars.next(), None);
   }
 
+  #[test]
+  pub fn test_lex_compound_assign() {
+    let kinds = Lexer::new("+= -= *= /= + - * /")
+      .map(|token| token.unwrap().1)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenKind::AddAssign,
+        TokenKind::SubAssign,
+        TokenKind::MulAssign,
+        TokenKind::DivAssign,
+        TokenKind::Add,
+        TokenKind::Sub,
+        TokenKind::Mul,
+        TokenKind::Div,
+      ]
+    );
+  }
+
+  #[test]
+  pub fn test_lex_colon() {
+    let kinds = Lexer::new("a:number")
+      .map(|token| token.unwrap().1)
+      .collect::<Vec<_>>();
+
+    assert_eq!(
+      kinds,
+      vec![
+        TokenKind::Ident("a"),
+        TokenKind::Colon,
+        TokenKind::Ident("number"),
+      ]
+    );
+  }
+
+  #[test]
+  pub fn test_peek() {
+    let mut lexer = Lexer::new("(add 1)");
+
+    assert_eq!(lexer.next().unwrap().unwrap().1, TokenKind::LParen);
+    assert_eq!(
+      lexer.peek().unwrap().as_ref().unwrap().1,
+      TokenKind::Ident("add")
+    );
+    assert_eq!(
+      lexer.peek().unwrap().as_ref().unwrap().1,
+      TokenKind::Ident("add")
+    );
+    assert_eq!(
+      lexer.next_token().unwrap().unwrap().1,
+      TokenKind::Ident("add")
+    );
+    assert_eq!(lexer.next().unwrap().unwrap().1, TokenKind::Number(1.0));
+    assert_eq!(lexer.next().unwrap().unwrap().1, TokenKind::RParen);
+    assert!(lexer.peek().is_none());
+    assert!(lexer.next().is_none());
+  }
+
   #[test]
   pub fn test_lex_errors_chal() {
     Lexer::new(include_str!("../../data/errors.chal"))
@@ -468,6 +712,38 @@ mod tests {
       .unwrap();
   }
 
+  /// Lex `buf`, render the tokens and check lexing them again gives the same kinds.
+  fn assert_round_trip(buf: &str) {
+    let tokens = Lexer::new(buf).collect::<Result<Vec<_>, _>>().unwrap();
+    let rendered = Lexer::render(&tokens);
+    let relexed = Lexer::new(&rendered)
+      .collect::<Result<Vec<_>, _>>()
+      .unwrap();
+
+    let kinds = tokens.into_iter().map(|token| token.1).collect::<Vec<_>>();
+    let relexed = relexed.into_iter().map(|token| token.1).collect::<Vec<_>>();
+
+    assert_eq!(kinds, relexed, "Rendered as {}", rendered);
+  }
+
+  #[test]
+  pub fn test_round_trip() {
+    let sources = [
+      include_str!("../../data/errors.chal"),
+      include_str!("../../data/fizzbuzz.chal"),
+      include_str!("../../data/math.chal"),
+      include_str!("../../data/recursion.chal"),
+      include_str!("../../data/string.chal"),
+      include_str!("../../data/whitespace.chal"),
+      "(% 5 2) (! 1) (^ 2 3) (++ x) (-- x) (+= x 1) (<< 1 2) (>= 1 2) (var x:number 1.5)",
+      "(print 'say \"hi\"') (print \"it's\") (m.f $y)",
+    ];
+
+    for buf in &sources {
+      assert_round_trip(buf);
+    }
+  }
+
   #[test]
   #[cfg_attr(miri, ignore)]
   pub fn test_lex_stress() {
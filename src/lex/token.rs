@@ -76,6 +76,26 @@ impl<'buf> Token<'buf> {
     matches!(self.1, TokenKind::SubInc)
   }
 
+  /// Returns `true` if the token is [`TokenKind::AddAssign`]
+  pub fn is_add_assign(&self) -> bool {
+    matches!(self.1, TokenKind::AddAssign)
+  }
+
+  /// Returns `true` if the token is [`TokenKind::SubAssign`]
+  pub fn is_sub_assign(&self) -> bool {
+    matches!(self.1, TokenKind::SubAssign)
+  }
+
+  /// Returns `true` if the token is [`TokenKind::MulAssign`]
+  pub fn is_mul_assign(&self) -> bool {
+    matches!(self.1, TokenKind::MulAssign)
+  }
+
+  /// Returns `true` if the token is [`TokenKind::DivAssign`]
+  pub fn is_div_assign(&self) -> bool {
+    matches!(self.1, TokenKind::DivAssign)
+  }
+
   /// Returns `true` if the token is [`TokenKind::BOr`]
   pub fn is_binary_or(&self) -> bool {
     matches!(self.1, TokenKind::BOr)
@@ -120,6 +140,11 @@ impl<'buf> Token<'buf> {
   pub fn is_gt_eq(&self) -> bool {
     matches!(self.1, TokenKind::GtEq)
   }
+
+  /// Returns `true` if the token is [`TokenKind::Colon`]
+  pub fn is_colon(&self) -> bool {
+    matches!(self.1, TokenKind::Colon)
+  }
 }
 
 impl<'buf> Token<'buf> {
@@ -175,6 +200,15 @@ pub enum TokenKind<'buf> {
   /// Incremental subtract operator
   SubInc,
 
+  /// Add assign operator
+  AddAssign,
+  /// Subtract assign operator
+  SubAssign,
+  /// Multiply assign operator
+  MulAssign,
+  /// Divide assign operator
+  DivAssign,
+
   /// Binary or operator
   BOr,
   /// Binary not operator
@@ -194,6 +228,14 @@ pub enum TokenKind<'buf> {
   Gt,
   /// Greater than equal to operator
   GtEq,
+
+  /// Type annotation separator
+  Colon,
+
+  /// Input which failed to lex, only produced by [`Lexer::collect_spanned`]
+  ///
+  /// [`Lexer::collect_spanned`]: crate::lex::Lexer::collect_spanned
+  Error,
 }
 
 impl<'buf> TokenKind<'buf> {
@@ -207,6 +249,8 @@ impl std::fmt::Display for TokenKind<'_> {
     match self {
       TokenKind::LParen => write!(f, "("),
       TokenKind::RParen => write!(f, ")"),
+      // Strings can't escape quotes so ones containing `"` are written in single quotes
+      TokenKind::String(inner) if inner.contains('"') => write!(f, "'{}'", inner),
       TokenKind::String(inner) => write!(f, "\"{}\"", inner),
       TokenKind::Number(inner) => write!(f, "{}", inner),
       TokenKind::Var(inner) => write!(f, "${}", inner),
@@ -216,11 +260,15 @@ impl std::fmt::Display for TokenKind<'_> {
       TokenKind::Div => write!(f, "/"),
       TokenKind::Mul => write!(f, "*"),
       TokenKind::Pow => write!(f, "^"),
-      TokenKind::Mod => write!(f, "$"),
+      TokenKind::Mod => write!(f, "%"),
       TokenKind::AddInc => write!(f, "++"),
       TokenKind::SubInc => write!(f, "--"),
+      TokenKind::AddAssign => write!(f, "+="),
+      TokenKind::SubAssign => write!(f, "-="),
+      TokenKind::MulAssign => write!(f, "*="),
+      TokenKind::DivAssign => write!(f, "/="),
       TokenKind::BOr => write!(f, "|"),
-      TokenKind::BNot => write!(f, "^"),
+      TokenKind::BNot => write!(f, "!"),
       TokenKind::BAnd => write!(f, "&"),
       TokenKind::BLShift => write!(f, "<<"),
       TokenKind::BRShift => write!(f, ">>"),
@@ -228,6 +276,8 @@ impl std::fmt::Display for TokenKind<'_> {
       TokenKind::LtEq => write!(f, "<="),
       TokenKind::Gt => write!(f, ">"),
       TokenKind::GtEq => write!(f, ">="),
+      TokenKind::Colon => write!(f, ":"),
+      TokenKind::Error => write!(f, "<error>"),
     }
   }
 }
@@ -1,6 +1,9 @@
 pub mod ast;
+pub mod check;
+pub mod diagnostic;
 pub mod ir;
 pub mod lex;
+pub mod timing;
 pub mod types;
 pub mod util;
 pub mod vm;
@@ -0,0 +1,134 @@
+//! Running a source while measuring how long each phase takes.
+
+use crate::{
+  ast::Parser,
+  ir::compile_expr,
+  lex::Lexer,
+  vm::{
+    error::{VmError, VmResult},
+    VirtualMachine,
+  },
+};
+use std::{
+  io::{self, Write},
+  time::{Duration, Instant},
+};
+
+/// How long each phase of running a source took.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+  pub lex: Duration,
+  pub parse: Duration,
+  /// Lowering the syntax tree to instructions.
+  pub lower: Duration,
+  /// Resolving imports to built-ins, see [`VirtualMachine::link`].
+  pub link: Duration,
+  pub run: Duration,
+}
+
+impl Timings {
+  pub fn total(&self) -> Duration {
+    self.lex + self.parse + self.lower + self.link + self.run
+  }
+}
+
+/// Lexes, parses, compiles, links and runs a source.
+pub struct Runner {
+  timing: bool,
+  output: Box<dyn Write>,
+}
+
+impl Runner {
+  pub fn new() -> Self {
+    Self {
+      timing: false,
+      output: Box::new(io::stdout()),
+    }
+  }
+
+  /// Measure how long each phase takes, see [`Runner::run`].
+  pub fn with_timing(mut self, enabled: bool) -> Self {
+    self.timing = enabled;
+    self
+  }
+
+  /// Set where `print`, its variants and `debug_stack` write to.
+  pub fn with_output(mut self, writer: Box<dyn Write>) -> Self {
+    self.output = writer;
+    self
+  }
+
+  /// Run `src`, getting how long each phase took if timing is enabled.
+  ///
+  /// Sources which fail to lex, parse or compile fail with [`VmError::Compile`]. Lexing is timed
+  /// as a pass of its own, so the parse time includes lexing again.
+  pub fn run(self, src: &str) -> VmResult<Option<Timings>> {
+    let mut timings = Timings::default();
+
+    time(&mut timings.lex, || {
+      Lexer::new(src).try_for_each(|token| token.map(drop))
+    })
+    .map_err(|err| VmError::Compile(err.to_string()))?;
+
+    let expr = time(&mut timings.parse, || Parser::new(src).parse())
+      .map_err(|err| VmError::Compile(err.to_string()))?;
+
+    let mut program = time(&mut timings.lower, || compile_expr(&expr))
+      .map_err(|err| VmError::Compile(err.to_string()))?;
+
+    let mut vm = VirtualMachine::new(&[]).with_output(self.output);
+
+    time(&mut timings.link, || vm.link(&mut program.code))?;
+    vm.reload(&program);
+    time(&mut timings.run, || vm.run())?;
+
+    Ok(self.timing.then_some(timings))
+  }
+}
+
+impl Default for Runner {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Call `f`, adding how long it took to `elapsed`.
+fn time<T>(elapsed: &mut Duration, f: impl FnOnce() -> T) -> T {
+  let start = Instant::now();
+  let value = f();
+  *elapsed += start.elapsed();
+
+  value
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Runner;
+  use crate::vm::error::VmError;
+  use std::{io, time::Duration};
+
+  #[test]
+  fn test_timings() {
+    let runner = || Runner::new().with_output(Box::new(io::sink()));
+    let src = "(fun fib (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2))))) (print (fib 15))";
+
+    let timings = runner().with_timing(true).run(src).unwrap().unwrap();
+
+    for phase in [
+      timings.lex,
+      timings.parse,
+      timings.lower,
+      timings.link,
+      timings.run,
+    ] {
+      assert!(phase > Duration::ZERO);
+    }
+
+    assert!(timings.total() >= timings.run);
+    assert_eq!(runner().run(src).unwrap(), None);
+    assert!(matches!(
+      runner().run("(print \"unterminated)"),
+      Err(VmError::Compile(_))
+    ));
+  }
+}
@@ -37,7 +37,7 @@ impl Default for Position {
   fn default() -> Self {
     Self {
       line: 1,
-      column: 0,
+      column: 1,
       offset: 0,
     }
   }
@@ -62,38 +62,83 @@ impl<'buf> Span<'buf> {
   pub fn new(beg: Position, end: Position, buf: &'buf str) -> Self {
     Self { beg, end, buf }
   }
+
+  /// Get the position of the first character.
+  pub fn begin(&self) -> Position {
+    self.beg
+  }
+
+  /// Get the position just past the last character.
+  pub fn end(&self) -> Position {
+    self.end
+  }
+
+  /// Copy the span without the source it's in, keeping only its position.
+  pub fn detach(&self) -> Span<'static> {
+    Span {
+      beg: self.beg,
+      end: self.end,
+      buf: "",
+    }
+  }
+
+  /// Get the whole source the span is in, see [`Position::offset`] for where the span is.
+  pub fn source(&self) -> &'buf str {
+    self.buf
+  }
 }
 
 impl std::fmt::Debug for Span<'_> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    write!(f, "line {}, column {}", self.end.line, self.end.column)
+    write!(f, "line {}, column {}", self.beg.line, self.beg.column)
   }
 }
 
 impl std::fmt::Display for Span<'_> {
   fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-    let beg_pad = self.beg.offset.saturating_sub(30);
-    let beg_pad = &self.buf[beg_pad..self.beg.offset]
+    // Offsets are kept within the buffer so a span of another source can't slice out of bounds
+    let end = floor_char_boundary(self.buf, self.end.offset);
+    let beg = floor_char_boundary(self.buf, cmp::min(self.beg.offset, end));
+
+    // At most 30 bytes of the line either side of the span
+    let beg_pad = floor_char_boundary(self.buf, beg.saturating_sub(30));
+    let beg_pad = self.buf[beg_pad..beg]
       .rfind('\n')
-      .unwrap_or(beg_pad);
+      .map_or(beg_pad, |at| beg_pad + at + 1);
 
-    let end_pad = self.end.offset.saturating_add(30);
-    let end_pad = cmp::min(end_pad, self.buf.len());
-    let end_pad = &self.buf[self.end.offset..end_pad]
+    let mut end_pad = cmp::min(end.saturating_add(30), self.buf.len());
+    while !self.buf.is_char_boundary(end_pad) {
+      end_pad += 1;
+    }
+    let end_pad = self.buf[end..end_pad]
       .find('\n')
-      .unwrap_or(end_pad);
+      .map_or(end_pad, |at| end + at);
+
+    // The caret line is indented past the line number and separator
+    let gutter = format!("{} | ", self.beg.line);
 
-    writeln!(f, "--> line {}, column {}", self.end.line, self.end.column,)?;
-    writeln!(f, "{} | {}", self.beg.line, &self.buf[*beg_pad..*end_pad])?;
+    writeln!(f, "--> line {}, column {}", self.beg.line, self.beg.column)?;
+    writeln!(f, "{}{}", gutter, &self.buf[beg_pad..end_pad])?;
     writeln!(
       f,
       "{}{}",
-      " ".repeat(self.beg.offset - beg_pad),
-      "^".repeat(self.end.offset - self.beg.offset)
+      " ".repeat(gutter.len() + self.buf[beg_pad..beg].chars().count()),
+      "^".repeat(cmp::max(self.buf[beg..end].chars().count(), 1))
     )
   }
 }
 
+/// Get the closest character boundary of `buf` at or before `offset`, at most `buf.len()`.
+fn floor_char_boundary(buf: &str, offset: usize) -> usize {
+  let mut offset = cmp::min(offset, buf.len());
+
+  while !buf.is_char_boundary(offset) {
+    offset -= 1;
+  }
+
+  offset
+}
+
 pub trait Positional {
   fn pos(&self) -> Position;
 }
@@ -306,3 +351,62 @@ where
     self.iter.as_str()
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::{Position, Span};
+
+  #[test]
+  fn test_span_accessors() {
+    let buf = "(var x\n  1)";
+    let beg = Position::default().extend_str("(var ");
+    let end = beg.extend('x');
+    let span = Span::new(beg, end, buf);
+
+    assert_eq!(span.begin(), beg);
+    assert_eq!(span.end(), end);
+    assert_eq!(span.source(), buf);
+    assert_eq!(&span.source()[span.begin().offset..span.end().offset], "x");
+    assert_eq!((span.end().line, span.end().column), (1, 7));
+  }
+
+  #[test]
+  fn test_span_display_bounds() {
+    let buf = "(print \"héllo\")";
+    let end = Position::default().extend_str(buf);
+    let beg = Position::default().extend_str("(print ");
+
+    // A span ending exactly at the end of the buffer
+    assert_eq!(
+      Span::new(beg, end, buf).to_string(),
+      "--> line 1, column 8\n1 | (print \"héllo\")\n           ^^^^^^^^\n"
+    );
+
+    // Offsets past the end of the buffer or inside a character are clamped
+    let past = Position {
+      offset: usize::MAX,
+      ..end
+    };
+    let inside = Position {
+      offset: buf.find('é').unwrap() + 1,
+      ..beg
+    };
+
+    Span::new(past, past, buf).to_string();
+    Span::new(inside, past, buf).to_string();
+    Span::new(beg, end, "").to_string();
+  }
+
+  #[test]
+  fn test_span_reports_start() {
+    let buf = "(print\n  \"multi\nline\")";
+    let beg = Position::default().extend_str("(print\n  ");
+    let end = beg.extend_str("\"multi\nline\"");
+
+    assert_eq!(end.line, 3);
+    assert_eq!(
+      format!("{:?}", Span::new(beg, end, buf)),
+      "line 2, column 3"
+    );
+  }
+}
@@ -0,0 +1,114 @@
+//! Hex and base64 encodings of binary data.
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encode `bytes` as lowercase hex.
+pub fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decode hex of either case, `None` unless every character is a hex digit in pairs.
+pub fn from_hex(value: &str) -> Option<Vec<u8>> {
+  if !value.len().is_multiple_of(2) {
+    return None;
+  }
+
+  value
+    .as_bytes()
+    .chunks(2)
+    .map(|pair| {
+      let pair = std::str::from_utf8(pair).ok()?;
+      u8::from_str_radix(pair, 16).ok()
+    })
+    .collect()
+}
+
+/// Encode `bytes` as padded standard base64.
+pub fn to_base64(bytes: &[u8]) -> String {
+  let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+
+  for chunk in bytes.chunks(3) {
+    let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+      group | (*byte as u32) << (16 - i * 8)
+    });
+
+    // A chunk of `n` bytes fills `n + 1` characters, the rest are padding
+    for i in 0..4 {
+      match i <= chunk.len() {
+        true => encoded.push(BASE64[(group >> (18 - i * 6)) as usize & 0x3f] as char),
+        false => encoded.push('='),
+      }
+    }
+  }
+
+  encoded
+}
+
+/// Decode padded standard base64, `None` if it's malformed.
+pub fn from_base64(value: &str) -> Option<Vec<u8>> {
+  let value = value.as_bytes();
+
+  if !value.len().is_multiple_of(4) {
+    return None;
+  }
+
+  let mut bytes = Vec::with_capacity(value.len() / 4 * 3);
+
+  for (i, chunk) in value.chunks(4).enumerate() {
+    let is_last = i + 1 == value.len() / 4;
+    let padding = chunk.iter().rev().take_while(|c| **c == b'=').count();
+
+    if padding > 2 || (padding > 0 && !is_last) {
+      return None;
+    }
+
+    let mut group = 0u32;
+
+    for (i, c) in chunk[..4 - padding].iter().enumerate() {
+      let sextet = BASE64.iter().position(|b| b == c)? as u32;
+      group |= sextet << (18 - i * 6);
+    }
+
+    bytes.extend_from_slice(&group.to_be_bytes()[1..4 - padding]);
+  }
+
+  Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{from_base64, from_hex, to_base64, to_hex};
+
+  #[test]
+  fn test_hex() {
+    assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+    assert_eq!(from_hex("00AB10"), Some(vec![0x00, 0xab, 0x10]));
+    assert_eq!(from_hex(""), Some(vec![]));
+    assert_eq!(from_hex("abc"), None);
+    assert_eq!(from_hex("zz"), None);
+    assert_eq!(from_hex("é1"), None);
+  }
+
+  #[test]
+  fn test_base64() {
+    let cases = [
+      ("", ""),
+      ("f", "Zg=="),
+      ("fo", "Zm8="),
+      ("foo", "Zm9v"),
+      ("foob", "Zm9vYg=="),
+      ("fooba", "Zm9vYmE="),
+      ("foobar", "Zm9vYmFy"),
+    ];
+
+    for (decoded, encoded) in &cases {
+      assert_eq!(to_base64(decoded.as_bytes()), *encoded);
+      assert_eq!(from_base64(encoded).as_deref(), Some(decoded.as_bytes()));
+    }
+
+    assert_eq!(from_base64("Zg="), None);
+    assert_eq!(from_base64("Z==="), None);
+    assert_eq!(from_base64("Zg==Zm9v"), None);
+    assert_eq!(from_base64("Zm9*"), None);
+  }
+}
@@ -1,3 +1,4 @@
 //! Utility methods
 
+pub mod encoding;
 pub mod uuid;
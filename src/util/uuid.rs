@@ -42,6 +42,16 @@ impl Uuid {
   pub fn nil() -> Self {
     Self(0)
   }
+
+  /// Create [`Uuid`] from its integer representation.
+  pub fn from_u128(value: u128) -> Self {
+    Self(value)
+  }
+
+  /// Get the integer representation of [`Uuid`].
+  pub fn as_u128(self) -> u128 {
+    self.0
+  }
 }
 
 impl Default for Uuid {
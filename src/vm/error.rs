@@ -1,9 +1,65 @@
+use super::types::Value;
+use crate::{
+  diagnostic::{Diagnostic, IntoDiagnostic},
+  ir::{instr::Label, scope::Local},
+  types::Span,
+};
 use std::{error::Error, fmt::Display};
 
 pub type VmResult<T> = Result<T, VmError>;
 
 #[derive(Debug, Clone)]
-pub enum VmError {}
+pub enum VmError {
+  /// Attempted to call a value that isn't a function or built-in.
+  NotCallable(Value),
+  /// Called a function with the wrong number of arguments.
+  ArityMismatch { expected: usize, actual: usize },
+  /// The number of `format` placeholders and arguments differ.
+  FormatMismatch { placeholders: usize, args: usize },
+  /// A built-in was passed a value of the wrong type.
+  TypeError {
+    expected: &'static str,
+    actual: Value,
+  },
+  /// A string passed to `parse_number` isn't a number.
+  ParseNumber(String),
+  /// A string passed to `from_hex` or `from_base64` isn't valid hex or base64.
+  Decode(String),
+  /// A script passed to `eval` failed to parse or compile.
+  Compile(String),
+  /// An arithmetic operator was applied to values other than numbers.
+  InvalidOperands(Value, Value),
+  /// Jumped to an address past the end of the script.
+  BadJumpTarget(usize),
+  /// Jumped to or referenced a function by a label that isn't in the script.
+  UnknownLabel(Label),
+  /// Loaded a local that hasn't been stored in the current call or the top-level.
+  UndefinedLocal(Local),
+  /// Returned with other than exactly the return value above the arguments of the call.
+  UnbalancedReturn { expected: usize, actual: usize },
+  /// Returned while no call was in progress.
+  ReturnOutsideFunction,
+  /// More calls were in progress than the maximum call depth.
+  CallDepthExceeded(usize),
+  /// Pushed a value onto a full stack of the given size.
+  StackOverflow(usize),
+  /// Popped a value off an empty stack.
+  StackUnderflow,
+  /// An integer operation overflowed with overflow checks enabled.
+  IntegerOverflow,
+  /// A `range` step of zero or one moving away from the end of the range.
+  BadRangeStep(f64),
+  /// Loaded a top-level variable that hasn't been stored by this or an earlier run.
+  UndefinedGlobal(String),
+  /// Loaded or linked an import that isn't a registered built-in.
+  UndefinedImport(String),
+  /// Called a built-in by a name that isn't registered.
+  UndefinedBuiltin(String),
+  /// Loaded a built-in by an index past the end of the built-in table.
+  UnknownBuiltin(u16),
+  /// Loaded or stored an argument past the end of the current call's argument window.
+  UnknownArg(u8),
+}
 
 impl Display for VmError {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -12,3 +68,39 @@ impl Display for VmError {
 }
 
 impl Error for VmError {}
+
+impl VmError {
+  /// Attach the location of the instruction which failed, if known.
+  pub fn at(self, span: Option<Span<'_>>) -> SpannedError<'_> {
+    SpannedError { error: self, span }
+  }
+}
+
+/// A [`VmError`] along with the location of the instruction which failed.
+#[derive(Debug, Clone)]
+pub struct SpannedError<'buf> {
+  pub error: VmError,
+  /// `None` for programs without source locations.
+  pub span: Option<Span<'buf>>,
+}
+
+impl Display for SpannedError<'_> {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match &self.span {
+      Some(span) => write!(f, "{}\n{}", self.error, span),
+      None => write!(f, "{}", self.error),
+    }
+  }
+}
+
+impl Error for SpannedError<'_> {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    Some(&self.error)
+  }
+}
+
+impl<'buf> IntoDiagnostic<'buf> for SpannedError<'buf> {
+  fn into_diagnostic(self) -> Diagnostic<'buf> {
+    Diagnostic::error(self.error.to_string(), self.span.unwrap_or_else(Span::eof))
+  }
+}
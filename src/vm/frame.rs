@@ -0,0 +1,32 @@
+//! Function call activation records.
+
+use super::types::Value;
+use crate::ir::scope::Local;
+use std::collections::HashMap;
+
+/// The state of a single function call.
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+  /// Address to resume at after returning.
+  pub ret: usize,
+  /// Stack length before the call's arguments were pushed.
+  pub base: usize,
+  /// Number of values above `base` read by [`Instruction::LdArg`], the arguments followed by any
+  /// captured values.
+  ///
+  /// [`Instruction::LdArg`]: crate::ir::instr::Instruction::LdArg
+  pub args: usize,
+  /// Locals defined during this call.
+  pub locals: HashMap<Local, Value>,
+}
+
+impl Frame {
+  pub fn new(ret: usize, base: usize, args: usize) -> Self {
+    Self {
+      ret,
+      base,
+      args,
+      locals: HashMap::new(),
+    }
+  }
+}
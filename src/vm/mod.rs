@@ -1,21 +1,48 @@
 pub mod error;
+pub mod frame;
+pub mod rng;
 pub mod stack;
 pub mod types;
 
 use self::{
-  error::VmResult,
+  error::{SpannedError, VmError, VmResult},
+  frame::Frame,
+  rng::Rng,
   stack::Stack,
   types::{Step, Value},
 };
-use crate::ir::{
-  instr::{Instruction, Label},
-  scope::Local,
+use crate::{
+  ast::Parser,
+  ir::{
+    compile_expr,
+    instr::{Constant, Instruction, Label},
+    scope::Local,
+    CompiledProgram,
+  },
+  types::Span,
+  util::encoding,
+};
+use std::{
+  cell::RefCell,
+  cmp::Ordering,
+  collections::HashMap,
+  convert::{TryFrom, TryInto},
+  io::{self, BufRead, BufReader, Write},
+  rc::Rc,
+  time::Instant,
 };
-use std::{collections::HashMap, rc::Rc};
 
-type BuiltIn = dyn Fn(&mut Stack) -> VmResult<()>;
+type BuiltIn = dyn Fn(&[Value]) -> VmResult<Value>;
 type BuiltInRc = Rc<BuiltIn>;
 
+/// Get the arguments of a built-in as an array, failing unless there are exactly `N`.
+pub fn expect_args<const N: usize>(args: &[Value]) -> VmResult<&[Value; N]> {
+  args.try_into().map_err(|_| VmError::ArityMismatch {
+    expected: N,
+    actual: args.len(),
+  })
+}
+
 macro_rules! jmp_if {
   ($to:ident, $stack:expr, $a:ident $condition:tt $b:ident) => {{
     let a = $stack.pop()?;
@@ -41,40 +68,96 @@ macro_rules! run_log_op {
 }
 
 macro_rules! run_arith_op {
-  ($stack:expr, $a:ident $op:tt $b:ident) => {
-    match ($stack.pop()?, $stack.pop()?) {
-      (Value::Number($a), Value::Number($b)) => {
-        $stack.push(Value::Number($a $op $b))?;
-        Ok(Step::Next)
-      }
-      _ => todo!(),
-    }
-  };
-  ($stack:expr, $a:ident.$op:tt($b:ident)) => {
-    match ($stack.pop()?, $stack.pop()?) {
-      (Value::Number($a), Value::Number($b)) => {
-        $stack.push(Value::Number($a.$op($b)))?;
-        Ok(Step::Next)
-      }
-      _ => todo!(),
-    }
-  };
+  ($vm:expr, $a:ident $op:tt $b:ident) => {{
+    let ($a, $b) = $vm.pop_numbers()?;
+    $vm.stack.push(Value::Number($a $op $b))?;
+
+    Ok(Step::Next)
+  }};
+  ($vm:expr, $a:ident.$op:tt($b:ident)) => {{
+    let ($a, $b) = $vm.pop_numbers()?;
+    $vm.stack.push(Value::Number($a.$op($b)))?;
+
+    Ok(Step::Next)
+  }};
 }
 
 macro_rules! run_arith_op_fn {
-  ($stack:expr, $a:ident $op:tt $b:ident) => {
-    match ($stack.pop()?, $stack.pop()?) {
-      (Value::Number(a), Value::Number(b)) => {
-        let $a = a as u64;
-        let $b = b as u64;
-        let c = ($a $op $b) as f64;
-
-        $stack.push(Value::Number(c))?;
-        Ok(Step::Next)
+  ($vm:expr, $a:ident $op:tt $b:ident) => {{
+    let (a, b) = $vm.pop_numbers()?;
+    let $a = Value::Number(a).as_u64()?;
+    let $b = Value::Number(b).as_u64()?;
+    let c = ($a $op $b) as f64;
+
+    $vm.stack.push(Value::Number(c))?;
+
+    Ok(Step::Next)
+  }};
+}
+
+/// Functions called by [`Instruction::CallF`] which the machine runs itself rather than as
+/// registered built-ins.
+const INTRINSICS: &[&str] = &[
+  "eval",
+  "debug_stack",
+  "format",
+  "min",
+  "max",
+  "map",
+  "filter",
+  "reduce",
+];
+
+/// Get the address following each label of `script`.
+fn get_labels(script: &[Instruction<'_>]) -> HashMap<Label, usize> {
+  script
+    .iter()
+    .enumerate()
+    .filter_map(|(offset, instr)| match instr {
+      Instruction::Label(label) => Some((*label, offset + 1)),
+      _ => None,
+    })
+    .collect()
+}
+
+/// Make a built-in writing its arguments to `writer`, with `separator` between them and
+/// `terminator` after them.
+fn print_to(
+  writer: Rc<RefCell<Box<dyn Write>>>,
+  separator: &'static str,
+  terminator: &'static str,
+) -> impl Fn(&[Value]) -> VmResult<Value> {
+  move |args| {
+    let mut writer = writer.borrow_mut();
+    let mut write = || {
+      for (i, value) in args.iter().enumerate() {
+        if i > 0 {
+          write!(writer, "{}", separator)?;
+        }
+
+        write!(writer, "{}", value)?;
       }
-      _ => todo!(),
-    }
-  };
+
+      write!(writer, "{}", terminator)?;
+      writer.flush()
+    };
+
+    // Output errors are ignored so scripts behave the same wherever their output goes
+    write().ok();
+
+    Ok(Value::Null)
+  }
+}
+
+/// How arithmetic and bitwise operators treat operands other than numbers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CoercionMode {
+  /// Both operands must be numbers, anything else fails with [`VmError::InvalidOperands`].
+  #[default]
+  Strict,
+  /// Adding a string to a string or number concatenates them as text, otherwise booleans and
+  /// strings holding numbers are converted to numbers.
+  Lenient,
 }
 
 pub struct VirtualMachine<'script> {
@@ -82,72 +165,611 @@ pub struct VirtualMachine<'script> {
   stack: Stack,
   script: &'script [Instruction<'script>],
   labels: HashMap<Label, usize>,
-  locals: HashMap<Local, Value>,
-  builtins: HashMap<String, BuiltInRc>,
+  frames: Vec<Frame>,
+  /// Top-level variables, kept when another program is loaded.
+  globals: HashMap<String, Value>,
+  /// Maximum number of calls in progress at once.
+  max_call_depth: usize,
+  /// Whether integer operations error instead of wrapping when they overflow.
+  overflow_checks: bool,
+  /// Whether values are deep copied when stored in a local rather than shared.
+  copy_on_assign: bool,
+  coercion: CoercionMode,
+  /// Built-ins in the order they were first registered, indexed by [`Instruction::LdBuiltin`].
+  builtins: Vec<BuiltInRc>,
+  /// Index of each built-in in `builtins` by name.
+  builtin_names: HashMap<String, u16>,
+  /// Where `print` and `debug_stack` write to.
+  output: Rc<RefCell<Box<dyn Write>>>,
+  rng: Rc<Rng>,
+  /// Executed instruction counts by name, `None` unless profiling is enabled.
+  profile: Option<HashMap<&'static str, u64>>,
+  /// Constant pool of `script`.
+  consts: &'script [Constant<'script>],
+  /// Source location of each instruction of `script`, if known.
+  spans: &'script [Span<'script>],
 }
 
 impl<'script> VirtualMachine<'script> {
   pub fn new(script: &'script [Instruction<'script>]) -> Self {
+    let rng = Rc::new(Rng::from_time());
+
     Self {
       pc: 0,
       stack: Stack::new(255),
       script,
-      labels: script
-        .iter()
-        .enumerate()
-        .filter_map(|(offset, instr)| match instr {
-          Instruction::Label(label) => Some((*label, offset + 1)),
-          _ => None,
-        })
-        .collect(),
-      locals: HashMap::new(),
-      builtins: HashMap::new(),
+      labels: get_labels(script),
+      frames: vec![Frame::new(script.len(), 0, 0)],
+      globals: HashMap::new(),
+      max_call_depth: 1024,
+      overflow_checks: false,
+      copy_on_assign: false,
+      coercion: CoercionMode::Strict,
+      builtins: Vec::new(),
+      builtin_names: HashMap::new(),
+      output: Rc::new(RefCell::new(Box::new(io::sink()))),
+      rng: rng.clone(),
+      profile: None,
+      consts: &[],
+      spans: &[],
     }
+    .builtin("random", {
+      let rng = rng.clone();
+      move |_| Ok(rng.next_f64().into())
+    })
+    .builtin("random_int", move |args| {
+      let [lo, hi] = expect_args(args)?;
+      let lo = lo.as_f64()?.floor();
+      let hi = hi.as_f64()?.floor();
+
+      // A number in `[lo, hi)`
+      Ok((lo + (rng.next_f64() * (hi - lo).max(0.0)).floor()).into())
+    })
+    .builtin("is_nan", |args| {
+      let [value] = expect_args(args)?;
+
+      Ok(Value::Bool(
+        matches!(value, Value::Number(value) if value.is_nan()),
+      ))
+    })
+    .builtin("approx_eq", |args| {
+      let [a, b, eps] = expect_args(args)?;
+      let (a, b, eps) = (a.as_f64()?, b.as_f64()?, eps.as_f64()?);
+
+      Ok(Value::Bool((a - b).abs() <= eps))
+    })
+    .builtin("sb_new", |_| {
+      Ok(Value::Builder(Rc::new(RefCell::new(String::new()))))
+    })
+    .builtin("sb_append", |args| {
+      let [builder, value] = expect_args(args)?;
+      let builder = builder.as_builder()?;
+
+      builder.borrow_mut().push_str(&value.to_string());
+
+      // The builder is returned so appends can be chained
+      Ok(Value::Builder(builder))
+    })
+    .builtin("sb_build", |args| {
+      let [builder] = expect_args(args)?;
+      let value = builder.as_builder()?.borrow().clone();
+
+      Ok(value.into())
+    })
+    // Strings are shared rather than copied unless copy on assign is enabled, so mutating one
+    // changes it through every variable, parameter and array element referring to it
+    .builtin("str_clear", |args| {
+      let [value] = expect_args(args)?;
+
+      value.as_string()?.borrow_mut().clear();
+
+      Ok(Value::Null)
+    })
+    .builtin("str_append", |args| {
+      let [value, suffix] = expect_args(args)?;
+      let value = value.as_string()?;
+
+      // Copied first so a string can be appended to itself
+      let suffix = suffix.as_string()?.borrow().clone();
+      value.borrow_mut().push_str(&suffix);
+
+      Ok(Value::String(value))
+    })
+    .builtin("range", |args| {
+      let (lo, hi, step) = match args {
+        [hi] => (0.0, hi.as_f64()?, 1.0),
+        [lo, hi] => (lo.as_f64()?, hi.as_f64()?, 1.0),
+        [lo, hi, step] => (lo.as_f64()?, hi.as_f64()?, step.as_f64()?),
+        _ => {
+          return Err(VmError::ArityMismatch {
+            expected: 3,
+            actual: args.len(),
+          })
+        }
+      };
+
+      if step == 0.0 || step.is_nan() || (hi - lo) * step < 0.0 {
+        return Err(VmError::BadRangeStep(step));
+      }
+
+      // Numbers from `lo` up to but excluding `hi`
+      let len = ((hi - lo) / step).ceil().max(0.0) as usize;
+      let values = (0..len)
+        .map(|i| Value::Number(lo + i as f64 * step))
+        .collect::<Vec<_>>();
+
+      Ok(values.into())
+    })
+    .builtin("contains", |args| {
+      let [haystack, needle] = expect_args(args)?;
+
+      // Arrays are searched for an equal element, anything else for a substring
+      if let Value::Array(values) = &haystack {
+        return Ok(Value::Bool(values.borrow().contains(needle)));
+      }
+
+      let (haystack, needle) = (haystack.as_string()?, needle.as_string()?);
+      let contains = haystack.borrow().contains(needle.borrow().as_str());
+
+      Ok(Value::Bool(contains))
+    })
+    .builtin("index_of", |args| {
+      let [haystack, needle] = expect_args(args)?;
+
+      if let Value::Array(values) = &haystack {
+        let index = values.borrow().iter().position(|value| value == needle);
+
+        return Ok(Value::Number(index.map_or(-1.0, |index| index as f64)));
+      }
+
+      let (haystack, needle) = (haystack.as_string()?, needle.as_string()?);
+      let haystack = haystack.borrow();
+
+      // Positions count characters rather than bytes
+      let index = match haystack.find(needle.borrow().as_str()) {
+        Some(offset) => haystack[..offset].chars().count() as f64,
+        None => -1.0,
+      };
+
+      Ok(Value::Number(index))
+    })
+    .builtin("deep_copy", |args| {
+      let [value] = expect_args(args)?;
+
+      Ok(value.deep_copy())
+    })
+    .builtin("to_string", |args| {
+      let [value] = expect_args(args)?;
+
+      Ok(value.to_string().into())
+    })
+    .builtin("parse_number", |args| {
+      let [value] = expect_args(args)?;
+      let value = value.as_string()?;
+      let value = value.borrow();
+
+      match value.trim().parse::<f64>() {
+        Ok(number) => Ok(number.into()),
+        Err(_) => Err(VmError::ParseNumber(value.clone())),
+      }
+    })
+    .builtin("to_hex", |args| {
+      let [value] = expect_args(args)?;
+
+      Ok(encoding::to_hex(&value.as_bytes()?.borrow()).into())
+    })
+    .builtin("from_hex", |args| {
+      let [value] = expect_args(args)?;
+      let value = value.as_string()?;
+      let value = value.borrow();
+
+      match encoding::from_hex(&value) {
+        Some(bytes) => Ok(Value::Bytes(Rc::new(RefCell::new(bytes)))),
+        None => Err(VmError::Decode(value.clone())),
+      }
+    })
+    .builtin("to_base64", |args| {
+      let [value] = expect_args(args)?;
+
+      Ok(encoding::to_base64(&value.as_bytes()?.borrow()).into())
+    })
+    .builtin("from_base64", |args| {
+      let [value] = expect_args(args)?;
+      let value = value.as_string()?;
+      let value = value.borrow();
+
+      match encoding::from_base64(&value) {
+        Some(bytes) => Ok(Value::Bytes(Rc::new(RefCell::new(bytes)))),
+        None => Err(VmError::Decode(value.clone())),
+      }
+    })
+    .builtin("env", |args| {
+      let [name] = expect_args(args)?;
+      let value = std::env::var(name.as_string()?.borrow().as_str());
+
+      Ok(value.map(Value::from).unwrap_or_default())
+    })
+    .input(BufReader::new(io::stdin()))
+    .with_output(Box::new(io::stdout()))
+    .clock({
+      let start = Instant::now();
+      move || start.elapsed().as_secs_f64() * 1000.0
+    })
+  }
+
+  /// Set the milliseconds `now` returns, by default the time since the machine was created.
+  pub fn clock<F>(self, clock: F) -> Self
+  where
+    F: 'static + Fn() -> f64,
+  {
+    self.builtin("now", move |_| Ok(clock().into()))
+  }
+
+  /// Set the variables `env` reads from instead of the process environment.
+  pub fn environment(self, vars: HashMap<String, String>) -> Self {
+    self.builtin("env", move |args| {
+      let [name] = expect_args(args)?;
+      let value = vars.get(name.as_string()?.borrow().as_str());
+
+      Ok(value.map(|value| value.as_str().into()).unwrap_or_default())
+    })
+  }
+
+  /// Set where `read_line` reads from.
+  pub fn input<R>(self, reader: R) -> Self
+  where
+    R: 'static + BufRead,
+  {
+    let reader = RefCell::new(reader);
+
+    self.builtin("read_line", move |_| {
+      let mut line = String::new();
+
+      // Input errors are treated the same as the end of input
+      match reader.borrow_mut().read_line(&mut line) {
+        Ok(0) | Err(_) => Ok(Value::Null),
+        Ok(_) => {
+          let len = line.trim_end_matches(&['\r', '\n'][..]).len();
+          line.truncate(len);
+
+          Ok(line.into())
+        }
+      }
+    })
+  }
+
+  /// Set where `print`, `println`, `printlns` and `debug_stack` write to.
+  ///
+  /// `print` writes its arguments back to back, `println` ends them with a newline and `printlns`
+  /// also separates them with spaces.
+  pub fn with_output(mut self, writer: Box<dyn Write>) -> Self {
+    let writer = Rc::new(RefCell::new(writer));
+
+    self.output = writer.clone();
+    self
+      .builtin("print", print_to(writer.clone(), "", ""))
+      .builtin("println", print_to(writer.clone(), "", "\n"))
+      .builtin("printlns", print_to(writer, " ", "\n"))
+  }
+
+  /// Seed `random` and `random_int` so runs are reproducible.
+  pub fn with_seed(self, seed: u64) -> Self {
+    self.rng.seed(seed);
+    self
+  }
+
+  /// Set the maximum number of values the stack can hold, pushing more fails with
+  /// [`VmError::StackOverflow`].
+  pub fn stack_size(mut self, size: usize) -> Self {
+    self.stack = Stack::new(size);
+    self
+  }
+
+  /// Set the maximum number of calls in progress at once, deeper calls fail with
+  /// [`VmError::CallDepthExceeded`].
+  pub fn max_call_depth(mut self, depth: usize) -> Self {
+    self.max_call_depth = depth;
+    self
+  }
+
+  /// Make shifts by 64 or more bits and left shifts losing bits fail with
+  /// [`VmError::IntegerOverflow`] instead of wrapping.
+  pub fn with_overflow_checks(mut self, enabled: bool) -> Self {
+    self.overflow_checks = enabled;
+    self
+  }
+
+  /// Deep copy strings, builders and arrays when they're stored in a variable or parameter so
+  /// every local has its own copy instead of sharing one.
+  pub fn with_copy_on_assign(mut self, enabled: bool) -> Self {
+    self.copy_on_assign = enabled;
+    self
+  }
+
+  /// Set how operators treat operands other than numbers, strict by default.
+  pub fn with_coercion(mut self, mode: CoercionMode) -> Self {
+    self.coercion = mode;
+    self
+  }
+
+  /// Create a machine running a compiled program with its constants and source locations.
+  pub fn load(program: &'script CompiledProgram<'script>) -> Self {
+    let mut vm = Self::new(&program.code);
+    vm.consts = &program.consts;
+    vm.spans = &program.spans;
+    vm
   }
 
+  /// Replace the running program with `program`, keeping the globals stored by earlier runs.
+  pub fn reload(&mut self, program: &'script CompiledProgram<'script>) {
+    self.pc = 0;
+    self.stack.clear(self.stack.len());
+    self.script = &program.code;
+    self.labels = get_labels(&program.code);
+    self.frames = vec![Frame::new(program.code.len(), 0, 0)];
+    self.consts = &program.consts;
+    self.spans = &program.spans;
+  }
+
+  /// Get the number of values on the stack.
+  pub fn stack_depth(&self) -> usize {
+    self.stack.len()
+  }
+
+  /// Get the source location of the current instruction, the one which failed if `run` errored.
+  pub fn span(&self) -> Option<&Span<'script>> {
+    self.spans.get(self.pc)
+  }
+
+  /// Count how many times each instruction executes, see [`VirtualMachine::profile`].
+  pub fn with_profiling(mut self, enabled: bool) -> Self {
+    self.profile = enabled.then(HashMap::new);
+    self
+  }
+
+  /// Get the number of times each instruction executed, empty unless profiling is enabled.
+  pub fn profile(&self) -> HashMap<&'static str, u64> {
+    self.profile.clone().unwrap_or_default()
+  }
+
+  /// Register a built-in called with its arguments in order, returning its result.
+  ///
+  /// Replacing a built-in keeps the index it was linked to.
   pub fn builtin<F>(mut self, name: &str, f: F) -> Self
   where
-    F: 'static + Fn(&mut Stack) -> VmResult<()>,
+    F: 'static + Fn(&[Value]) -> VmResult<Value>,
   {
-    self.builtins.insert(name.to_string(), Rc::new(f));
+    match self.builtin_names.get(name) {
+      Some(&index) => self.builtins[index as usize] = Rc::new(f),
+      None => {
+        let index = u16::try_from(self.builtins.len()).expect("Too many built-ins");
+
+        self.builtins.push(Rc::new(f));
+        self.builtin_names.insert(name.to_string(), index);
+      }
+    }
+
     self
   }
 
+  /// Get the registered built-in called `name`.
+  fn get_builtin(&self, name: &str) -> Option<&BuiltInRc> {
+    let index = *self.builtin_names.get(name)?;
+
+    self.builtins.get(index as usize)
+  }
+
+  /// Resolve each [`Instruction::LdImport`] of `code` to an [`Instruction::LdBuiltin`] and each
+  /// [`Instruction::CallF`] of a registered built-in to an [`Instruction::CallBuiltin`] indexing
+  /// this machine's built-ins, so built-ins aren't looked up by name each time they run.
+  ///
+  /// Calls of the machine's own functions, such as `format`, and of names which aren't registered
+  /// are left to be looked up when they run.
+  ///
+  /// Fails with [`VmError::UndefinedImport`] if an import isn't a registered built-in.
+  pub fn link(&self, code: &mut [Instruction<'_>]) -> VmResult<()> {
+    for instr in code {
+      match instr {
+        Instruction::LdImport(name) => match self.builtin_names.get(*name) {
+          Some(&index) => *instr = Instruction::LdBuiltin(index),
+          None => return Err(VmError::UndefinedImport(name.to_string())),
+        },
+        Instruction::CallF(name, argc) if !INTRINSICS.contains(name) => {
+          if let Some(&index) = self.builtin_names.get(*name) {
+            *instr = Instruction::CallBuiltin(index, *argc);
+          }
+        }
+        _ => {}
+      }
+    }
+
+    Ok(())
+  }
+
   pub fn run(&mut self) -> VmResult<()> {
-    while self.pc < self.script.len() {
+    self.run_frames(0)
+  }
+
+  /// Run the same as [`VirtualMachine::run`], locating an error at the instruction which failed.
+  pub fn run_spanned(&mut self) -> Result<(), Box<SpannedError<'script>>> {
+    self
+      .run()
+      .map_err(|err| Box::new(err.at(self.span().cloned())))
+  }
+
+  /// Run until the end of the script or until only `depth` call frames are left.
+  fn run_frames(&mut self, depth: usize) -> VmResult<()> {
+    while self.pc < self.script.len() && self.frames.len() > depth {
+      if let Some(profile) = &mut self.profile {
+        *profile.entry(self.script[self.pc].name()).or_default() += 1;
+      }
+
       match self.run_next()? {
         Step::Next => self.pc += 1,
         Step::Jmp(to) => match self.labels.get(&to).cloned() {
           Some(offset) => self.pc = offset,
-          None => todo!("Unexpected label {:?}", to),
+          None => return Err(VmError::UnknownLabel(to)),
         },
+        // Jumping to the end of the script halts
+        Step::JmpAddr(to) if to > self.script.len() => return Err(VmError::BadJumpTarget(to)),
         Step::JmpAddr(to) => {
           self.pc = to;
         }
+        // Nothing runs after a halt, including the callers of a function called by a built-in
+        Step::Halt => {
+          self.pc = self.script.len();
+          break;
+        }
       }
     }
 
     Ok(())
   }
 
-  fn run_next(&mut self) -> VmResult<Step> {
-    println!("{:?} - pc: {}", self.script[self.pc], self.pc);
+  /// Call a function, closure or built-in value with `args` and get its result.
+  fn call_value(&mut self, callee: Value, args: Vec<Value>) -> VmResult<Value> {
+    let (addr, arity, captured) = match callee {
+      Value::BuiltIn(builtin) => return builtin(&args),
+      Value::Function { addr, arity } => (addr, arity, Vec::new()),
+      Value::Closure {
+        addr,
+        arity,
+        captured,
+      } => (addr, arity, captured),
+      value => return Err(VmError::NotCallable(value)),
+    };
+
+    if arity != args.len() {
+      return Err(VmError::ArityMismatch {
+        expected: arity,
+        actual: args.len(),
+      });
+    }
+
+    let (pc, depth) = (self.pc, self.frames.len());
+
+    for value in args {
+      self.stack.push(value)?;
+    }
+
+    self.push_frame(arity)?;
+    self.frame_mut().args += captured.len();
+
+    for value in captured {
+      self.stack.push(value)?;
+    }
+
+    self.pc = addr;
+    self.run_frames(depth)?;
+
+    // The script halted before the call returned
+    if self.frames.len() > depth {
+      return Ok(Value::Null);
+    }
 
+    self.pc = pc;
+    self.stack.pop()
+  }
+
+  fn pop_args(&mut self, argc: usize) -> VmResult<Vec<Value>> {
+    let mut args = (0..argc)
+      .map(|_| self.stack.pop())
+      .collect::<VmResult<Vec<_>>>()?;
+
+    args.reverse();
+
+    Ok(args)
+  }
+
+  fn run_next(&mut self) -> VmResult<Step> {
     match &self.script[self.pc] {
       Instruction::Nop => Ok(Step::Next),
 
       Instruction::LdNull => self.run_ld(Value::Null),
       Instruction::LdTrue => self.run_ld(true),
       Instruction::LdFalse => self.run_ld(false),
+      Instruction::LdConst(index) => self.run_ld(&self.consts[*index as usize]),
       Instruction::LdF64(value) => self.run_ld(*value),
       Instruction::LdStr(value) => self.run_ld(value.clone()),
       Instruction::LdAddr(value) => self.run_ld(*value),
+      Instruction::LdFn(label, arity) => self.run_ldfn(*label, *arity),
+      Instruction::MkClosure(label, arity, captured) => {
+        self.run_mkclosure(*label, *arity, *captured)
+      }
+      Instruction::MkArray(len) => {
+        let values = self.pop_args(*len)?;
+
+        self.run_ld(values)
+      }
       Instruction::LdImport(value) => self.run_ldimport(value),
+      Instruction::LdBuiltin(index) => match self.builtins.get(*index as usize).cloned() {
+        Some(builtin) => self.run_ld(Value::BuiltIn(builtin)),
+        None => Err(VmError::UnknownBuiltin(*index)),
+      },
 
       Instruction::StLoc(local) => self.run_stloc(*local),
+      Instruction::StArg(index) => self.run_starg(*index),
+      Instruction::StGlobal(name) => {
+        let value = match self.copy_on_assign {
+          true => self.stack.pop()?.deep_copy(),
+          false => self.stack.pop()?,
+        };
+
+        self.globals.insert(name.to_string(), value);
+
+        Ok(Step::Next)
+      }
+      Instruction::Pop => {
+        self.stack.pop()?;
+
+        Ok(Step::Next)
+      }
+      Instruction::Swap => {
+        let a = self.stack.pop()?;
+        let b = self.stack.pop()?;
+
+        self.stack.push(a)?;
+        self.stack.push(b)?;
+
+        Ok(Step::Next)
+      }
+      Instruction::Dup => {
+        let a = self.stack.pop()?;
+
+        self.stack.push(a.clone())?;
+        self.stack.push(a)?;
+
+        Ok(Step::Next)
+      }
+      Instruction::Over => {
+        let a = self.stack.pop()?;
+        let b = self.stack.pop()?;
+
+        self.stack.push(b.clone())?;
+        self.stack.push(a)?;
+        self.stack.push(b)?;
+
+        Ok(Step::Next)
+      }
       Instruction::LdLoc(local) => self.run_ldloc(*local),
+      Instruction::LdArg(index) => self.run_ldarg(*index),
+      Instruction::LdGlobal(name) => match self.globals.get(*name).cloned() {
+        Some(value) => {
+          self.stack.push(value)?;
+          Ok(Step::Next)
+        }
+        None => Err(VmError::UndefinedGlobal(name.to_string())),
+      },
 
       Instruction::Jmp(to) => Ok(Step::Jmp(*to)),
+      Instruction::JmpIf(to) => match self.stack.pop()?.is_truthy() {
+        true => Ok(Step::Jmp(*to)),
+        false => Ok(Step::Next),
+      },
+      Instruction::JmpIfNot(to) => match self.stack.pop()?.is_truthy() {
+        true => Ok(Step::Next),
+        false => Ok(Step::Jmp(*to)),
+      },
       Instruction::JmpEq(to) => jmp_if!(to, self.stack, a == b),
       Instruction::JmpNEq(to) => jmp_if!(to, self.stack, a != b),
       Instruction::JmpLt(to) => jmp_if!(to, self.stack, a < b),
@@ -155,23 +777,40 @@ impl<'script> VirtualMachine<'script> {
       Instruction::JmpGt(to) => jmp_if!(to, self.stack, a > b),
       Instruction::JmpGtEq(to) => jmp_if!(to, self.stack, a >= b),
 
-      Instruction::Call(label) => self.run_call(*label),
-      Instruction::CallF(name) => match self.builtins.get(*name) {
-        Some(builtin) => {
-          builtin(&mut self.stack)?;
-
-          Ok(Step::Next)
-        }
-        None => todo!("Unexpected built-in `{}`", name),
+      Instruction::Call(label, argc) => self.run_call(*label, *argc),
+      Instruction::Tailcall(label, argc) => self.run_tailcall(*label, *argc),
+      Instruction::CallF("eval", _) => self.run_eval(),
+      Instruction::CallF("debug_stack", argc) => self.run_debug_stack(*argc),
+      Instruction::CallF("format", argc) => self.run_format(*argc),
+      Instruction::CallF("min", argc) => self.run_extreme(*argc, Ordering::Less),
+      Instruction::CallF("max", argc) => self.run_extreme(*argc, Ordering::Greater),
+      Instruction::CallF("map", argc) => self.run_map(*argc),
+      Instruction::CallF("filter", argc) => self.run_filter(*argc),
+      Instruction::CallF("reduce", argc) => self.run_reduce(*argc),
+      Instruction::CallF(name, argc) => match self.get_builtin(name).cloned() {
+        Some(builtin) => self.run_builtin(builtin, *argc),
+        None => Err(VmError::UndefinedBuiltin(name.to_string())),
+      },
+      Instruction::CallBuiltin(index, argc) => match self.builtins.get(*index as usize).cloned() {
+        Some(builtin) => self.run_builtin(builtin, *argc),
+        None => Err(VmError::UnknownBuiltin(*index)),
       },
+      Instruction::CallDyn(argc) => self.run_calldyn(*argc),
       Instruction::Ret => self.run_ret(),
+      Instruction::Halt => Ok(Step::Halt),
+
+      Instruction::Add => self.run_add(),
+      Instruction::Sub => run_arith_op!(self, a - b),
+      Instruction::Mul => run_arith_op!(self, a * b),
+      Instruction::Div => run_arith_op!(self, a / b),
+      Instruction::Mod => run_arith_op!(self, a % b),
+      Instruction::Pow => run_arith_op!(self, a.powf(b)),
+      Instruction::Neg => {
+        let value = self.stack.pop()?.as_f64()?;
+        self.stack.push(Value::Number(-value))?;
 
-      Instruction::Add => run_arith_op!(self.stack, a + b),
-      Instruction::Sub => run_arith_op!(self.stack, a - b),
-      Instruction::Mul => run_arith_op!(self.stack, a * b),
-      Instruction::Div => run_arith_op!(self.stack, a / b),
-      Instruction::Mod => run_arith_op!(self.stack, a % b),
-      Instruction::Pow => run_arith_op!(self.stack, a.powf(b)),
+        Ok(Step::Next)
+      }
 
       Instruction::Eq => run_log_op!(self.stack, a == b),
       Instruction::NEq => run_log_op!(self.stack, a != b),
@@ -180,23 +819,25 @@ impl<'script> VirtualMachine<'script> {
       Instruction::LtEq => run_log_op!(self.stack, a <= b),
       Instruction::GtEq => run_log_op!(self.stack, a >= b),
 
-      Instruction::BNot => {
-        let value = match self.stack.pop()? {
-          Value::Bool(value) => Value::Bool(!value),
-          Value::Number(value) => Value::Number(!(value as u32) as _),
-          _ => todo!(),
-        };
+      Instruction::Not => {
+        let value = self.stack.pop()?;
+        self.stack.push(Value::Bool(!value.is_truthy()))?;
 
-        self.stack.push(value)?;
+        Ok(Step::Next)
+      }
+      Instruction::BNot => {
+        let value = self.stack.pop()?.as_u64()?;
+        self.stack.push(Value::Number(!value as _))?;
 
         Ok(Step::Next)
       }
-      Instruction::BOr => run_arith_op_fn!(self.stack, a | b),
-      Instruction::BAnd => run_arith_op_fn!(self.stack, a & b),
-      Instruction::LShift => run_arith_op_fn!(self.stack, a << b),
-      Instruction::RShift => run_arith_op_fn!(self.stack, a >> b),
+      Instruction::BOr => run_arith_op_fn!(self, a | b),
+      Instruction::BAnd => run_arith_op_fn!(self, a & b),
+      Instruction::LShift => self.run_shift(true),
+      Instruction::RShift => self.run_shift(false),
 
-      Instruction::Label(_) => panic!("Encountered label"),
+      // Jumps land after their label, reaching one means falling through to it
+      Instruction::Label(_) => Ok(Step::Next),
     }
   }
 
@@ -207,137 +848,2014 @@ impl<'script> VirtualMachine<'script> {
   }
 
   fn run_ldimport(&mut self, value: &str) -> VmResult<Step> {
-    match self.builtins.get(value) {
-      Some(builtin) => self.stack.push(Value::BuiltIn(builtin.clone()))?,
-      None => todo!(),
+    match self.get_builtin(value).cloned() {
+      Some(builtin) => self.run_ld(Value::BuiltIn(builtin)),
+      None => Err(VmError::UndefinedImport(value.to_string())),
+    }
+  }
+
+  fn run_ldfn(&mut self, label: Label, arity: usize) -> VmResult<Step> {
+    match self.labels.get(&label).cloned() {
+      Some(addr) => self.stack.push(Value::Function { addr, arity })?,
+      None => return Err(VmError::UnknownLabel(label)),
     };
 
     Ok(Step::Next)
   }
 
-  fn run_ldloc(&mut self, local: Local) -> VmResult<Step> {
-    self.stack.push(self.locals[&local].clone())?;
+  fn run_mkclosure(&mut self, label: Label, arity: usize, captured: usize) -> VmResult<Step> {
+    let addr = match self.labels.get(&label).cloned() {
+      Some(addr) => addr,
+      None => return Err(VmError::UnknownLabel(label)),
+    };
+
+    let mut values = (0..captured)
+      .map(|_| self.stack.pop())
+      .collect::<VmResult<Vec<_>>>()?;
+
+    values.reverse();
+
+    self.stack.push(Value::Closure {
+      addr,
+      arity,
+      captured: values,
+    })?;
 
     Ok(Step::Next)
   }
 
-  fn run_stloc(&mut self, local: Local) -> VmResult<Step> {
-    self.locals.insert(local, self.stack.pop()?);
+  fn frame_mut(&mut self) -> &mut Frame {
+    self.frames.last_mut().expect("Root frame")
+  }
+
+  /// Arguments are read from the window above the base of the current call.
+  fn run_ldarg(&mut self, index: u8) -> VmResult<Step> {
+    let frame = self.frames.last().expect("Root frame");
+    let value = match (index as usize) < frame.args {
+      true => self.stack.get(frame.base + index as usize).cloned(),
+      false => None,
+    };
+
+    self.stack.push(value.ok_or(VmError::UnknownArg(index))?)?;
 
     Ok(Step::Next)
   }
 
-  fn run_call(&mut self, label: Label) -> VmResult<Step> {
-    self.stack.push_top(Value::Addr(self.pc + 1))?;
+  fn run_starg(&mut self, index: u8) -> VmResult<Step> {
+    let value = match self.copy_on_assign {
+      true => self.stack.pop()?.deep_copy(),
+      false => self.stack.pop()?,
+    };
+    let frame = self.frames.last().expect("Root frame");
+    let stored = match (index as usize) < frame.args {
+      true => self.stack.set(frame.base + index as usize, value),
+      false => None,
+    };
 
-    // Parameters?
+    stored.ok_or(VmError::UnknownArg(index))?;
 
-    Ok(Step::Jmp(label))
+    Ok(Step::Next)
   }
 
-  fn run_ret(&mut self) -> VmResult<Step> {
-    let addr = self.stack.pop_top()?;
-    let addr = match addr {
-      Value::Addr(addr) => addr,
-      _ => todo!(),
+  /// Locals are read from the current call falling back to the top-level locals.
+  fn run_ldloc(&mut self, local: Local) -> VmResult<Step> {
+    let value = self
+      .frames
+      .last()
+      .and_then(|frame| frame.locals.get(&local))
+      .or_else(|| self.frames[0].locals.get(&local))
+      .cloned();
+
+    match value {
+      Some(value) => self.stack.push(value)?,
+      None => return Err(VmError::UndefinedLocal(local)),
     };
 
-    Ok(Step::JmpAddr(addr))
+    Ok(Step::Next)
   }
-}
 
-#[cfg(test)]
-mod tests {
-  use super::VirtualMachine;
-  use crate::{
-    ir::{compile, instr::Instruction},
-    vm::types::Value,
-  };
-  use std::borrow::Cow;
+  /// Locals are written to the top-level locals when defined there, otherwise to the current
+  /// call.
+  fn run_stloc(&mut self, local: Local) -> VmResult<Step> {
+    let value = match self.copy_on_assign {
+      true => self.stack.pop()?.deep_copy(),
+      false => self.stack.pop()?,
+    };
+    let frame = match self.frame_mut().locals.contains_key(&local) {
+      true => self.frame_mut(),
+      false if self.frames[0].locals.contains_key(&local) => &mut self.frames[0],
+      false => self.frame_mut(),
+    };
 
-  #[test]
-  fn test_string_chal() {
-    let inst = compile(include_str!("../../data/recursion.chal")).unwrap();
-    let mut vm = VirtualMachine::new(&inst)
-      .builtin("print", |stack| {
-        println!("PRINT: {}", stack.pop()?);
+    frame.locals.insert(local, value);
 
-        Ok(())
-      })
-      .builtin("charAt", |stack| {
-        let rhs = stack.pop()?.as_f64()? as usize;
+    Ok(Step::Next)
+  }
 
-        let lhs = stack.pop()?.as_string()?;
-        let lhs = lhs.borrow();
+  /// Pop the lhs and rhs of an operator as numbers, converting them in lenient mode.
+  fn pop_numbers(&mut self) -> VmResult<(f64, f64)> {
+    let (a, b) = (self.stack.pop()?, self.stack.pop()?);
 
-        let ch = lhs
-          .chars()
-          .skip(rhs)
-          .take(1)
-          .next()
-          .unwrap_or_default()
-          .to_string();
+    self.coerce_numbers(a, b)
+  }
 
-        stack.push(ch.into())?;
+  fn coerce_numbers(&self, a: Value, b: Value) -> VmResult<(f64, f64)> {
+    let coerce = |value: &Value| match (self.coercion, value) {
+      (_, Value::Number(value)) => Some(*value),
+      (CoercionMode::Strict, _) => None,
+      (CoercionMode::Lenient, Value::Bool(value)) => Some(*value as u8 as f64),
+      (CoercionMode::Lenient, Value::String(value)) => value.borrow().trim().parse().ok(),
+      (CoercionMode::Lenient, _) => None,
+    };
 
-        Ok(())
-      })
-      .builtin("removeAt", |stack| {
-        let rhs = stack.pop()?.as_f64()?;
-        let lhs = stack.pop()?.as_string()?;
+    match (coerce(&a), coerce(&b)) {
+      (Some(a), Some(b)) => Ok((a, b)),
+      _ => Err(VmError::InvalidOperands(a, b)),
+    }
+  }
 
-        lhs.borrow_mut().remove(rhs as _);
+  fn run_add(&mut self) -> VmResult<Step> {
+    let (a, b) = (self.stack.pop()?, self.stack.pop()?);
 
-        stack.push(lhs.into())?;
+    // Strings are concatenated rather than parsed, so `1 + "2"` is "12"
+    let concat = match (&a, &b) {
+      (Value::String(_), Value::String(_) | Value::Number(_))
+      | (Value::Number(_), Value::String(_)) => self.coercion == CoercionMode::Lenient,
+      _ => false,
+    };
 
-        Ok(())
-      })
-      .builtin("append", |stack| {
-        let rhs = stack.pop()?.as_string()?;
-        let rhs = rhs.borrow();
+    let value = match concat {
+      true => format!("{}{}", a, b).into(),
+      false => {
+        let (a, b) = self.coerce_numbers(a, b)?;
+        Value::Number(a + b)
+      }
+    };
 
-        let lhs = stack.pop()?.as_string()?;
+    self.stack.push(value)?;
 
-        lhs.borrow_mut().push_str(rhs.as_str());
+    Ok(Step::Next)
+  }
 
-        stack.push(lhs.into())?;
+  /// Shift the lhs as an integer by the rhs, wrapping unless overflow checks are enabled.
+  fn run_shift(&mut self, left: bool) -> VmResult<Step> {
+    let (a, b) = self.pop_numbers()?;
+    let (a, b) = (Value::Number(a).as_u64()?, Value::Number(b).as_u64()?);
 
-        Ok(())
-      })
-      .builtin("length", |stack| {
-        let value = stack.pop()?.as_string()?;
-        let value = value.borrow();
+    let checked = || {
+      let b = u32::try_from(b).ok()?;
 
-        stack.push(Value::Number(value.len() as _))?;
+      match left {
+        // Bits shifted out of the high end are lost
+        true => a.checked_shl(b).filter(|value| value >> b == a),
+        false => a.checked_shr(b),
+      }
+    };
 
-        Ok(())
-      })
-      .builtin("indexOf", |stack| {
-        let needle = stack.pop()?.as_string()?;
-        let needle = needle.borrow();
-        let needle = needle.as_str();
+    let value = match (self.overflow_checks, left) {
+      (true, _) => checked().ok_or(VmError::IntegerOverflow)?,
+      (false, true) => a.wrapping_shl(b as u32),
+      (false, false) => a.wrapping_shr(b as u32),
+    };
+
+    self.stack.push(Value::Number(value as f64))?;
+
+    Ok(Step::Next)
+  }
+
+  /// Push a frame for a call whose `argc` arguments are on the top of the stack.
+  fn push_frame(&mut self, argc: usize) -> VmResult<()> {
+    // The root frame isn't a call
+    if self.frames.len() > self.max_call_depth {
+      return Err(VmError::CallDepthExceeded(self.max_call_depth));
+    }
+
+    let base = self.stack.len().saturating_sub(argc);
+
+    self.frames.push(Frame::new(self.pc + 1, base, argc));
+
+    Ok(())
+  }
+
+  fn run_call(&mut self, label: Label, argc: usize) -> VmResult<Step> {
+    self.push_frame(argc)?;
+
+    Ok(Step::Jmp(label))
+  }
+
+  /// Re-enter a function with the current frame, dropping the locals of the call it replaces.
+  fn run_tailcall(&mut self, label: Label, argc: usize) -> VmResult<Step> {
+    // The top-level has no frame of its own to reuse
+    if self.frames.len() == 1 {
+      return self.run_call(label, argc);
+    }
+
+    // The new arguments replace the arguments and anything else the call it replaces left
+    let args = self.pop_args(argc)?;
+    let frame = self.frame_mut();
+
+    frame.locals.clear();
+    frame.args = argc;
+
+    let base = frame.base;
+
+    self.stack.clear(self.stack.len().saturating_sub(base));
+
+    for value in args {
+      self.stack.push(value)?;
+    }
+
+    Ok(Step::Jmp(label))
+  }
+
+  fn run_calldyn(&mut self, argc: usize) -> VmResult<Step> {
+    match self.stack.pop()? {
+      Value::Function { addr, arity } if arity == argc => {
+        self.push_frame(argc)?;
+
+        Ok(Step::JmpAddr(addr))
+      }
+      Value::Closure {
+        addr,
+        arity,
+        captured,
+      } if arity == argc => {
+        self.push_frame(argc)?;
+        self.frame_mut().args += captured.len();
+
+        // Captured values are bound by the closure after its arguments
+        for value in captured {
+          self.stack.push(value)?;
+        }
+
+        Ok(Step::JmpAddr(addr))
+      }
+      Value::Function { arity, .. } | Value::Closure { arity, .. } => Err(VmError::ArityMismatch {
+        expected: arity,
+        actual: argc,
+      }),
+      Value::BuiltIn(builtin) => self.run_builtin(builtin, argc),
+      value => Err(VmError::NotCallable(value)),
+    }
+  }
+
+  /// Call a built-in, a built-in not returning anything results in null.
+  fn run_builtin(&mut self, builtin: BuiltInRc, argc: usize) -> VmResult<Step> {
+    let args = self.pop_args(argc)?;
+    let value = builtin(&args)?;
+    self.stack.push(value)?;
+
+    Ok(Step::Next)
+  }
+
+  /// Run a script in a nested virtual machine sharing the current built-ins.
+  fn run_eval(&mut self) -> VmResult<Step> {
+    let script = self.stack.pop()?.as_string()?.borrow().clone();
+    let expr = Parser::new(&script)
+      .parse()
+      .map_err(|err| VmError::Compile(err.to_string()))?;
+    let program = compile_expr(&expr).map_err(|err| VmError::Compile(err.to_string()))?;
+
+    let mut vm = VirtualMachine::load(&program);
+    vm.builtins = self.builtins.clone();
+    vm.builtin_names = self.builtin_names.clone();
+    vm.run()?;
+
+    let value = match vm.stack.is_empty() {
+      true => Value::Null,
+      false => vm.stack.pop()?,
+    };
+
+    self.stack.push(value)?;
+
+    Ok(Step::Next)
+  }
+
+  /// Write the depth and values of the stack to the output.
+  fn run_debug_stack(&mut self, argc: usize) -> VmResult<Step> {
+    if argc != 0 {
+      return Err(VmError::ArityMismatch {
+        expected: 0,
+        actual: argc,
+      });
+    }
+
+    let mut output = self.output.borrow_mut();
+
+    // Output errors are ignored the same as they are by `print`
+    writeln!(output, "stack ({}): {:?}", self.stack.len(), self.stack)
+      .and_then(|_| output.flush())
+      .ok();
+
+    drop(output);
+    self.stack.push(Value::Null)?;
+
+    Ok(Step::Next)
+  }
+
+  /// Push the smallest (`Less`) or largest (`Greater`) of two or more numbers.
+  fn run_extreme(&mut self, argc: usize, keep: Ordering) -> VmResult<Step> {
+    if argc < 2 {
+      return Err(VmError::ArityMismatch {
+        expected: 2,
+        actual: argc,
+      });
+    }
+
+    let mut extreme = None;
+
+    for _ in 0..argc {
+      let value = match self.stack.pop()? {
+        value @ Value::Number(_) => value,
+        actual => {
+          return Err(VmError::TypeError {
+            expected: "number",
+            actual,
+          })
+        }
+      };
+
+      extreme = match extreme {
+        Some(extreme) if value.partial_cmp(&extreme) != Some(keep) => Some(extreme),
+        _ => Some(value),
+      };
+    }
+
+    self.stack.push(extreme.expect("At least two arguments"))?;
+
+    Ok(Step::Next)
+  }
+
+  /// Call `f` with each element of an array, collecting the results into a new array.
+  fn run_map(&mut self, argc: usize) -> VmResult<Step> {
+    let args = self.pop_args(argc)?;
+    let [values, f] = expect_args(&args)?;
+    let values = values.as_array()?.borrow().clone();
+
+    let values = values
+      .into_iter()
+      .map(|value| self.call_value(f.clone(), vec![value]))
+      .collect::<VmResult<Vec<_>>>()?;
+
+    self.stack.push(values.into())?;
+
+    Ok(Step::Next)
+  }
+
+  /// Collect the elements of an array for which `pred` is truthy into a new array.
+  fn run_filter(&mut self, argc: usize) -> VmResult<Step> {
+    let args = self.pop_args(argc)?;
+    let [values, pred] = expect_args(&args)?;
+    let values = values.as_array()?.borrow().clone();
+    let mut kept = Vec::new();
+
+    for value in values {
+      if self
+        .call_value(pred.clone(), vec![value.clone()])?
+        .is_truthy()
+      {
+        kept.push(value);
+      }
+    }
+
+    self.stack.push(kept.into())?;
+
+    Ok(Step::Next)
+  }
+
+  /// Fold the elements of an array into `init` with `f`, called with the result so far first.
+  fn run_reduce(&mut self, argc: usize) -> VmResult<Step> {
+    let args = self.pop_args(argc)?;
+    let [values, f, init] = expect_args(&args)?;
+    let values = values.as_array()?.borrow().clone();
+    let mut acc = init.clone();
+
+    for value in values {
+      acc = self.call_value(f.clone(), vec![acc, value])?;
+    }
+
+    self.stack.push(acc)?;
+
+    Ok(Step::Next)
+  }
+
+  /// Substitute each `{}` of a template with the arguments following it, `{{` and `}}` escape
+  /// literal braces.
+  fn run_format(&mut self, argc: usize) -> VmResult<Step> {
+    let mut args = self.pop_args(argc)?.into_iter();
+    let template = match args.next() {
+      Some(template) => template.as_string()?.borrow().clone(),
+      None => {
+        return Err(VmError::FormatMismatch {
+          placeholders: 0,
+          args: 0,
+        })
+      }
+    };
+
+    let placeholders = template.replace("{{", "").matches("{}").count();
+
+    if placeholders != args.len() {
+      return Err(VmError::FormatMismatch {
+        placeholders,
+        args: args.len(),
+      });
+    }
+
+    let mut formatted = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+      match (ch, chars.peek()) {
+        ('{', Some('{')) | ('}', Some('}')) => {
+          chars.next();
+          formatted.push(ch);
+        }
+        ('{', Some('}')) => {
+          chars.next();
+
+          let arg = args.next().expect("Placeholders counted");
+          formatted.push_str(&arg.to_string());
+        }
+        _ => formatted.push(ch),
+      }
+    }
+
+    self.stack.push(formatted.into())?;
+
+    Ok(Step::Next)
+  }
+
+  fn run_ret(&mut self) -> VmResult<Step> {
+    // The root frame belongs to the top-level and can't be returned from
+    if self.frames.len() < 2 {
+      return Err(VmError::ReturnOutsideFunction);
+    }
+
+    let frame = self.frames.pop().expect("Call frame");
+
+    // Compiled functions leave exactly their return value above their arguments
+    let expected = frame.base + frame.args + 1;
+
+    if self.stack.len() != expected {
+      return Err(VmError::UnbalancedReturn {
+        expected,
+        actual: self.stack.len(),
+      });
+    }
+
+    // The arguments are dropped from under the return value
+    let value = self.stack.pop()?;
+
+    self.stack.clear(frame.args);
+    self.stack.push(value)?;
+
+    Ok(Step::JmpAddr(frame.ret))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    error::{VmError, VmResult},
+    expect_args, CoercionMode, VirtualMachine,
+  };
+  use crate::{
+    ast::Parser,
+    ir::{
+      compile, compile_sources,
+      depth::max_stack_depth,
+      instr::{Instruction, Label},
+      module::Sources,
+      scope::Local,
+      CompiledProgram,
+    },
+    vm::types::Value,
+  };
+  use std::{
+    borrow::Cow,
+    cell::RefCell,
+    io::{self, Cursor, Write},
+    rc::Rc,
+  };
+
+  /// Compile and run a script, getting the value it leaves on the stack.
+  fn try_run_value(script: &str) -> VmResult<Value> {
+    let inst = compile(script).unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run()?;
+
+    let value = vm.stack.pop().unwrap();
+    assert!(vm.stack.is_empty(), "{}", script);
+
+    Ok(value)
+  }
+
+  fn run_value(script: &str) -> Value {
+    try_run_value(script).unwrap()
+  }
+
+  /// Add an `emit` built-in collecting the values it's called with.
+  fn with_emit(vm: VirtualMachine<'_>) -> (VirtualMachine<'_>, Rc<RefCell<Vec<Value>>>) {
+    let values = Rc::new(RefCell::new(Vec::new()));
+    let emitted = values.clone();
+    let vm = vm.builtin("emit", move |args| {
+      let [value] = expect_args(args)?;
+      emitted.borrow_mut().push(value.clone());
+
+      Ok(Value::Null)
+    });
+
+    (vm, values)
+  }
+
+  #[test]
+  fn test_string_chal() {
+    let output = Output::default();
+    let inst = compile(include_str!("../../data/recursion.chal")).unwrap();
+    let mut vm = VirtualMachine::load(&inst)
+      .with_output(Box::new(output.clone()))
+      .builtin("charAt", |args| {
+        let [lhs, rhs] = expect_args(args)?;
+        let rhs = rhs.as_f64()? as usize;
+
+        let lhs = lhs.as_string()?;
+        let lhs = lhs.borrow();
+
+        let ch = lhs
+          .chars()
+          .skip(rhs)
+          .take(1)
+          .next()
+          .unwrap_or_default()
+          .to_string();
+
+        Ok(ch.into())
+      })
+      .builtin("removeAt", |args| {
+        let [lhs, rhs] = expect_args(args)?;
+        let rhs = rhs.as_f64()?;
+        let lhs = lhs.as_string()?;
+
+        lhs.borrow_mut().remove(rhs as _);
+
+        Ok(lhs.into())
+      })
+      .builtin("append", |args| {
+        let [lhs, rhs] = expect_args(args)?;
+        let rhs = rhs.as_string()?;
+        let rhs = rhs.borrow();
+
+        let lhs = lhs.as_string()?;
+
+        lhs.borrow_mut().push_str(rhs.as_str());
+
+        Ok(lhs.into())
+      })
+      .builtin("length", |args| {
+        let [value] = expect_args(args)?;
+        let value = value.as_string()?;
+        let value = value.borrow();
+
+        Ok(Value::Number(value.len() as _))
+      })
+      .builtin("indexOf", |args| {
+        let [haystack, needle] = expect_args(args)?;
+        let needle = needle.as_string()?;
+        let needle = needle.borrow();
+        let needle = needle.as_str();
+
+        let haystack = haystack.as_string()?;
+        let haystack = haystack.borrow();
+        let haystack = haystack.as_str();
+
+        let index = haystack.find(needle).map(|val| val as i64).unwrap_or(-1);
+
+        Ok(index.into())
+      })
+      .builtin("readInNumber", |_| Ok(Value::Number(5.0)));
+
+    vm.run().unwrap();
+
+    assert_eq!(
+      String::from_utf8(output.0.borrow().clone()).unwrap(),
+      "Enter a max number: Counter is at: 5"
+    );
+  }
+
+  #[test]
+  fn test_cond() {
+    let run = |x: f64| {
+      let script = format!(
+        "(var x {}) \
+         (cond \
+           ((equal $x 1) \"one\") \
+           ((equal $x 2) \"two\") \
+           ((equal $x 2) \"also two\") \
+           (else \"other\"))",
+        x
+      );
+
+      let inst = compile(&script).unwrap();
+      let mut vm = VirtualMachine::load(&inst);
+      vm.run().unwrap();
+
+      let value = vm.stack.pop().unwrap();
+      assert!(vm.stack.is_empty());
+
+      value
+    };
+
+    assert_eq!(run(1.0), "one".into());
+    assert_eq!(run(2.0), "two".into());
+    assert_eq!(run(3.0), "other".into());
+  }
+
+  #[test]
+  fn test_compound_assign() {
+    assert_eq!(run_value("(var x 5) (+= x 3) $x"), 8.0.into());
+    assert_eq!(run_value("(var x 5) (-= x 3) $x"), 2.0.into());
+    assert_eq!(run_value("(var x 5) (*= x 3) $x"), 15.0.into());
+    assert_eq!(run_value("(var x 6) (/= x 3) $x"), 2.0.into());
+  }
+
+  #[test]
+  fn test_call_dyn() {
+    let inst = compile(
+      "(fun sub (a b) (- a b)) \
+       (var f sub) \
+       (f 10 3)",
+    )
+    .unwrap();
+
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 7.0.into());
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_recursion() {
+    let inst = compile(
+      "(fun fact (n) (if (< n 2) 1 (* n (fact (- n 1))))) \
+       (fact 5)",
+    )
+    .unwrap();
+
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 120.0.into());
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_ret_clears_frame() {
+    let inst = compile("(fun f (a) (1 2 a)) (f 3)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 3.0.into());
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_fn_body_returns_last() {
+    let inst = compile("(fun f (x) (var y (* x 2)) (+ x 1) (- $y 3)) (+ 1 (f 10))").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 18.0.into());
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_fn_body_returns_null() {
+    for script in &[
+      "(fun f (n) (var x 1)) (var r (f 7)) $r",
+      "(fun f (n) (+= n 1)) (var r (f 7)) $r",
+      "(fun f (n) ((var x n) (fun g () 1))) (var r (f 7)) $r",
+      "(var f (fun (n) (var x n))) (var r (f 7)) $r",
+    ] {
+      let inst = compile(script).unwrap();
+      let mut vm = VirtualMachine::load(&inst);
+      vm.run().unwrap();
+
+      assert_eq!(vm.stack.pop().unwrap(), Value::Null, "{}", script);
+      assert!(vm.stack.is_empty());
+    }
+  }
+
+  #[test]
+  fn test_early_return() {
+    let inst = compile(
+      "(fun sign (n) \
+         (if (< n 0) (return \"negative\")) \
+         (if (equal n 0) (return \"zero\")) \
+         \"positive\") \
+       (emit (sign (- 0 2))) \
+       (emit (sign 0)) \
+       (emit (sign 2))",
+    )
+    .unwrap();
+
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(
+      *values.borrow(),
+      vec!["negative".into(), "zero".into(), "positive".into()]
+    );
+    assert_eq!(vm.stack.pop().unwrap(), Value::Null);
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_neg() {
+    let mut vm = VirtualMachine::new(&[Instruction::LdF64(5.0), Instruction::Neg]);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), Value::Number(-5.0));
+
+    let mut vm = VirtualMachine::new(&[Instruction::LdTrue, Instruction::Neg]);
+
+    assert!(matches!(vm.run(), Err(VmError::TypeError { .. })));
+  }
+
+  #[test]
+  fn test_halt() {
+    let mut vm = VirtualMachine::new(&[
+      Instruction::LdF64(1.0),
+      Instruction::Halt,
+      Instruction::LdF64(2.0),
+      Instruction::CallF("unknown", 0),
+    ]);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), Value::Number(1.0));
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_globals() {
+    let first = compile("(var x 21)").unwrap();
+    let second = compile("(* $x 2)").unwrap();
+    let mut vm = VirtualMachine::load(&first);
+    vm.run().unwrap();
+    vm.reload(&second);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), Value::Number(42.0));
+
+    let mut vm = VirtualMachine::load(&second);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::UndefinedGlobal(name)) if name == "x"
+    ));
+  }
+
+  #[test]
+  fn test_assign_param() {
+    let inst = compile("(fun f (n) (+= n 10) ($n (to_string n)) n) (f 1)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), "11".into());
+  }
+
+  #[test]
+  fn test_overflow_checks() {
+    let run = |script: &str, checks: bool| {
+      let inst = compile(script).unwrap();
+      let mut vm = VirtualMachine::load(&inst).with_overflow_checks(checks);
+
+      vm.run().map(|_| vm.stack.pop().unwrap())
+    };
+
+    assert_eq!(run("(<< 1 64)", false).unwrap(), 1.0.into());
+    assert_eq!(run("(<< 3 63)", false).unwrap(), 2f64.powi(63).into());
+    assert_eq!(run("(<< 1 63)", true).unwrap(), 2f64.powi(63).into());
+    assert_eq!(run("(>> 5 1)", true).unwrap(), 2.0.into());
+
+    assert!(matches!(
+      run("(<< 1 64)", true),
+      Err(VmError::IntegerOverflow)
+    ));
+    assert!(matches!(
+      run("(<< 3 63)", true),
+      Err(VmError::IntegerOverflow)
+    ));
+    assert!(matches!(
+      run("(>> 1 64)", true),
+      Err(VmError::IntegerOverflow)
+    ));
+  }
+
+  #[test]
+  fn test_bitwise_integers() {
+    assert_eq!(run_value("(& 6 3)"), 2.0.into());
+    assert_eq!(run_value("(| 6 3)"), 7.0.into());
+
+    // Rather than truncating to `0` and `1`
+    for script in &[
+      "(& (- 1) 1)",
+      "(& 1.5 1)",
+      "(| 1 0.5)",
+      "(<< 1 (- 1))",
+      "(>> 2.5 1)",
+    ] {
+      assert!(
+        matches!(
+          try_run_value(script),
+          Err(VmError::TypeError {
+            expected: "unsigned integer",
+            ..
+          })
+        ),
+        "{}",
+        script
+      );
+    }
+  }
+
+  #[test]
+  fn test_max_stack_depth() {
+    let script = "(+ 1 (* 2 (- 3 (/ 4 (+ 5 6)))))";
+    let depth = max_stack_depth(&Parser::new(script).parse().unwrap()).unwrap();
+
+    assert_eq!(depth, 6);
+
+    let inst = compile(script).unwrap();
+    let mut vm = VirtualMachine::load(&inst).stack_size(depth);
+    vm.run().unwrap();
+
+    assert_eq!(
+      vm.stack.pop().unwrap(),
+      Value::Number(1.0 + 2.0 * (3.0 - 4.0 / 11.0))
+    );
+  }
+
+  #[test]
+  fn test_max_stack_depth_call() {
+    let script = "(fun f (a) (+ a (+ a (+ a a)))) (var v 1) (+ 1 (+ 2 (+ 3 (f $v))))";
+    let depth = max_stack_depth(&Parser::new(script).parse().unwrap()).unwrap();
+
+    let inst = compile(script).unwrap();
+    let mut vm = VirtualMachine::load(&inst).stack_size(depth);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), Value::Number(10.0));
+  }
+
+  #[test]
+  fn test_call_no_args() {
+    let inst = compile("(fun g () (+ 2 3)) (g)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), Value::Number(5.0));
+  }
+
+  #[test]
+  fn test_call_depth() {
+    let inst = compile("(fun f (n) (+ 1 (f n))) (f 0)").unwrap();
+    let mut vm = VirtualMachine::load(&inst)
+      .stack_size(4096)
+      .max_call_depth(100);
+
+    assert!(matches!(vm.run(), Err(VmError::CallDepthExceeded(100))));
+    assert_eq!(vm.frames.len(), 101);
+
+    // The default stack fills up well before the default call depth is reached
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::StackOverflow(255))));
+  }
+
+  #[test]
+  fn test_tail_call() {
+    let inst = compile(
+      "(fun countdown (n) (if (equal n 0) \"done\" (countdown (- n 1)))) \
+       (countdown 1000)",
+    )
+    .unwrap();
+
+    // Only the top-level call pushes a frame
+    let calls = inst
+      .code
+      .iter()
+      .filter(|instr| matches!(instr, Instruction::Call(..)))
+      .count();
+
+    assert_eq!(calls, 1);
+    assert!(inst
+      .code
+      .iter()
+      .any(|instr| matches!(instr, Instruction::Tailcall(..))));
+
+    // The recursion stays within the frame of the top-level call
+    let mut vm = VirtualMachine::load(&inst).max_call_depth(2);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), "done".into());
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_tailcall_frames() {
+    let inst = compile(
+      "(fun countdown (n) (if (equal n 0) \"done\" (countdown (- n 1)))) \
+       (countdown 1000)",
+    )
+    .unwrap();
+
+    // A single call deep however many times the function re-enters itself
+    let mut vm = VirtualMachine::load(&inst).max_call_depth(1);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), "done".into());
+    assert_eq!(vm.frames.len(), 1);
+  }
+
+  #[test]
+  fn test_link_calls() {
+    let mut program = compile(
+      "(fun count (n) (tick) (if (equal n 0) (format \"{}\" n) (count (- n 1)))) \
+       (count 10)",
+    )
+    .unwrap();
+
+    let ticks = Rc::new(RefCell::new(0));
+    let counter = ticks.clone();
+    let mut vm = VirtualMachine::new(&[])
+      .with_profiling(true)
+      .builtin("tick", move |_| {
+        *counter.borrow_mut() += 1;
+
+        Ok(Value::Null)
+      })
+      .builtin("format", |_| Ok(Value::Null));
+
+    vm.link(&mut program.code).unwrap();
+    vm.reload(&program);
+    vm.run().unwrap();
+
+    // Only the machine's own `format` is still called by name
+    let profile = vm.profile();
+
+    assert_eq!(*ticks.borrow(), 11);
+    assert_eq!(profile["CallBuiltin"], 11);
+    assert_eq!(profile["CallF"], 1);
+    assert_eq!(vm.stack.pop().unwrap(), Value::from("0"));
+  }
+
+  #[test]
+  fn test_profile() {
+    let inst = compile(
+      "(fun countdown (n) (if (equal n 0) \"done\" (countdown (- n 1)))) \
+       (countdown 10)",
+    )
+    .unwrap();
+
+    let mut vm = VirtualMachine::load(&inst).with_profiling(true);
+    vm.run().unwrap();
+
+    let profile = vm.profile();
+
+    // One jump over the function definition then one tail call back to the body per iteration
+    assert_eq!(profile["Jmp"], 1);
+    assert_eq!(profile["Tailcall"], 10);
+    assert_eq!(profile["Call"], 1);
+    assert!(VirtualMachine::load(&inst).profile().is_empty());
+  }
+
+  #[test]
+  fn test_closure() {
+    let inst = compile(
+      "(fun make (n) (fun (x) (+ x n))) \
+       (var add1 (make 1)) \
+       (var add5 (make 5)) \
+       (emit (add1 10)) \
+       (emit (add5 10))",
+    )
+    .unwrap();
+
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec![11.0.into(), 15.0.into()]);
+    assert_eq!(vm.stack.pop().unwrap(), Value::Null);
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_nested_fn() {
+    let inst = compile(
+      "(fun outer (n) \
+         (fun inner (x) (+ x n)) \
+         (fun twice (x) (inner (inner x))) \
+         (emit (twice 1)) \
+         (var f inner) \
+         (f 0)) \
+       (emit (outer 10))",
+    )
+    .unwrap();
+
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec![21.0.into(), 10.0.into()]);
+  }
+
+  #[test]
+  fn test_many_locals() {
+    // Locals aren't indexed by slot so there's no limit to how many a function defines
+    let vars = (0..300)
+      .map(|i| format!("(var v{} {})", i, i))
+      .collect::<Vec<_>>()
+      .join(" ");
+    let script = format!("(fun f (n) {} $v299) (f 0)", vars);
+    let inst = compile(&script).unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 299.0.into());
+  }
+
+  #[test]
+  fn test_nan() {
+    let inst = compile(
+      "(emit (is_nan (/ 0 0))) \
+       (emit (is_nan 1)) \
+       (emit (equal (/ 0 0) (/ 0 0)))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(
+      *values.borrow(),
+      vec![true.into(), false.into(), false.into()]
+    );
+
+    let nan = Value::Number(f64::NAN);
+
+    assert_ne!(nan, nan);
+    assert!(nan.strict_eq(&nan));
+    assert!(!nan.strict_eq(&1.0.into()));
+    assert!(Value::Number(1.0).strict_eq(&1.0.into()));
+  }
+
+  #[test]
+  fn test_to_string_parse_number() {
+    let inst = compile("(emit (to_string 42)) (emit (parse_number \"3.5\"))").unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec!["42".into(), 3.5.into()]);
+
+    let inst = compile("(parse_number \"abc\")").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::ParseNumber(value)) if value == "abc"));
+  }
+
+  #[test]
+  fn test_read_line() {
+    let inst = compile("(emit (read_line)) (emit (read_line)) (emit (read_line))").unwrap();
+    let (mut vm, values) =
+      with_emit(VirtualMachine::load(&inst).input(Cursor::new("first\r\nsecond")));
+    vm.run().unwrap();
+
+    assert_eq!(
+      *values.borrow(),
+      vec!["first".into(), "second".into(), Value::Null]
+    );
+  }
+
+  #[test]
+  fn test_builtin_args() {
+    let inst = compile("(var x 1) (emit (count 2 (+ 3 4) \"5\")) $x").unwrap();
+    let (vm, values) = with_emit(VirtualMachine::load(&inst));
+    let mut vm = vm.builtin("count", |args| {
+      assert_eq!(args, &[2.0.into(), 7.0.into(), "5".into()]);
+
+      Ok((args.len() as f64).into())
+    });
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec![3.0.into()]);
+    assert_eq!(vm.stack.pop().unwrap(), 1.0.into());
+    assert!(vm.stack.is_empty());
+
+    let inst = compile("(to_string 1 2)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::ArityMismatch {
+        expected: 1,
+        actual: 2
+      })
+    ));
+  }
+
+  #[test]
+  fn test_range() {
+    let array = |values: &[f64]| Value::from(values.iter().map(|&n| n.into()).collect::<Vec<_>>());
+
+    assert_eq!(run_value("(range 3)"), array(&[0.0, 1.0, 2.0]));
+    assert_eq!(
+      run_value("(range 1 10 2)"),
+      array(&[1.0, 3.0, 5.0, 7.0, 9.0])
+    );
+    assert_eq!(run_value("(range 3 0 (- 1))"), array(&[3.0, 2.0, 1.0]));
+    assert_eq!(run_value("(range 2 2)"), array(&[]));
+    assert_eq!(run_value("(to_string (range 3))"), "[0, 1, 2]".into());
+    assert!(matches!(
+      try_run_value("(range 0 3 0)"),
+      Err(VmError::BadRangeStep(_))
+    ));
+    assert!(matches!(
+      try_run_value("(range 3 0 1)"),
+      Err(VmError::BadRangeStep(_))
+    ));
+  }
+
+  #[test]
+  fn test_higher_order() {
+    let inst = compile(
+      "(fun inc (n) (+ n 1)) \
+       (fun add (a b) (+ a b)) \
+       (var step 10) \
+       (emit (map (range 1 4) inc)) \
+       (emit (reduce (range 1 4) add 0)) \
+       (emit (filter (range 5) (fun (n) (< n 2)))) \
+       (emit (map (range 2) (fun (n) (+ n $step))))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    let array = |values: &[f64]| Value::from(values.iter().map(|&n| n.into()).collect::<Vec<_>>());
+
+    assert_eq!(
+      *values.borrow(),
+      vec![
+        array(&[2.0, 3.0, 4.0]),
+        6.0.into(),
+        array(&[0.0, 1.0]),
+        array(&[10.0, 11.0]),
+      ]
+    );
+    assert_eq!(vm.stack.pop().unwrap(), Value::Null);
+    assert!(vm.stack.is_empty());
+
+    let inst = compile("(map (range 2) (fun (a b) a))").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::ArityMismatch {
+        expected: 2,
+        actual: 1
+      })
+    ));
+  }
+
+  #[test]
+  fn test_deep_copy() {
+    let run = |script, copy_on_assign| {
+      let inst = compile(script).unwrap();
+      let mut vm = VirtualMachine::load(&inst).with_copy_on_assign(copy_on_assign);
+      vm.run().unwrap();
+
+      vm.stack.pop().unwrap()
+    };
+
+    let aliased = "(var a (sb_new)) (var b $a) (sb_append $b \"x\") (sb_build $a)";
+    let copied = "(var a (sb_new)) (var b (deep_copy $a)) (sb_append $b \"x\") (sb_build $a)";
+
+    assert_eq!(run(aliased, false), "x".into());
+    assert_eq!(run(copied, false), "".into());
+    assert_eq!(run(aliased, true), "".into());
+  }
+
+  #[test]
+  fn test_str_mutation() {
+    let inst = compile(
+      "(var a (to_string \"ab\")) (var b $a) \
+       (str_append $b \"c\") (emit (to_string $a)) \
+       (str_append $a $a) (emit (to_string $b)) \
+       (str_clear $b) (emit (to_string $a))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(
+      *values.borrow(),
+      vec!["abc".into(), "abcabc".into(), "".into()]
+    );
+  }
+
+  #[test]
+  fn test_bytes() {
+    let inst = compile(
+      "(var bytes (from_hex \"00ff7f10\")) \
+       (emit $bytes) \
+       (emit (to_hex $bytes)) \
+       (emit (to_base64 $bytes)) \
+       (emit (equal (from_base64 (to_base64 $bytes)) $bytes)) \
+       (emit (from_hex (to_hex (from_base64 \"AP9/EA==\"))))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(
+      *values.borrow(),
+      vec![
+        Value::from(&[0x00, 0xff, 0x7f, 0x10][..]),
+        "00ff7f10".into(),
+        "AP9/EA==".into(),
+        true.into(),
+        Value::from(&[0x00, 0xff, 0x7f, 0x10][..]),
+      ]
+    );
+
+    let inst = compile("(from_base64 \"AP9\")").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::Decode(value)) if value == "AP9"));
+  }
+
+  #[test]
+  fn test_contains() {
+    let inst = compile(
+      "(emit (contains \"haystack\" \"st\")) \
+       (emit (contains \"haystack\" \"needle\")) \
+       (emit (index_of \"héllo\" \"llo\")) \
+       (emit (index_of \"haystack\" \"needle\"))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
 
-        let haystack = stack.pop()?.as_string()?;
-        let haystack = haystack.borrow();
-        let haystack = haystack.as_str();
+    assert_eq!(
+      *values.borrow(),
+      vec![true.into(), false.into(), 2.0.into(), (-1.0).into()]
+    );
+  }
+
+  #[test]
+  fn test_contains_element() {
+    let inst = compile(
+      "(emit (contains (range 3) 1)) \
+       (emit (contains (range 3) 3)) \
+       (emit (index_of (range 3) 2)) \
+       (emit (index_of (range 3) \"2\"))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(
+      *values.borrow(),
+      vec![true.into(), false.into(), 2.0.into(), (-1.0).into()]
+    );
+  }
+
+  #[test]
+  fn test_approx_eq() {
+    let inst = compile(
+      "(emit (approx_eq (/ 1 3) 0.3333 0.001)) \
+       (emit (approx_eq (/ 1 3) 0.3 0.001)) \
+       (emit (approx_eq 1 1 0))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
 
-        let index = haystack.find(needle).map(|val| val as f64).unwrap_or(-1.0);
+    assert_eq!(
+      *values.borrow(),
+      vec![true.into(), false.into(), true.into()]
+    );
 
-        stack.push(Value::Number(index))?;
+    let inst = compile("(approx_eq 1 \"1\" 0.1)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
 
-        Ok(())
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::TypeError {
+        expected: "number",
+        ..
       })
-      .builtin("readInNumber", |stack| {
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
-        let input = input.trim().parse::<f64>().unwrap();
+    ));
+  }
 
-        stack.push(Value::Number(input))?;
+  #[test]
+  fn test_string_builder() {
+    let inst = compile(
+      "(fun fill (b n) (if (equal n 0) b ((sb_append b \"ab\") (fill b (- n 1))))) \
+       (var b (sb_new)) \
+       (fill $b 1000) \
+       (emit (sb_build (sb_append (sb_append $b 1) \"!\")))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
 
-        Ok(())
-      });
+    let value = values.borrow()[0].as_string().unwrap();
+
+    assert_eq!(value.borrow().len(), 2002);
+    assert!(value.borrow().ends_with("ab1!"));
+  }
+
+  /// An output sink whose writes can be read back after it's given to a machine.
+  #[derive(Clone, Default)]
+  struct Output(Rc<RefCell<Vec<u8>>>);
+
+  impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+      self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+      Ok(())
+    }
+  }
+
+  #[test]
+  fn test_output() {
+    let output = Output::default();
+    let inst = compile("(print \"x\")").unwrap();
+    let mut vm = VirtualMachine::load(&inst).with_output(Box::new(output.clone()));
+    vm.run().unwrap();
+
+    assert_eq!(*output.0.borrow(), b"x");
+  }
+
+  #[test]
+  fn test_print_variants() {
+    let run = |script| {
+      let output = Output::default();
+      let inst = compile(script).unwrap();
+      let mut vm = VirtualMachine::load(&inst).with_output(Box::new(output.clone()));
+      vm.run().unwrap();
+
+      let output = output.0.borrow().clone();
+      String::from_utf8(output).unwrap()
+    };
+
+    assert_eq!(run("(print \"a\" 1 true) (print)"), "a1true");
+    assert_eq!(run("(println \"a\" 1 true) (println)"), "a1true\n\n");
+    assert_eq!(
+      run("(printlns \"a\" 1 true) (printlns \"b\")"),
+      "a 1 true\nb\n"
+    );
+  }
+
+  /// Compile and run a source with its output captured, followed by the error it failed with.
+  fn run_golden(src: &str) -> String {
+    let output = Output::default();
+    let inst = compile(src).unwrap();
+    let mut vm = VirtualMachine::load(&inst).with_output(Box::new(output.clone()));
+    let result = vm.run();
+
+    let mut output = String::from_utf8(output.0.borrow().clone()).unwrap();
+
+    if let Err(err) = result {
+      output += &format!("error: {}\n", err);
+    }
+
+    output
+  }
+
+  #[test]
+  fn test_golden_chal() {
+    let cases = [
+      (
+        "golden/fizzbuzz",
+        include_str!("../../data/golden/fizzbuzz.chal"),
+        include_str!("../../data/golden/fizzbuzz.expected"),
+      ),
+      (
+        "math",
+        include_str!("../../data/math.chal"),
+        include_str!("../../data/math.expected"),
+      ),
+    ];
+
+    for (name, src, expected) in &cases {
+      assert_eq!(run_golden(src), *expected, "Output of data/{}.chal", name);
+    }
+  }
+
+  #[test]
+  fn test_debug_stack() {
+    let output = Output::default();
+    let inst = compile("(+ 1 (* 2 ((debug_stack) 3)))").unwrap();
+    let mut vm = VirtualMachine::load(&inst).with_output(Box::new(output.clone()));
+    vm.run().unwrap();
+
+    assert_eq!(
+      String::from_utf8(output.0.borrow().clone()).unwrap(),
+      "stack (2): [Value::Number(1), Value::Number(2)]\n"
+    );
+    assert_eq!(vm.stack_depth(), 1);
+  }
+
+  #[test]
+  fn test_env() {
+    let inst = compile("(emit (env \"HOME\")) (emit (env \"UNSET\"))").unwrap();
+    let vars = [("HOME".to_string(), "/home/chal".to_string())];
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst).environment(vars.into()));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec!["/home/chal".into(), Value::Null]);
+  }
+
+  #[test]
+  fn test_empty_group() {
+    let inst = compile("(emit ()) (emit (equal () ()))").unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec![Value::Null, true.into()]);
+  }
+
+  #[test]
+  fn test_now() {
+    let inst = compile("(emit (now)) (emit (- (now) (now)))").unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst).clock(|| 1500.0));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec![1500.0.into(), 0.0.into()]);
+  }
+
+  #[test]
+  fn test_random() {
+    let inst = compile(
+      "(emit (random)) (emit (random)) \
+       (emit (random_int 1 7)) (emit (random_int 1 7))",
+    )
+    .unwrap();
+    let run = |seed| {
+      let (mut vm, values) = with_emit(VirtualMachine::load(&inst).with_seed(seed));
+      vm.run().unwrap();
+
+      let values = values.borrow();
+      values
+        .iter()
+        .map(|value| value.as_f64().unwrap())
+        .collect::<Vec<_>>()
+    };
+
+    let values = run(42);
+
+    assert_eq!(values, run(42));
+    assert_ne!(values, run(7));
+    assert!(values[..2].iter().all(|value| (0.0..1.0).contains(value)));
+    assert!(values[2..]
+      .iter()
+      .all(|value| value.fract() == 0.0 && (1.0..7.0).contains(value)));
+  }
+
+  #[test]
+  fn test_format() {
+    let inst = compile(
+      "(emit (format \"{} + {} = {}\" 1 2 3)) \
+       (emit (format \"{{{}}}\" \"x\"))",
+    )
+    .unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec!["1 + 2 = 3".into(), "{x}".into()]);
+
+    let inst = compile("(format \"{} + {} = {}\" 1 2)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::FormatMismatch {
+        placeholders: 3,
+        args: 2
+      })
+    ));
+  }
+
+  #[test]
+  fn test_format_non_string() {
+    let inst = compile("(emit (format \"{} {} {}\" true (if false 1) (range 2)))").unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(*values.borrow(), vec!["true null [0, 1]".into()]);
+
+    let inst = compile("(format true)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::TypeError {
+        expected: "string",
+        actual: Value::Bool(true)
+      })
+    ));
+  }
+
+  #[test]
+  fn test_min_max() {
+    let inst = compile("(emit (max 1 5 3)) (emit (min (- 0 2) 0)) (emit (max 4 4))").unwrap();
+    let (mut vm, values) = with_emit(VirtualMachine::load(&inst));
+    vm.run().unwrap();
+
+    assert_eq!(
+      *values.borrow(),
+      vec![5.0.into(), (-2.0).into(), 4.0.into()]
+    );
+
+    let inst = compile("(max 1 \"5\")").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::TypeError {
+        expected: "number",
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn test_if_value() {
+    assert_eq!(run_value("(var x (if 1 10 20)) $x"), 10.0.into());
+    assert_eq!(run_value("(var x (if 0 10 20)) $x"), 20.0.into());
+    // A branch which is a statement still leaves a value
+    assert_eq!(run_value("(var x (if 1 (var y 1))) $x"), Value::Null);
+  }
+
+  #[test]
+  fn test_if_truthiness() {
+    assert_eq!(run_value("(var x 5) (if $x \"yes\" \"no\")"), "yes".into());
+    assert_eq!(
+      run_value("(var x 5) (if (not $x) \"yes\" \"no\")"),
+      "no".into()
+    );
+    assert_eq!(
+      run_value("(var x \"\") (if (not $x) \"yes\" \"no\")"),
+      "yes".into()
+    );
+  }
+
+  #[test]
+  fn test_if_without_fallthrough() {
+    // Both a literal and a runtime condition take the implicit else branch
+    for script in &["(var x (if 0 1))", "(var c 0) (var x (if $c 1))"] {
+      let inst = compile(script).unwrap();
+      let mut vm = VirtualMachine::load(&inst);
+      vm.run().unwrap();
+
+      assert_eq!(vm.globals["x"], Value::Null);
+      assert!(vm.stack.is_empty());
+    }
+  }
+
+  #[test]
+  fn test_not() {
+    assert_eq!(run_value("(not 0)"), true.into());
+    assert_eq!(run_value("(not 5)"), false.into());
+    assert_eq!(run_value("(not \"\")"), true.into());
+    assert_eq!(run_value("(not (not \"x\"))"), true.into());
+  }
+
+  #[test]
+  fn test_not_bnot() {
+    // `not` negates truthiness while `!` complements the bits of an integer
+    assert_eq!(run_value("(var x 5) (not $x)"), false.into());
+    assert_eq!(run_value("(var x 5) (! $x)"), (!5u64 as f64).into());
+    assert_eq!(run_value("(var x 0) (not $x)"), true.into());
+    assert_eq!(run_value("(var x 0) (! $x)"), (!0u64 as f64).into());
+    assert_eq!(run_value("(var x true) (not $x)"), false.into());
+    assert!(matches!(
+      try_run_value("(var x true) (! $x)"),
+      Err(VmError::TypeError {
+        expected: "number",
+        ..
+      })
+    ));
+
+    // Rather than truncating to `0` or wrapping at 32 bits
+    for script in &["(! (- 1))", "(! 1.5)"] {
+      assert!(
+        matches!(
+          try_run_value(script),
+          Err(VmError::TypeError {
+            expected: "unsigned integer",
+            ..
+          })
+        ),
+        "{}",
+        script
+      );
+    }
+  }
+
+  #[test]
+  fn test_bool_comparison() {
+    assert_eq!(run_value("(equal true true)"), true.into());
+    assert_eq!(run_value("(neq true false)"), true.into());
+    assert_eq!(run_value("(< false true)"), true.into());
+    assert_eq!(run_value("(> false true)"), false.into());
+    assert_eq!(run_value("(if (< false true) 1 2)"), 1.0.into());
+  }
+
+  #[test]
+  fn test_number_comparison() {
+    // Integer and float literals are both numbers
+    assert_eq!(run_value("(equal 3 3.0)"), true.into());
+    assert_eq!(run_value("(if (equal 3 3.0) 1 2)"), 1.0.into());
+    assert_eq!(run_value("(< 2 2.5)"), true.into());
+  }
+
+  #[test]
+  fn test_compound_pops() {
+    let inst = compile("(1 2 3)").unwrap();
+
+    assert_eq!(
+      inst
+        .code
+        .iter()
+        .filter(|instr| matches!(instr, Instruction::Pop))
+        .count(),
+      2
+    );
+
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 3.0.into());
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_eval() {
+    let inst = compile("(eval \"(+ 40 2)\")").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 42.0.into());
+    assert!(vm.stack.is_empty());
+
+    let inst = compile("(eval \"(+ 40 2\")").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::Compile(_))));
+  }
+
+  #[test]
+  fn test_import() {
+    let sources = Sources::load(concat!(
+      env!("CARGO_MANIFEST_DIR"),
+      "/data/import/main.chal"
+    ))
+    .unwrap();
+
+    let inst = compile_sources(&sources).unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 5.0.into());
+  }
+
+  #[test]
+  fn test_error_span() {
+    let program = compile("(var x \"a\")\n\n(print (* $x 2))").unwrap();
+    let mut vm = VirtualMachine::load(&program);
+
+    assert!(matches!(vm.run(), Err(VmError::InvalidOperands(..))));
+    assert!(format!("{:?}", vm.span().unwrap()).starts_with("line 3,"));
+  }
+
+  #[test]
+  fn test_spanned_error() {
+    let program = compile("(var x 1)\n(print (- true))").unwrap();
+    let mut vm = VirtualMachine::load(&program);
+    let err = vm.run_spanned().unwrap_err();
+
+    assert!(matches!(err.error, VmError::TypeError { .. }));
+    assert_eq!(
+      err.to_string(),
+      "TypeError { expected: \"number\", actual: Value::Bool(true) }\n\
+       --> line 2, column 9\n\
+       2 | (print (- true))\n\
+       \x20           ^\n"
+    );
+
+    // Without source locations only the error is shown
+    let mut vm = VirtualMachine::new(&[Instruction::LdTrue, Instruction::Neg]);
+    let err = vm.run_spanned().unwrap_err();
+
+    assert_eq!(err.to_string(), err.error.to_string());
+  }
+
+  #[test]
+  fn test_steps() {
+    let label = Label::default();
+    let inst = [
+      Instruction::LdF64(1.0),
+      Instruction::Jmp(label),
+      Instruction::LdF64(2.0),
+      Instruction::Label(label),
+      Instruction::LdF64(3.0),
+    ];
+    let mut vm = VirtualMachine::new(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 3.0.into());
+    assert_eq!(vm.stack.pop().unwrap(), 1.0.into());
+    assert!(vm.stack.is_empty());
+
+    // Calling a function value jumps to its address, returning jumps back after the call
+    let inst = [
+      Instruction::CallF("target", 0),
+      Instruction::CallDyn(0),
+      Instruction::Halt,
+      Instruction::LdF64(4.0),
+      Instruction::Ret,
+    ];
+    let mut vm =
+      VirtualMachine::new(&inst).builtin("target", |_| Ok(Value::Function { addr: 3, arity: 0 }));
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 4.0.into());
+    assert!(vm.stack.is_empty());
+  }
+
+  #[test]
+  fn test_bad_jump_target() {
+    let inst = [Instruction::CallF("target", 0), Instruction::CallDyn(0)];
+    let mut vm = VirtualMachine::new(&inst).builtin("target", |_| {
+      Ok(Value::Function {
+        addr: 100,
+        arity: 0,
+      })
+    });
+
+    assert!(matches!(vm.run(), Err(VmError::BadJumpTarget(100))));
+
+    let label = Label::default();
+    let inst = [Instruction::Jmp(label)];
+    let mut vm = VirtualMachine::new(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::UnknownLabel(to)) if to == label));
+
+    // The end of the script is a valid target
+    let inst = [
+      Instruction::CallF("target", 0),
+      Instruction::CallDyn(0),
+      Instruction::Nop,
+    ];
+    let mut vm =
+      VirtualMachine::new(&inst).builtin("target", |_| Ok(Value::Function { addr: 3, arity: 0 }));
+
+    assert!(vm.run().is_ok());
+  }
+
+  #[test]
+  fn test_malformed_code() {
+    let label = Label::default();
+    let inst = [Instruction::LdFn(label, 0)];
+    let mut vm = VirtualMachine::new(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::UnknownLabel(to)) if to == label));
+
+    let local = Local::default();
+    let inst = [Instruction::LdLoc(local)];
+    let mut vm = VirtualMachine::new(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::UndefinedLocal(at)) if at == local));
+
+    let inst = [Instruction::LdNull, Instruction::Ret];
+    let mut vm = VirtualMachine::new(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::ReturnOutsideFunction)));
+  }
+
+  #[test]
+  fn test_call_dyn_errors() {
+    let inst = compile("(fun sub (a b) (- a b)) (var f sub) (f 10)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::ArityMismatch {
+        expected: 2,
+        actual: 1
+      })
+    ));
+
+    let inst = compile("(var f 1) (f 10)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(vm.run(), Err(VmError::NotCallable(_))));
+  }
+
+  /// A function called with `argc` arguments which leaves `values` on the stack when it returns.
+  fn ret_with(argc: usize, values: usize) -> Vec<Instruction<'static>> {
+    let label = Label::default();
+    let mut inst = vec![Instruction::LdNull; argc];
+
+    inst.extend([
+      Instruction::Call(label, argc),
+      Instruction::Halt,
+      Instruction::Label(label),
+    ]);
+    inst.extend((0..values).map(|i| Instruction::LdF64(i as f64)));
+    inst.push(Instruction::Ret);
+    inst
+  }
+
+  #[test]
+  fn test_balanced_ret() {
+    let inst = ret_with(2, 1);
+    let mut vm = VirtualMachine::new(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 0.0.into());
+    assert!(vm.stack.is_empty());
+
+    for script in &[
+      "(fun f (a b) (var c (+ a b)) (if $c $c 0)) (f 1 2) (f 3 4)",
+      // A body ending in a statement and an early return from a branch
+      "(fun f (n) (var x 1)) (var r (f 7)) $r",
+      "(fun f (n) (+ 1 (if n 2 3)) (if n (return n)) 0) (f 2)",
+    ] {
+      let inst = compile(script).unwrap();
+      let mut vm = VirtualMachine::load(&inst);
+      vm.run().unwrap();
+
+      assert_eq!(vm.stack.len(), 1, "{}", script);
+    }
+
+    // A return between operands is rejected rather than stranding them
+    assert!(compile("(fun f (n) (+ 1 (return n))) (f 2)").is_err());
+  }
+
+  #[test]
+  fn test_ldarg() {
+    let inst = compile("(fun f (a b)(- a b))(f 10 3)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert!(inst.code.contains(&Instruction::LdArg(1)));
+    assert_eq!(vm.stack.pop().unwrap(), 7.0.into());
+    assert!(vm.stack.is_empty());
+
+    let inst = [Instruction::LdArg(0)];
+    let mut vm = VirtualMachine::new(&inst);
 
+    assert!(matches!(vm.run(), Err(VmError::UnknownArg(0))));
+  }
+
+  #[test]
+  fn test_unbalanced_ret() {
+    for (argc, values, actual) in [(1, 2, 3), (1, 0, 1)] {
+      let inst = ret_with(argc, values);
+      let mut vm = VirtualMachine::new(&inst);
+
+      assert!(matches!(
+        vm.run(),
+        Err(VmError::UnbalancedReturn { expected: 2, actual: at }) if at == actual
+      ));
+    }
+  }
+
+  #[test]
+  fn test_swap() {
+    let inst = [
+      Instruction::LdF64(1.0),
+      Instruction::LdF64(2.0),
+      Instruction::Swap,
+    ];
+    let mut vm = VirtualMachine::new(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 1.0.into());
+    assert_eq!(vm.stack.pop().unwrap(), 2.0.into());
+  }
+
+  #[test]
+  fn test_dup_over() {
+    let inst = [
+      Instruction::LdF64(1.0),
+      Instruction::LdF64(2.0),
+      Instruction::Over,
+      Instruction::Dup,
+    ];
+    let mut vm = VirtualMachine::new(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.len(), 4);
+    assert_eq!(vm.stack.pop().unwrap(), 1.0.into());
+    assert_eq!(vm.stack.pop().unwrap(), 1.0.into());
+    assert_eq!(vm.stack.pop().unwrap(), 2.0.into());
+    assert_eq!(vm.stack.pop().unwrap(), 1.0.into());
+
+    let inst = compile("(fun square (x) (* x x)) (square 7)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 49.0.into());
+  }
+
+  #[test]
+  fn test_quote() {
+    let inst = compile("(quote (1 2 3))").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(
+      vm.stack.pop().unwrap(),
+      Value::from(vec![1.0.into(), 2.0.into(), 3.0.into()])
+    );
+
+    // The head of a quoted list is data, not a function to call
+    let inst = compile("(quote (add (1) ()))").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(
+      vm.stack.pop().unwrap(),
+      Value::from(vec![
+        "add".into(),
+        Value::from(vec![1.0.into()]),
+        Value::from(vec![]),
+      ])
+    );
+  }
+
+  #[test]
+  fn test_coercion() {
+    let run = |script, mode| {
+      let inst = compile(script).unwrap();
+      let mut vm = VirtualMachine::load(&inst).with_coercion(mode);
+
+      vm.run().map(|_| vm.stack.pop().unwrap())
+    };
+
+    assert!(matches!(
+      run("(+ 1 \"2\")", CoercionMode::Strict),
+      Err(VmError::InvalidOperands(..))
+    ));
+    assert!(matches!(
+      run("(* true 2)", CoercionMode::Strict),
+      Err(VmError::InvalidOperands(..))
+    ));
+
+    assert_eq!(
+      run("(+ 1 \"2\")", CoercionMode::Lenient).unwrap(),
+      "12".into()
+    );
+    assert_eq!(
+      run("(+ \"a\" \"b\")", CoercionMode::Lenient).unwrap(),
+      "ab".into()
+    );
+    assert_eq!(
+      run("(* \" 3 \" 2)", CoercionMode::Lenient).unwrap(),
+      6.0.into()
+    );
+    assert_eq!(
+      run("(- true 3)", CoercionMode::Lenient).unwrap(),
+      (-2.0).into()
+    );
+    assert_eq!(
+      run("(+ 1 true)", CoercionMode::Lenient).unwrap(),
+      2.0.into()
+    );
+    assert!(matches!(
+      run("(* \"x\" 2)", CoercionMode::Lenient),
+      Err(VmError::InvalidOperands(..))
+    ));
+  }
+
+  #[test]
+  fn test_operand_order() {
+    let inst = compile("(- 5 3)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 2.0.into());
+
+    let inst = compile("(/ 6 3)").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
     vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), 2.0.into());
   }
 
   #[test]
@@ -406,7 +2924,7 @@ mod tests {
   fn test_ld_import() {
     let mut vm = VirtualMachine::new(&[Instruction::LdImport("printf")])
       //
-      .builtin("printf", |_| Ok(()));
+      .builtin("printf", |_| Ok(Value::Null));
 
     vm.run().unwrap();
 
@@ -414,6 +2932,84 @@ mod tests {
     assert!(matches!(vm.stack.pop().unwrap(), Value::BuiltIn(_)));
   }
 
+  #[test]
+  fn test_link() {
+    let mut program = CompiledProgram {
+      code: vec![
+        Instruction::LdImport("printf"),
+        Instruction::LdImport("print"),
+      ],
+      ..Default::default()
+    };
+    let mut vm = VirtualMachine::new(&[]).builtin("printf", |_| Ok(Value::Null));
+
+    vm.link(&mut program.code).unwrap();
+
+    assert_eq!(
+      program.code[0],
+      Instruction::LdBuiltin(vm.builtin_names["printf"])
+    );
+
+    // Default built-ins are registered in the same order by every machine, and replacing one
+    // keeps its index
+    let mut code = [Instruction::LdImport("print")];
+    VirtualMachine::new(&[])
+      .with_output(Box::new(io::sink()))
+      .link(&mut code)
+      .unwrap();
+
+    assert_eq!(code[0], program.code[1]);
+
+    vm.reload(&program);
+    vm.run().unwrap();
+
+    assert!(matches!(vm.stack.pop().unwrap(), Value::BuiltIn(_)));
+    assert!(matches!(vm.stack.pop().unwrap(), Value::BuiltIn(_)));
+  }
+
+  #[test]
+  fn test_link_undefined_import() {
+    let mut code = [Instruction::LdNull, Instruction::LdImport("nope")];
+    let vm = VirtualMachine::new(&[]);
+
+    assert!(matches!(
+      vm.link(&mut code),
+      Err(VmError::UndefinedImport(name)) if name == "nope"
+    ));
+    assert_eq!(code[1], Instruction::LdImport("nope"));
+  }
+
+  #[test]
+  fn test_undefined_builtin() {
+    let inst = compile("(var x (nosuch))").unwrap();
+    let mut vm = VirtualMachine::load(&inst);
+
+    assert!(matches!(
+      vm.run(),
+      Err(VmError::UndefinedBuiltin(name)) if name == "nosuch"
+    ));
+  }
+
+  #[test]
+  fn test_builtin_eq() {
+    let inst = [
+      Instruction::LdImport("printf"),
+      Instruction::LdImport("printf"),
+      Instruction::Eq,
+      Instruction::LdImport("printf"),
+      Instruction::LdImport("print"),
+      Instruction::Eq,
+    ];
+    let mut vm = VirtualMachine::new(&inst)
+      .builtin("printf", |_| Ok(Value::Null))
+      .builtin("print", |_| Ok(Value::Null));
+
+    vm.run().unwrap();
+
+    assert_eq!(vm.stack.pop().unwrap(), false.into());
+    assert_eq!(vm.stack.pop().unwrap(), true.into());
+  }
+
   // #[test]
   // fn test_ld_loc() {
   //   let mut vm = VirtualMachine::new(&[
@@ -0,0 +1,68 @@
+//! Pseudo-random number generation.
+
+use std::{
+  cell::Cell,
+  time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A xorshift64 pseudo-random number generator.
+///
+/// Not suitable for anything requiring unpredictability, the same seed always produces the
+/// same sequence.
+#[derive(Debug, Clone)]
+pub struct Rng(Cell<u64>);
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    let rng = Self(Cell::new(0));
+    rng.seed(seed);
+    rng
+  }
+
+  /// Create a generator seeded from the system clock.
+  pub fn from_time() -> Self {
+    let nanos = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|elapsed| elapsed.as_nanos() as u64)
+      .unwrap_or_default();
+
+    Self::new(nanos)
+  }
+
+  pub fn seed(&self, seed: u64) {
+    // xorshift never leaves a state of zero
+    self
+      .0
+      .set(if seed == 0 { 0x9e3779b97f4a7c15 } else { seed });
+  }
+
+  pub fn next_u64(&self) -> u64 {
+    let mut x = self.0.get();
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    self.0.set(x);
+    x
+  }
+
+  /// Get a number in `[0, 1)`.
+  pub fn next_f64(&self) -> f64 {
+    // The top 53 bits fill the mantissa of a double exactly
+    (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::Rng;
+
+  #[test]
+  fn test_next_f64_range() {
+    let rng = Rng::new(0);
+
+    for _ in 0..1000 {
+      let value = rng.next_f64();
+      assert!((0.0..1.0).contains(&value));
+    }
+  }
+}
@@ -1,6 +1,9 @@
 use std::fmt::Debug;
 
-use super::{error::VmResult, types::Value};
+use super::{
+  error::{VmError, VmResult},
+  types::Value,
+};
 #[derive(Clone)]
 pub struct Stack {
   pos: usize,
@@ -16,14 +19,8 @@ impl Stack {
   }
 
   pub fn pop(&mut self) -> VmResult<Value> {
-    // println!(
-    //   "  pop() - pos: {}, item: {:?}",
-    //   self.pos.saturating_sub(1),
-    //   self.items[self.pos.saturating_sub(1)]
-    // );
-
     if self.pos == 0 {
-      todo!("Stack underflow")
+      return Err(VmError::StackUnderflow);
     }
 
     let actual = self.pos - 1;
@@ -34,19 +31,38 @@ impl Stack {
     Ok(item)
   }
 
+  /// Drop the top `size` values.
   pub fn clear(&mut self, size: usize) {
-    self.pos = self.pos.saturating_sub(size);
+    let pos = self.pos.saturating_sub(size);
+
+    // Release abandoned values rather than holding them until they're overwritten
+    self.items[pos..self.pos].fill(Value::Null);
+    self.pos = pos;
+  }
+
+  /// Get a value by its index from the bottom of the stack.
+  pub fn get(&self, index: usize) -> Option<&Value> {
+    self.items[..self.pos].get(index)
+  }
+
+  /// Replace a value by its index from the bottom of the stack, `None` if it's past the top.
+  pub fn set(&mut self, index: usize, value: Value) -> Option<()> {
+    self.items[..self.pos]
+      .get_mut(index)
+      .map(|item| *item = value)
+  }
+
+  pub fn len(&self) -> usize {
+    self.pos
   }
 
-  pub fn is_empty(&mut self) -> bool {
+  pub fn is_empty(&self) -> bool {
     self.pos == 0
   }
 
   pub fn push(&mut self, value: Value) -> VmResult<()> {
-    println!("  push({:?}) - pos: {}", value, self.pos);
-
-    if self.pos >= self.items.len() - 1 {
-      todo!("Stack overflow")
+    if self.pos >= self.items.len() {
+      return Err(VmError::StackOverflow(self.items.len()));
     }
 
     self.items[self.pos] = value;
@@ -54,24 +70,46 @@ impl Stack {
 
     Ok(())
   }
+}
 
-  pub fn push_top(&mut self, value: Value) -> VmResult<()> {
-    let top = self.items.len() - 1;
-    self.items[top] = value;
-
-    Ok(())
+impl Debug for Stack {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{:?}", &self.items[..self.pos])
   }
+}
 
-  pub fn pop_top(&mut self) -> VmResult<Value> {
-    let top = self.items.len() - 1;
-    let item = self.items[top].clone();
+#[cfg(test)]
+mod tests {
+  use super::Stack;
+  use crate::vm::{error::VmError, types::Value};
+  use std::{cell::RefCell, rc::Rc};
 
-    Ok(item)
+  #[test]
+  fn test_clear() {
+    let value = Rc::new(RefCell::new("value".to_string()));
+    let mut stack = Stack::new(8);
+
+    stack.push(1.0.into()).unwrap();
+    stack.push(value.clone().into()).unwrap();
+    stack.push(value.clone().into()).unwrap();
+
+    assert_eq!(Rc::strong_count(&value), 3);
+
+    stack.clear(2);
+
+    assert_eq!(Rc::strong_count(&value), 1);
+    assert_eq!(stack.len(), 1);
+    assert!(stack.items[1..].iter().all(|item| *item == Value::Null));
   }
-}
 
-impl Debug for Stack {
-  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    write!(f, "{:?}", &self.items[..self.pos])
+  #[test]
+  fn test_pop_empty() {
+    let mut stack = Stack::new(8);
+
+    stack.push(1.0.into()).unwrap();
+
+    assert_eq!(stack.pop().unwrap(), 1.0.into());
+    assert!(matches!(stack.pop(), Err(VmError::StackUnderflow)));
+    assert!(stack.is_empty());
   }
 }
@@ -1,21 +1,49 @@
-use super::{error::VmResult, BuiltInRc};
-use crate::ir::instr::Label;
+use super::{
+  error::{VmError, VmResult},
+  BuiltInRc,
+};
+use crate::{
+  ir::instr::{Constant, Label},
+  util::encoding::to_hex,
+};
 use std::{
-  borrow::{Borrow, Cow},
+  borrow::Cow,
   cell::RefCell,
+  convert::TryFrom,
   fmt::{Debug, Display},
+  hash::{Hash, Hasher},
   ops::Deref,
   rc::Rc,
 };
 
-#[derive(Clone)]
+/// A value of a script.
+///
+/// Strings, builders, bytes and arrays are shared, cloning one or assigning it to another variable
+/// aliases the same buffer so changes through one are seen through the other. Use
+/// [`Value::deep_copy`] or the `deep_copy` built-in for an independent copy.
+#[derive(Clone, Default)]
 pub enum Value {
+  #[default]
   Null,
   Addr(usize),
   Bool(bool),
   Number(f64),
   String(Rc<RefCell<String>>),
+  /// A string appended to in place by `sb_append`.
+  Builder(Rc<RefCell<String>>),
+  /// Binary data, shown as hex.
+  Bytes(Rc<RefCell<Vec<u8>>>),
+  Array(Rc<RefCell<Vec<Value>>>),
   BuiltIn(BuiltInRc),
+  Function {
+    addr: usize,
+    arity: usize,
+  },
+  Closure {
+    addr: usize,
+    arity: usize,
+    captured: Vec<Value>,
+  },
 }
 
 impl Value {
@@ -23,14 +51,140 @@ impl Value {
     match &self {
       Self::String(value) => Ok(value.clone()),
       Self::Number(value) => Ok(Rc::new(RefCell::new(value.to_string()))),
-      _ => todo!("Bad type"),
+      _ => Err(VmError::TypeError {
+        expected: "string",
+        actual: self.clone(),
+      }),
+    }
+  }
+
+  /// Copy the contents of strings, builders, bytes and arrays rather than sharing them.
+  pub fn deep_copy(&self) -> Self {
+    match self {
+      Self::String(value) => Self::String(Rc::new(RefCell::new(value.borrow().clone()))),
+      Self::Builder(value) => Self::Builder(Rc::new(RefCell::new(value.borrow().clone()))),
+      Self::Bytes(value) => Self::Bytes(Rc::new(RefCell::new(value.borrow().clone()))),
+      Self::Array(values) => values
+        .borrow()
+        .iter()
+        .map(Value::deep_copy)
+        .collect::<Vec<_>>()
+        .into(),
+      Self::Closure {
+        addr,
+        arity,
+        captured,
+      } => Self::Closure {
+        addr: *addr,
+        arity: *arity,
+        captured: captured.iter().map(Value::deep_copy).collect(),
+      },
+      value => value.clone(),
+    }
+  }
+
+  pub fn as_builder(&self) -> VmResult<Rc<RefCell<String>>> {
+    match &self {
+      Self::Builder(value) => Ok(value.clone()),
+      _ => Err(VmError::TypeError {
+        expected: "builder",
+        actual: self.clone(),
+      }),
+    }
+  }
+
+  pub fn as_bytes(&self) -> VmResult<Rc<RefCell<Vec<u8>>>> {
+    match &self {
+      Self::Bytes(value) => Ok(value.clone()),
+      _ => Err(VmError::TypeError {
+        expected: "bytes",
+        actual: self.clone(),
+      }),
+    }
+  }
+
+  pub fn as_array(&self) -> VmResult<Rc<RefCell<Vec<Value>>>> {
+    match &self {
+      Self::Array(values) => Ok(values.clone()),
+      _ => Err(VmError::TypeError {
+        expected: "array",
+        actual: self.clone(),
+      }),
+    }
+  }
+
+  /// Returns `false` for null, `false`, zero, `NaN` and empty strings.
+  pub fn is_truthy(&self) -> bool {
+    match self {
+      Self::Null => false,
+      Self::Bool(value) => *value,
+      Self::Number(value) => *value != 0.0 && !value.is_nan(),
+      Self::String(value) => !value.borrow().is_empty(),
+      _ => true,
+    }
+  }
+
+  /// Compare values treating `NaN` as equal to `NaN`.
+  ///
+  /// [`PartialEq`] follows IEEE 754 so `NaN` is never equal to anything, including itself,
+  /// which is what `equal` and the conditional jumps use.
+  pub fn strict_eq(&self, other: &Self) -> bool {
+    let all_eq = |values: &[Value], others: &[Value]| {
+      values.len() == others.len() && values.iter().zip(others).all(|(a, b)| a.strict_eq(b))
+    };
+
+    match (self, other) {
+      (Self::Number(value), Self::Number(other)) if value.is_nan() => other.is_nan(),
+      (Self::Array(values), Self::Array(others)) => all_eq(&values.borrow(), &others.borrow()),
+      (
+        Self::Closure { addr, captured, .. },
+        Self::Closure {
+          addr: other,
+          captured: other_captured,
+          ..
+        },
+      ) => addr == other && all_eq(captured, other_captured),
+      _ => self == other,
+    }
+  }
+
+  /// Get a number as an integer, failing for numbers with a fraction or out of range.
+  pub fn as_i64(&self) -> VmResult<i64> {
+    match self.as_f64()? {
+      value
+        if value.fract() == 0.0
+          && (-9.223372036854776e18..9.223372036854776e18).contains(&value) =>
+      {
+        Ok(value as i64)
+      }
+      _ => Err(VmError::TypeError {
+        expected: "integer",
+        actual: self.clone(),
+      }),
+    }
+  }
+
+  /// Get a number as an unsigned integer, failing for negative numbers, numbers with a fraction
+  /// or out of range.
+  pub fn as_u64(&self) -> VmResult<u64> {
+    match self.as_f64()? {
+      value if value.fract() == 0.0 && (0.0..1.8446744073709552e19).contains(&value) => {
+        Ok(value as u64)
+      }
+      _ => Err(VmError::TypeError {
+        expected: "unsigned integer",
+        actual: self.clone(),
+      }),
     }
   }
 
   pub fn as_f64(&self) -> VmResult<f64> {
     match &self {
       Self::Number(value) => Ok(*value),
-      _ => todo!("Bad type"),
+      _ => Err(VmError::TypeError {
+        expected: "number",
+        actual: self.clone(),
+      }),
     }
   }
 }
@@ -43,11 +197,52 @@ impl Debug for Value {
       Self::Bool(value) => write!(f, "Value::Bool({})", value),
       Self::Number(value) => write!(f, "Value::Number({})", value),
       Self::String(value) => write!(f, "Value::String({})", value.deref().borrow()),
+      Self::Builder(value) => write!(f, "Value::Builder({})", value.deref().borrow()),
+      Self::Bytes(value) => write!(f, "Value::Bytes({})", to_hex(&value.borrow())),
+      Self::Array(values) => write!(f, "Value::Array({:?})", values.deref().borrow()),
       Self::BuiltIn(_) => write!(f, "Value::Null"),
+      Self::Function { addr, arity } => write!(f, "Value::Function({}, {})", addr, arity),
+      Self::Closure {
+        addr,
+        arity,
+        captured,
+      } => write!(f, "Value::Closure({}, {}, {:?})", addr, arity, captured),
+    }
+  }
+}
+
+/// Hashes agree with [`PartialEq`] and [`Value::strict_eq`], numbers hash their bits with `-0.0`
+/// treated as `0.0` and every `NaN` the same, strings, bytes and arrays their contents and
+/// builders and built-ins their address.
+///
+/// Strings, bytes and arrays are shared so one mustn't be modified while it's used as a key.
+impl Hash for Value {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    std::mem::discriminant(self).hash(state);
+
+    match self {
+      Self::Null => {}
+      Self::Addr(addr) => addr.hash(state),
+      Self::Bool(value) => value.hash(state),
+      Self::Number(value) if *value == 0.0 => 0f64.to_bits().hash(state),
+      Self::Number(value) if value.is_nan() => f64::NAN.to_bits().hash(state),
+      Self::Number(value) => value.to_bits().hash(state),
+      Self::String(value) => value.borrow().hash(state),
+      Self::Builder(value) => (Rc::as_ptr(value) as usize).hash(state),
+      Self::Bytes(value) => value.borrow().hash(state),
+      Self::Array(values) => values.borrow().hash(state),
+      Self::BuiltIn(value) => (Rc::as_ptr(value) as *const () as usize).hash(state),
+      Self::Function { addr, .. } => addr.hash(state),
+      Self::Closure { addr, captured, .. } => {
+        addr.hash(state);
+        captured.hash(state);
+      }
     }
   }
 }
 
+/// Values are equal when they hold equal contents, functions compare by address and builders and
+/// built-ins by identity so one is only equal to itself.
 impl PartialEq for Value {
   fn eq(&self, other: &Self) -> bool {
     match self {
@@ -56,42 +251,89 @@ impl PartialEq for Value {
       Self::Bool(value) => matches!(other, Self::Bool(other) if value == other),
       Self::Number(value) => matches!(other, Self::Number(other) if value == other),
       Self::String(value) => matches!(other, Self::String(other) if value == other),
-      Self::BuiltIn(_) => false,
+      Self::Builder(value) => matches!(other, Self::Builder(other) if Rc::ptr_eq(value, other)),
+      Self::Bytes(value) => matches!(other, Self::Bytes(other) if value == other),
+      Self::Array(values) => matches!(other, Self::Array(other) if values == other),
+      Self::BuiltIn(value) => matches!(other, Self::BuiltIn(other) if Rc::ptr_eq(value, other)),
+      Self::Function { addr, .. } => {
+        matches!(other, Self::Function { addr: other, .. } if addr == other)
+      }
+      Self::Closure { addr, captured, .. } => matches!(
+        other,
+        Self::Closure { addr: other, captured: other_captured, .. }
+          if addr == other && captured == other_captured
+      ),
     }
   }
 }
 
+/// A value used as a map key, equal by [`Value::strict_eq`] so a `NaN` key can be found again.
+#[derive(Debug, Clone)]
+pub struct ValueKey(pub Value);
+
+impl PartialEq for ValueKey {
+  fn eq(&self, other: &Self) -> bool {
+    self.0.strict_eq(&other.0)
+  }
+}
+
+impl Eq for ValueKey {}
+
+impl Hash for ValueKey {
+  fn hash<H: Hasher>(&self, state: &mut H) {
+    self.0.hash(state);
+  }
+}
+
 impl PartialOrd for Value {
   fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
     match self {
       Self::Null => None,
       Self::Addr(_) => None,
-      Self::Bool(_) => None,
+      Self::Bool(value) => match other {
+        Self::Bool(other) => value.partial_cmp(other),
+        _ => None,
+      },
       Self::Number(value) => match other {
         Self::Number(other) => value.partial_cmp(other),
         _ => None,
       },
       Self::String(_) => None,
+      Self::Builder(_) => None,
+      Self::Bytes(_) => None,
+      Self::Array(_) => None,
       Self::BuiltIn(_) => None,
+      Self::Function { .. } => None,
+      Self::Closure { .. } => None,
     }
   }
 }
 
-impl Default for Value {
-  fn default() -> Self {
-    Value::Null
-  }
-}
-
 impl Display for Value {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     match self {
       Self::Null => write!(f, "null"),
-      Self::Addr(_) => todo!(),
-      Self::BuiltIn(_) => todo!(),
+      Self::Addr(addr) => write!(f, "<addr@{}>", addr),
+      Self::BuiltIn(_) => write!(f, "<builtin>"),
+      Self::Function { addr, .. } => write!(f, "<function@{}>", addr),
+      Self::Closure { addr, .. } => write!(f, "<closure@{}>", addr),
       Self::Bool(value) => write!(f, "{}", value),
       Self::Number(value) => write!(f, "{}", value),
-      Self::String(value) => write!(f, "{}", value.deref().borrow()),
+      Self::String(value) | Self::Builder(value) => write!(f, "{}", value.deref().borrow()),
+      Self::Bytes(value) => write!(f, "{}", to_hex(&value.borrow())),
+      Self::Array(values) => {
+        write!(f, "[")?;
+
+        for (i, value) in values.borrow().iter().enumerate() {
+          if i > 0 {
+            write!(f, ", ")?;
+          }
+
+          write!(f, "{}", value)?;
+        }
+
+        write!(f, "]")
+      }
     }
   }
 }
@@ -114,6 +356,33 @@ impl From<f64> for Value {
   }
 }
 
+impl From<i64> for Value {
+  fn from(value: i64) -> Self {
+    Value::Number(value as f64)
+  }
+}
+
+impl From<i32> for Value {
+  fn from(value: i32) -> Self {
+    Value::Number(value.into())
+  }
+}
+
+impl From<u32> for Value {
+  fn from(value: u32) -> Self {
+    Value::Number(value.into())
+  }
+}
+
+impl From<&Constant<'_>> for Value {
+  fn from(value: &Constant<'_>) -> Self {
+    match value {
+      Constant::Number(value) => (*value).into(),
+      Constant::String(value) => value.as_ref().into(),
+    }
+  }
+}
+
 impl From<&str> for Value {
   fn from(value: &str) -> Self {
     value.to_string().into()
@@ -126,21 +395,196 @@ impl<'a> From<Cow<'a, str>> for Value {
   }
 }
 
-impl<'a> From<String> for Value {
+impl From<String> for Value {
   fn from(value: String) -> Self {
     Rc::new(RefCell::new(value)).into()
   }
 }
 
-impl<'a> From<Rc<RefCell<String>>> for Value {
+impl From<&[u8]> for Value {
+  fn from(value: &[u8]) -> Self {
+    Self::Bytes(Rc::new(RefCell::new(value.to_vec())))
+  }
+}
+
+impl From<Vec<Value>> for Value {
+  fn from(values: Vec<Value>) -> Self {
+    Self::Array(Rc::new(RefCell::new(values)))
+  }
+}
+
+impl From<Rc<RefCell<String>>> for Value {
   fn from(value: Rc<RefCell<String>>) -> Self {
     Self::String(value)
   }
 }
 
+impl TryFrom<Value> for f64 {
+  type Error = VmError;
+
+  fn try_from(value: Value) -> VmResult<Self> {
+    value.as_f64()
+  }
+}
+
+/// Strings and builders, without converting other values to strings the way
+/// [`Value::as_string`] does.
+impl TryFrom<Value> for String {
+  type Error = VmError;
+
+  fn try_from(value: Value) -> VmResult<Self> {
+    match &value {
+      Value::String(value) | Value::Builder(value) => Ok(value.borrow().clone()),
+      _ => Err(VmError::TypeError {
+        expected: "string",
+        actual: value,
+      }),
+    }
+  }
+}
+
+/// Booleans only, use [`Value::is_truthy`] for the truthiness of other values.
+impl TryFrom<Value> for bool {
+  type Error = VmError;
+
+  fn try_from(value: Value) -> VmResult<Self> {
+    match value {
+      Value::Bool(value) => Ok(value),
+      value => Err(VmError::TypeError {
+        expected: "bool",
+        actual: value,
+      }),
+    }
+  }
+}
+
+/// Where the machine continues after running an instruction.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
 pub enum Step {
+  /// Continue with the following instruction.
   Next,
+  /// Continue after a label of the script, as compiled jumps and calls do.
   Jmp(Label),
+  /// Continue at an absolute address of the script, as returns and calls of function values do.
   JmpAddr(usize),
+  /// Stop running.
+  Halt,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Value {
+  fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    match self {
+      Self::Null => serializer.serialize_unit(),
+      Self::Bool(value) => serializer.serialize_bool(*value),
+      Self::Number(value) => serializer.serialize_f64(*value),
+      Self::String(value) | Self::Builder(value) => serializer.serialize_str(&value.borrow()),
+      Self::Bytes(value) => serializer.serialize_bytes(&value.borrow()),
+      Self::Array(values) => serializer.collect_seq(values.borrow().iter()),
+      // Addresses and callables only have meaning inside the running VM
+      Self::Addr(_) | Self::BuiltIn(_) | Self::Function { .. } | Self::Closure { .. } => Err(
+        serde::ser::Error::custom(format!("cannot serialize {:?}", self)),
+      ),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{Value, ValueKey};
+  use crate::vm::error::VmError;
+  use std::{collections::HashMap, convert::TryFrom};
+
+  #[test]
+  fn test_from_int() {
+    assert_eq!(Value::from(3i64), Value::Number(3.0));
+    assert_eq!(Value::from(-3i32), Value::Number(-3.0));
+    assert_eq!(Value::from(3u32), Value::Number(3.0));
+  }
+
+  #[test]
+  fn test_try_from() {
+    assert_eq!(f64::try_from(Value::from(1.5)).unwrap(), 1.5);
+    assert_eq!(String::try_from(Value::from("text")).unwrap(), "text");
+    assert!(bool::try_from(Value::from(true)).unwrap());
+
+    assert!(matches!(
+      String::try_from(Value::from(1.0)),
+      Err(VmError::TypeError {
+        expected: "string",
+        actual: Value::Number(_),
+      })
+    ));
+    assert!(bool::try_from(Value::from(1.0)).is_err());
+    assert!(f64::try_from(Value::Null).is_err());
+  }
+
+  #[test]
+  #[allow(clippy::mutable_key_type)]
+  fn test_hash() {
+    let mut map = HashMap::new();
+
+    map.insert(ValueKey(1.0.into()), Value::from("one"));
+    map.insert(ValueKey("two".into()), Value::from(2.0));
+    map.insert(ValueKey(0.0.into()), Value::from("zero"));
+    map.insert(ValueKey(f64::NAN.into()), Value::Null);
+
+    assert_eq!(map[&ValueKey(1.0.into())], "one".into());
+    assert_eq!(map[&ValueKey("two".into())], 2.0.into());
+    assert_eq!(map[&ValueKey((-0.0).into())], "zero".into());
+    assert_eq!(map[&ValueKey((-f64::NAN).into())], Value::Null);
+    assert!(!map.contains_key(&ValueKey("one".into())));
+
+    let array = |value: f64| Value::from(vec![Value::from(value)]);
+
+    map.insert(ValueKey(array(f64::NAN)), Value::from(true));
+
+    assert_eq!(map[&ValueKey(array(f64::NAN))], true.into());
+  }
+
+  #[test]
+  fn test_as_int() {
+    assert_eq!(Value::from(3.0).as_u64().unwrap(), 3);
+    assert_eq!(Value::from(-0.0).as_u64().unwrap(), 0);
+    assert_eq!(Value::from(-3.0).as_i64().unwrap(), -3);
+    assert_eq!(Value::from(i64::MIN as f64).as_i64().unwrap(), i64::MIN);
+
+    for value in [-1.0, 1.5, f64::NAN, f64::INFINITY, 2f64.powi(64)] {
+      assert!(Value::from(value).as_u64().is_err());
+    }
+
+    for value in [0.5, f64::NAN, f64::NEG_INFINITY, 2f64.powi(63)] {
+      assert!(Value::from(value).as_i64().is_err());
+    }
+
+    assert!(Value::from("1").as_u64().is_err());
+  }
+
+  #[test]
+  fn test_bytes() {
+    let bytes = Value::from(&[0x00, 0xab, 0x10][..]);
+
+    assert_eq!(bytes.to_string(), "00ab10");
+    assert_eq!(bytes, bytes.deep_copy());
+    assert_ne!(bytes, Value::from(&[0x00][..]));
+    assert_ne!(bytes, "00ab10".into());
+  }
+
+  #[cfg(feature = "serde")]
+  #[test]
+  fn test_serialize() {
+    let values = vec![
+      Value::Null,
+      true.into(),
+      1.5.into(),
+      "value".into(),
+      vec![1.0.into()].into(),
+    ];
+
+    assert_eq!(
+      serde_json::to_string(&values).unwrap(),
+      r#"[null,true,1.5,"value",[1.0]]"#
+    );
+    assert!(serde_json::to_string(&Value::Addr(1)).is_err());
+  }
 }